@@ -10,6 +10,7 @@
           include = [
             "nxy-common"
             "nxy-cli"
+            "nxy-client"
             "nxy-agent"
             "nxy-server"
             "Cargo.toml"
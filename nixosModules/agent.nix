@@ -12,6 +12,24 @@ in
       example = "ws://localhost:8080";
       type = lib.types.str;
     };
+
+    labels = lib.mkOption {
+      description = "labels reported to the nxy server, labels set through the API take precedence";
+      default = { };
+      example = { env = "prod"; role = "web"; };
+      type = lib.types.attrsOf lib.types.str;
+    };
+
+    shareStore = {
+      enable = lib.mkEnableOption "serving the local nix store to other agents of the same site";
+
+      url = lib.mkOption {
+        description = "url under which peers can reach the shared nix store";
+        default = "http://${config.networking.hostName}:${toString config.services.nix-serve.port}";
+        defaultText = lib.literalExpression ''"http://''${config.networking.hostName}:''${toString config.services.nix-serve.port}"'';
+        type = lib.types.str;
+      };
+    };
   };
 
   config = lib.mkIf cfg.enable {
@@ -19,12 +37,19 @@ in
       self.overlays.default
     ];
 
+    services.nix-serve = lib.mkIf cfg.shareStore.enable {
+      enable = true;
+      openFirewall = true;
+    };
+
+    environment.etc."nxy/labels.json".text = builtins.toJSON cfg.labels;
 
     systemd.services.nxy-agent = {
       enable = true;
       wantedBy = [ "multi-user.target" ];
       after = [ "nix-deamon.service" ];
-      path = [ config.nix.package ];
+      # iproute2 for the ip addresses of the facts
+      path = [ config.nix.package pkgs.iproute2 ];
 
       # don't stop the service if the unit disappers
       unitConfig.X-StopOnRemoval = false;
@@ -34,7 +59,8 @@ in
         KillMode = "process";
         Restart = "always";
         RestartSec = 5;
-        ExecStart = "${pkgs.nxy-agent}/bin/nxy-agent /var/lib/nxy ${cfg.server}";
+        ExecStart = "${pkgs.nxy-agent}/bin/nxy-agent /var/lib/nxy ${cfg.server}"
+          + lib.optionalString cfg.shareStore.enable " ${cfg.shareStore.url}";
       };
     };
   };
@@ -1,6 +1,7 @@
 use std::{path::PathBuf, process::Command};
 
-use eyre::{bail, ensure, Result};
+use eyre::{bail, ensure, eyre, Result};
+use nxy_common::types::ActivationMode;
 
 pub(crate) type StorePath = PathBuf;
 
@@ -10,16 +11,112 @@ pub(crate) type StorePath = PathBuf;
 ///
 /// * `profile` - Profile name
 /// * `store_path` - Store path to activate
-pub(crate) fn activate(profile: String, store_path: StorePath) -> Result<()> {
+/// * `mode` - How to activate `store_path`, see `switch-to-configuration`
+pub(crate) fn activate(profile: String, store_path: StorePath, mode: ActivationMode) -> Result<()> {
     if !is_nixos_system(&store_path)? {
         bail!("only nixos profiles are currently supported");
     }
 
-    set_profile(&profile, &store_path)?;
-    let ac = get_activation_script(&store_path);
+    if mode.sets_profile() {
+        set_profile(&profile, &store_path)?;
+    }
+    switch_to_configuration(&store_path, mode)
+}
+
+/// Activate the previous generation of `profile`
+///
+/// Only modes which set the profile switch it back to the previous generation, it's restored if
+/// the activation fails.
+///
+/// # Returns
+///
+/// Store path of the activated generation
+pub(crate) fn rollback(profile: String, mode: ActivationMode) -> Result<StorePath> {
+    let profile_dir = profile_dir(&profile);
+    let (current, previous) = generations(&profile)?;
+    let store_path =
+        std::fs::canonicalize(profile_dir.with_file_name(link_name(&profile, previous)))?;
+
+    if !mode.sets_profile() {
+        switch_to_configuration(&store_path, mode)?;
+        return Ok(store_path);
+    }
+
+    switch_generation(&profile, previous)?;
+    if let Err(err) = switch_to_configuration(&store_path, mode) {
+        if let Err(restore_err) = switch_generation(&profile, current) {
+            tracing::error!(?restore_err, current, "failed to restore profile");
+        }
+        return Err(err);
+    }
+    Ok(store_path)
+}
+
+/// Numbers of the current and the previous generation of `profile`
+///
+/// The previous generation is the newest one older than the current, as `nix-env --rollback`
+/// picks it.
+fn generations(profile: &str) -> Result<(u64, u64)> {
+    let profile_dir = profile_dir(profile);
+    let current = std::fs::read_link(&profile_dir)?;
+    let current = current
+        .file_name()
+        .and_then(|name| generation(profile, &name.to_string_lossy()))
+        .ok_or_else(|| eyre!("{} isn't a profile generation", current.display()))?;
+
+    let mut previous = None;
+    for entry in std::fs::read_dir(profile_dir.parent().unwrap_or(&profile_dir))? {
+        let Some(number) = generation(profile, &entry?.file_name().to_string_lossy()) else {
+            continue;
+        };
+        if number < current && previous.map_or(true, |previous| number > previous) {
+            previous = Some(number);
+        }
+    }
+    let previous =
+        previous.ok_or_else(|| eyre!("no generation before {current} to roll back to"))?;
+    Ok((current, previous))
+}
+
+/// Number of the generation link `name` of `profile`, eg. 42 for `system-42-link`
+fn generation(profile: &str, name: &str) -> Option<u64> {
+    name.strip_prefix(profile)?
+        .strip_prefix('-')?
+        .strip_suffix("-link")?
+        .parse()
+        .ok()
+}
+
+fn link_name(profile: &str, generation: u64) -> String {
+    format!("{profile}-{generation}-link")
+}
+
+/// Point `profile` to the existing generation `generation`
+fn switch_generation(profile: &str, generation: u64) -> Result<()> {
+    let output = crate::cancel::output(
+        Command::new("nix-env")
+            .arg("--profile")
+            .arg(profile_dir(profile))
+            .arg("--switch-generation")
+            .arg(generation.to_string()),
+    )?;
+    ensure!(
+        output.status.success(),
+        "switching profile to generation {generation} failed"
+    );
+    Ok(())
+}
+
+fn switch_to_configuration(store_path: &StorePath, mode: ActivationMode) -> Result<()> {
+    let ac = get_activation_script(store_path);
 
     //TODO: how to protect this from service restart?
-    let output = Command::new(ac).arg("switch").output()?;
+    let output = crate::cancel::output(Command::new(ac).arg(mode.as_str()))?;
+
+    // switch-to-configuration also fails if units failed to start, report them either way
+    crate::units::record_activation(&String::from_utf8_lossy(&output.stderr));
+    crate::units::notify();
+
     if !output.status.success() {
         tracing::error!(stderr = %String::from_utf8_lossy(&output.stderr), stdout = %String::from_utf8_lossy(&output.stdout), "failed to switch profile");
         bail!("failed to switch profile")
@@ -29,21 +126,23 @@ pub(crate) fn activate(profile: String, store_path: StorePath) -> Result<()> {
 
 /// Set `profile` to `store_path`
 fn set_profile(profile: &str, store_path: &StorePath) -> Result<()> {
-    let system_profiles_dir = PathBuf::from("/nix/var/nix/profiles");
-    let profile_dir = system_profiles_dir.join(profile);
-
     let mut cmd = Command::new("nix-env");
     cmd.arg("--profile");
-    cmd.arg(profile_dir);
+    cmd.arg(profile_dir(profile));
     cmd.arg("--set");
     cmd.arg(store_path);
 
-    let output = cmd.output()?;
+    let output = crate::cancel::output(&mut cmd)?;
     ensure!(output.status.success(), "updating profile failed");
 
     Ok(())
 }
 
+fn profile_dir(profile: &str) -> PathBuf {
+    let system_profiles_dir = PathBuf::from("/nix/var/nix/profiles");
+    system_profiles_dir.join(profile)
+}
+
 /// Returns `true` if `store_path` points to a NixOS system configuration
 fn is_nixos_system(store_path: &StorePath) -> std::io::Result<bool> {
     store_path.join("nixos-version").try_exists()
@@ -53,3 +152,18 @@ fn is_nixos_system(store_path: &StorePath) -> std::io::Result<bool> {
 fn get_activation_script(store_path: &StorePath) -> PathBuf {
     store_path.join("bin/switch-to-configuration")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generation_links() {
+        assert_eq!(generation("system", "system-42-link"), Some(42));
+        assert_eq!(generation("system", &link_name("system", 7)), Some(7));
+        assert_eq!(generation("system", "system"), None);
+        assert_eq!(generation("system", "system-profiles"), None);
+        assert_eq!(generation("system", "system-x-link"), None);
+        assert_eq!(generation("system", "other-1-link"), None);
+    }
+}
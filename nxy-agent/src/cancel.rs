@@ -0,0 +1,149 @@
+//! Cancellation of running requests via `$/cancelRequest`.
+//!
+//! Every request is processed on its own thread, which remembers the cancellation token of the
+//! request it is working on. Ids may repeat, eg. after a reconnect, so a token is only ever
+//! shared by a single request. Long running commands are started with [`output`], which kills them as soon as
+//! the request got cancelled.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::Read,
+    process::{Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use eyre::Result;
+use nxy_common::RequestId;
+use once_cell::sync::Lazy;
+
+/// Set once a request got cancelled
+type Token = Arc<AtomicBool>;
+
+/// Tokens of the running requests, the latest one for repeated ids
+static RUNNING: Lazy<Mutex<HashMap<RequestId, Token>>> = Lazy::new(Default::default);
+
+thread_local! {
+    static CURRENT: RefCell<Option<Token>> = const { RefCell::new(None) };
+}
+
+/// Returned by [`output`] if the command was killed because its request got cancelled
+#[derive(Debug, thiserror::Error)]
+#[error("request cancelled")]
+pub(crate) struct Cancelled;
+
+/// Run `f` as the handler of request `id` on the current thread
+pub(crate) fn run<T>(id: RequestId, f: impl FnOnce() -> T) -> T {
+    let token = Token::default();
+    RUNNING.lock().unwrap().insert(id.clone(), Arc::clone(&token));
+    CURRENT.with(|current| *current.borrow_mut() = Some(Arc::clone(&token)));
+
+    let result = f();
+
+    CURRENT.with(|current| *current.borrow_mut() = None);
+    let mut running = RUNNING.lock().unwrap();
+    // a later request with the same id keeps its token
+    if running.get(&id).map_or(false, |other| Arc::ptr_eq(other, &token)) {
+        running.remove(&id);
+    }
+    result
+}
+
+/// Mark the latest request `id` as cancelled, unknown or already finished requests are ignored
+pub(crate) fn cancel(id: &RequestId) {
+    if let Some(token) = RUNNING.lock().unwrap().get(id) {
+        tracing::info!(%id, "cancelling request");
+        token.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Returns `true` if the request handled by the current thread got cancelled
+pub(crate) fn is_cancelled() -> bool {
+    CURRENT.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .map_or(false, |token| token.load(Ordering::Relaxed))
+    })
+}
+
+/// Like [`Command::output`], but kills the child process once the current request gets cancelled.
+pub(crate) fn output(cmd: &mut Command) -> Result<Output> {
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+    let stdout = read_to_end(child.stdout.take());
+    let stderr = read_to_end(child.stderr.take());
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if is_cancelled() {
+            child.kill()?;
+            child.wait()?;
+            return Err(Cancelled.into());
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn read_to_end(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    /// A request reusing the id of a still running one, eg. after a reconnect
+    #[test]
+    fn repeated_ids() {
+        let id = RequestId::String("repeated-ids".to_string());
+        let (started_tx, started_rx) = mpsc::channel();
+        let (finish_tx, finish_rx) = mpsc::channel::<()>();
+
+        let first = thread::spawn({
+            let id = id.clone();
+            move || {
+                run(id, || {
+                    started_tx.send(()).unwrap();
+                    finish_rx.recv().unwrap();
+                    is_cancelled()
+                })
+            }
+        });
+        started_rx.recv().unwrap();
+
+        run(id.clone(), || {
+            cancel(&id);
+            assert!(is_cancelled());
+
+            finish_tx.send(()).unwrap();
+            assert!(!first.join().unwrap(), "only the latest request is cancelled");
+            assert!(
+                RUNNING.lock().unwrap().contains_key(&id),
+                "the first request removed the token of the second"
+            );
+        });
+        assert!(!RUNNING.lock().unwrap().contains_key(&id));
+    }
+}
@@ -0,0 +1,149 @@
+//! Collect the system inventory reported by `$/facts`.
+
+use std::{fs, io, net::IpAddr, path::Path, process::Command};
+
+use eyre::{eyre, Context, Result};
+use nxy_common::{
+    labels::{self, Labels},
+    types::{DiskUsage, Facts},
+};
+use serde::Deserialize;
+
+/// Collect the facts, probes of optional tools leave their field empty if they fail
+pub(crate) fn collect() -> Result<Facts> {
+    Ok(Facts {
+        hostname: read_trimmed("/proc/sys/kernel/hostname")?,
+        machine_id: read_trimmed("/etc/machine-id")?,
+        nixos_version: nixos_version()?,
+        kernel_version: read_trimmed("/proc/sys/kernel/osrelease")?,
+        architecture: std::env::consts::ARCH.to_string(),
+        cpu_count: std::thread::available_parallelism()?.get(),
+        memory_total: memory_total()?,
+        nix_disk: disk_usage("/nix")?,
+        uptime: uptime()?,
+        ip_addresses: best_effort("ip_addresses", ip_addresses()),
+        nix_version: best_effort(
+            "nix_version",
+            command_output(Command::new("nix").arg("--version")),
+        ),
+        labels: labels()?,
+    })
+}
+
+/// Labels set by `services.nxy-agent.labels`
+const LABELS_PATH: &str = "/etc/nxy/labels.json";
+
+fn best_effort<T: Default>(fact: &str, result: Result<T>) -> T {
+    result.unwrap_or_else(|err| {
+        tracing::warn!(fact, ?err, "failed to collect fact");
+        T::default()
+    })
+}
+
+fn read_trimmed(path: impl AsRef<Path>) -> Result<String> {
+    let path = path.as_ref();
+    let content =
+        fs::read_to_string(path).wrap_err_with(|| format!("failed to read {}", path.display()))?;
+    Ok(content.trim().to_string())
+}
+
+fn nixos_version() -> Result<Option<String>> {
+    match fs::read_to_string("/run/current-system/nixos-version") {
+        Ok(version) => Ok(Some(version.trim().to_string())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Returns `MemTotal` of `/proc/meminfo` in bytes
+fn memory_total() -> Result<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo")?;
+    let kib = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|value| value.trim().strip_suffix("kB"))
+        .ok_or_else(|| eyre!("MemTotal missing in /proc/meminfo"))?
+        .trim()
+        .parse::<u64>()?;
+    Ok(kib * 1024)
+}
+
+fn disk_usage(path: &str) -> Result<DiskUsage> {
+    let output = command_output(Command::new("df").args(["--output=size,avail", "-B1", path]))?;
+    // skip the header line
+    let mut values = output
+        .lines()
+        .nth(1)
+        .ok_or_else(|| eyre!("unexpected df output: {output}"))?
+        .split_whitespace()
+        .map(str::parse::<u64>);
+    match (values.next(), values.next()) {
+        (Some(total), Some(available)) => Ok(DiskUsage {
+            total: total?,
+            available: available?,
+        }),
+        _ => Err(eyre!("unexpected df output: {output}")),
+    }
+}
+
+fn uptime() -> Result<u64> {
+    let uptime = read_trimmed("/proc/uptime")?;
+    let seconds = uptime
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| eyre!("unexpected /proc/uptime content: {uptime}"))?
+        .parse::<f64>()?;
+    Ok(seconds as u64)
+}
+
+#[derive(Deserialize)]
+struct Interface {
+    #[serde(default)]
+    addr_info: Vec<AddrInfo>,
+}
+
+#[derive(Deserialize)]
+struct AddrInfo {
+    local: IpAddr,
+}
+
+fn ip_addresses() -> Result<Vec<IpAddr>> {
+    let output = command_output(Command::new("ip").args(["-json", "address", "show"]))?;
+    let interfaces: Vec<Interface> = serde_json::from_str(&output)?;
+    Ok(interfaces
+        .into_iter()
+        .flat_map(|interface| interface.addr_info)
+        .map(|addr| addr.local)
+        .filter(|addr| !addr.is_loopback())
+        .collect())
+}
+
+/// Labels of the NixOS configuration, empty if none are set
+fn labels() -> Result<Labels> {
+    let content = match fs::read_to_string(LABELS_PATH) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Labels::new()),
+        Err(err) => return Err(err).wrap_err_with(|| format!("failed to read {LABELS_PATH}")),
+    };
+    let labels: Labels = serde_json::from_str(&content)
+        .wrap_err_with(|| format!("failed to parse {LABELS_PATH}"))?;
+    for (key, value) in &labels {
+        labels::validate_key(key)?;
+        labels::validate_value(key, value)?;
+    }
+    Ok(labels)
+}
+
+fn command_output(cmd: &mut Command) -> Result<String> {
+    let output = cmd
+        .output()
+        .wrap_err_with(|| format!("failed to run {:?}", cmd.get_program()))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "{:?} failed: {}",
+            cmd.get_program(),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
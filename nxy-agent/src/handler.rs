@@ -2,30 +2,73 @@ use std::{io, path::PathBuf, process::Command};
 
 use eyre::{bail, Result};
 use nxy_common::{
-    types::{ActivateParams, DownloadParams, Status, System},
-    ErrorCode, Request, Response,
+    methods,
+    types::{
+        ActivateParams, ActivationMode, Capabilities, DownloadParams, Facts, InitializeParams,
+        InitializeResult, RebootParams, RollbackParams, Status, System, Units, PROTOCOL_VERSION,
+    },
+    Dispatcher, NotificationMethod,
 };
-use serde_json::json;
 use tracing::instrument;
 
-use crate::STATE;
+use crate::{activate::StorePath, STATE};
 
-#[instrument(skip(request))]
-pub(super) fn ping(request: &Request) -> Result<Response> {
+#[instrument]
+pub(super) fn initialize(params: InitializeParams) -> Result<InitializeResult> {
+    if params.protocol_version != PROTOCOL_VERSION {
+        tracing::warn!(
+            server = params.protocol_version,
+            agent = PROTOCOL_VERSION,
+            "server speaks a different protocol version"
+        );
+    }
+
+    let mut supported: Vec<String> = crate::dispatch(Dispatcher::default())
+        .methods()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    // notifications aren't dispatched
+    supported.extend(
+        [
+            methods::UnitsChanged::NAME,
+            methods::SystemChanged::NAME,
+            methods::CancelRequest::NAME,
+        ]
+        .map(String::from),
+    );
+
+    Ok(InitializeResult {
+        protocol_version: PROTOCOL_VERSION,
+        capabilities: Capabilities {
+            methods: supported,
+            activation_modes: vec![
+                ActivationMode::Switch,
+                ActivationMode::Boot,
+                ActivationMode::Test,
+                ActivationMode::DryActivate,
+            ],
+            multi_source_download: true,
+        },
+    })
+}
+
+#[instrument]
+pub(super) fn ping() -> Result<String> {
     tracing::trace!("PONG");
-    Ok(Response::new_ok(request.id, "pong"))
+    Ok("pong".to_string())
 }
 
-fn current_system() -> io::Result<PathBuf> {
+pub(crate) fn current_system() -> io::Result<PathBuf> {
     std::fs::read_link("/run/current-system")
 }
 
-fn booted_system() -> io::Result<PathBuf> {
+pub(crate) fn booted_system() -> io::Result<PathBuf> {
     std::fs::read_link("/run/booted-system")
 }
 
-#[instrument(skip(request))]
-pub(super) fn status(request: &Request) -> Result<Response> {
+#[instrument]
+pub(super) fn status() -> Result<Status> {
     let system = System {
         current: current_system()?,
         booted: booted_system()?,
@@ -34,51 +77,61 @@ pub(super) fn status(request: &Request) -> Result<Response> {
         let state = STATE.lock().unwrap();
         state.id
     };
+    let reboot_required = crate::reboot::reboot_required(&system.booted, &system.current);
     let status = Status {
         id,
         version: env!("CARGO_PKG_VERSION").to_string(),
+        reboot_required,
         system,
+        // optional third argument, url under which peers can substitute from our store
+        substituter: std::env::args().nth(3),
     };
 
-    Ok(Response::new_ok(request.id, json!(status)))
+    Ok(status)
 }
 
-pub(super) fn download(request: &Request) -> Result<Response> {
-    let params: DownloadParams = serde_json::from_value(request.params.clone())?;
-
-    let mut cmd = Command::new("nix");
-    cmd.args([
-        "copy",
-        "--substitute-on-destination",
-        "--verbose",
-        "--no-check-sigs",
-        "--from",
-        &params.from,
-    ]);
-    cmd.arg(params.store_path);
-
-    let output = cmd.output()?;
-    if !output.status.success() {
-        println!("{}", String::from_utf8_lossy(&output.stderr));
-        bail!("nix copy failed");
-    }
+#[instrument]
+pub(super) fn facts() -> Result<Facts> {
+    crate::facts::collect()
+}
 
-    Ok(Response::new_ok(request.id, ()))
+#[instrument]
+pub(super) fn units() -> Result<Units> {
+    crate::units::collect()
 }
 
-pub(super) fn activate(request: &Request) -> Result<Response> {
-    let params: ActivateParams = serde_json::from_value(request.params.clone())?;
+pub(super) fn download(params: DownloadParams) -> Result<()> {
+    for from in params.from.as_slice() {
+        let mut cmd = Command::new("nix");
+        cmd.args([
+            "copy",
+            "--substitute-on-destination",
+            "--verbose",
+            "--no-check-sigs",
+            "--from",
+            from,
+        ]);
+        cmd.arg(&params.store_path);
+
+        let output = crate::cancel::output(&mut cmd)?;
+        if output.status.success() {
+            tracing::info!(from, "copied store path");
+            return Ok(());
+        }
+        tracing::warn!(from, stderr = %String::from_utf8_lossy(&output.stderr), "nix copy failed, trying next substituter");
+    }
+
+    bail!("nix copy failed for all substituters");
+}
 
-    crate::activate::activate("system".to_string(), params.store_path)?;
+pub(super) fn activate(params: ActivateParams) -> Result<()> {
+    crate::activate::activate("system".to_string(), params.store_path, params.mode)
+}
 
-    Ok(Response::new_ok(request.id, ()))
+pub(super) fn rollback(params: RollbackParams) -> Result<StorePath> {
+    crate::activate::rollback("system".to_string(), params.mode)
 }
 
-#[instrument(skip(request))]
-pub(super) fn unknown(request: &Request) -> Result<Response> {
-    Ok(Response::new_err(
-        request.id,
-        ErrorCode::MethodNotFound as i32,
-        "pong".to_string(),
-    ))
+pub(super) fn reboot(params: RebootParams) -> Result<()> {
+    crate::reboot::reboot(params)
 }
@@ -1,16 +1,32 @@
-use std::{env::args, net::TcpStream, path::PathBuf, sync::Mutex, time::Duration};
+use std::{
+    env::args,
+    io::ErrorKind,
+    net::TcpStream,
+    path::PathBuf,
+    sync::{mpsc, Mutex},
+    time::Duration,
+};
 
-use eyre::Result;
+use eyre::{bail, Result};
 use once_cell::sync::Lazy;
 use state::State;
 use tracing::instrument;
 use tungstenite::{client::connect, stream::MaybeTlsStream, Message, WebSocket};
 
-use nxy_common::{JsonRPC, Request, Response};
+use nxy_common::{
+    methods, Dispatcher, ErrorCode, JsonRPC, Message as RpcMessage, MethodError, Notification,
+    Request, Response,
+};
 
 mod activate;
+mod cancel;
+mod facts;
 mod handler;
+mod notify;
+mod reboot;
 mod state;
+mod units;
+mod watch;
 
 pub static STATE: Lazy<Mutex<State>> = Lazy::new(|| {
     let path = args()
@@ -28,25 +44,82 @@ fn main() -> Result<()> {
         .nth(2)
         .expect("second argument must be server address eg. ws://localhost:8080");
 
+    units::watch();
+    watch::current_system();
     run(&server_url)
 }
 
 fn run(server_url: &str) -> Result<()> {
     loop {
         let (mut socket, _) = connect_with_backoff(server_url)?;
-        loop {
-            let msg = socket.read_message()?;
-            let rpc: JsonRPC = msg.into_text()?.parse()?;
-            match rpc {
-                JsonRPC::Request(request) => {
-                    let res: JsonRPC = handle_request(request)?.into();
-                    socket.write_message(Message::Text(res.to_string()))?;
-                }
-                JsonRPC::Response(res) => {
-                    tracing::warn!(?res, "received response, this should happen")
-                }
-                JsonRPC::Notification(notification) => tracing::info!(?notification),
+        if let Err(err) = serve(&mut socket) {
+            tracing::warn!(?err, "connection to server lost, reconnecting");
+        }
+    }
+}
+
+/// Process messages from the server until the connection is closed.
+///
+/// Requests are handled on their own threads, so long running requests don't block the
+/// connection and can be cancelled.
+fn serve(socket: &mut WebSocket<MaybeTlsStream<TcpStream>>) -> Result<()> {
+    // poll for finished requests while waiting for new messages
+    match socket.get_mut() {
+        MaybeTlsStream::Plain(stream) => {
+            stream.set_read_timeout(Some(Duration::from_millis(100)))?
+        }
+        #[allow(unreachable_patterns)]
+        _ => bail!("unsupported stream type"),
+    }
+    let (replies_tx, replies) = mpsc::channel::<RpcMessage>();
+    notify::connect(replies_tx.clone());
+
+    loop {
+        while let Ok(reply) = replies.try_recv() {
+            socket.write_message(Message::Text(reply.to_string()))?;
+        }
+
+        let text = match socket.read_message() {
+            Ok(Message::Text(text)) => text,
+            Ok(_) => continue,
+            Err(tungstenite::Error::Io(err))
+                if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                continue
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let msgs = RpcMessage::parse(&text).filter_map(|msg| match msg {
+            Ok(JsonRPC::Request(request)) => Some(Ok(request)),
+            Ok(JsonRPC::Response(res)) => {
+                tracing::warn!(?res, "received response, this should happen");
+                None
             }
+            Ok(JsonRPC::Notification(notification)) => {
+                handle_notification(notification);
+                None
+            }
+            Err(res) => {
+                tracing::warn!(error = ?res.error, "received invalid message");
+                Some(Err(res))
+            }
+        });
+
+        if let Some(msgs) = msgs {
+            let replies_tx = replies_tx.clone();
+            std::thread::spawn(move || {
+                let replies = msgs.filter_map(|msg| match msg {
+                    Ok(request) => Some(JsonRPC::from(cancel::run(request.id.clone(), || {
+                        handle_request(request)
+                    }))),
+                    Err(res) => Some(JsonRPC::from(res)),
+                });
+                if let Some(replies) = replies {
+                    // the connection might be gone already, nobody is waiting for a reply then
+                    let _ = replies_tx.send(replies);
+                }
+            });
         }
     }
 }
@@ -81,20 +154,55 @@ fn backoff(duration: Duration) -> Duration {
     duration * 2
 }
 
-#[instrument(skip_all, err, fields(id = %request.id, method = request.method))]
-fn handle_request(request: Request) -> Result<Response> {
+#[instrument(skip_all, fields(id = %request.id, method = request.method))]
+fn handle_request(request: Request) -> Response {
     tracing::debug!("start processing request");
-    let response = match request.method.as_str() {
-        "$/ping" => handler::ping(&request),
-        "$/status" => handler::status(&request),
-        "$/download" => handler::download(&request),
-        "$/activate" => handler::activate(&request),
-        _ => handler::unknown(&request),
-    };
+    let response = dispatch(Dispatcher::new(request)).finish();
+    if let Some(ref error) = response.error {
+        tracing::error!(?error, "request failed");
+    }
     tracing::debug!("done processing request");
     response
 }
 
+/// Register the handlers of all methods, the advertised capabilities are derived from them
+pub(crate) fn dispatch(dispatcher: Dispatcher) -> Dispatcher {
+    dispatcher
+        .on::<methods::Initialize>(|params| internal(handler::initialize(params)))
+        .on::<methods::Ping>(|()| internal(handler::ping()))
+        .on::<methods::Status>(|()| internal(handler::status()))
+        .on::<methods::Units>(|()| internal(handler::units()))
+        .on::<methods::Facts>(|()| internal(handler::facts()))
+        .on::<methods::Download>(|params| internal(handler::download(params)))
+        .on::<methods::Activate>(|params| internal(handler::activate(params)))
+        .on::<methods::Rollback>(|params| internal(handler::rollback(params)))
+        .on::<methods::Reboot>(|params| internal(handler::reboot(params)))
+}
+
+#[instrument(skip_all, fields(method = notification.method))]
+fn handle_notification(notification: Notification) {
+    if let Some(params) = notification.extract::<methods::CancelRequest>() {
+        match params {
+            Ok(params) => cancel::cancel(&params.id),
+            Err(err) => tracing::warn!(?err, "invalid cancel request"),
+        }
+    } else {
+        tracing::info!(?notification, "ignoring unknown notification");
+    }
+}
+
+/// Report handler errors as [`ErrorCode::InternalError`], or [`ErrorCode::RequestCancelled`]
+/// if the request got cancelled
+fn internal<T, E>(result: Result<T>) -> std::result::Result<T, MethodError<E>> {
+    result.map_err(|err| {
+        if err.is::<cancel::Cancelled>() {
+            MethodError::new(ErrorCode::RequestCancelled, err)
+        } else {
+            MethodError::internal(format!("{err:#}"))
+        }
+    })
+}
+
 fn install_tracing() {
     use tracing_error::ErrorLayer;
     use tracing_subscriber::prelude::*;
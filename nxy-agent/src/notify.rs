@@ -0,0 +1,27 @@
+//! Send notifications to the server from any thread.
+
+use std::sync::{mpsc, Mutex};
+
+use nxy_common::{JsonRPC, Message as RpcMessage, Notification, NotificationMethod};
+use once_cell::sync::Lazy;
+
+/// Outgoing messages of the current connection, `None` while disconnected
+static OUTBOX: Lazy<Mutex<Option<mpsc::Sender<RpcMessage>>>> = Lazy::new(Default::default);
+
+/// Route notifications to the connection that owns `outbox`
+pub(crate) fn connect(outbox: mpsc::Sender<RpcMessage>) {
+    *OUTBOX.lock().unwrap() = Some(outbox);
+}
+
+/// Send notification `N`, dropped if the agent is not connected
+pub(crate) fn send<N: NotificationMethod>(params: N::Params) {
+    let notification = JsonRPC::from(Notification::typed::<N>(params));
+    let mut outbox = OUTBOX.lock().unwrap();
+    let sent = outbox
+        .as_ref()
+        .map_or(false, |tx| tx.send(notification.into()).is_ok());
+    if !sent {
+        tracing::debug!(method = N::NAME, "not connected, dropping notification");
+        *outbox = None;
+    }
+}
@@ -0,0 +1,140 @@
+//! Detect whether a reboot is required and schedule reboots.
+
+use std::{fs, path::Path, process::Command, thread, time::Duration};
+
+use eyre::{bail, ensure, eyre, Result};
+use nxy_common::types::{RebootParams, RebootReason, RebootWindow};
+
+/// Returns the components that differ between the `booted` and `current` system
+pub(crate) fn reboot_required(booted: &Path, current: &Path) -> Vec<RebootReason> {
+    [
+        (RebootReason::Kernel, "kernel"),
+        (RebootReason::Initrd, "initrd"),
+        (RebootReason::KernelModules, "kernel-modules"),
+        (RebootReason::Systemd, "systemd"),
+    ]
+    .into_iter()
+    .filter(|(_, component)| {
+        // components missing in both systems are considered equal
+        fs::canonicalize(booted.join(component)).ok()
+            != fs::canonicalize(current.join(component)).ok()
+    })
+    .map(|(reason, _)| reason)
+    .collect()
+}
+
+/// Reboot now or schedule a reboot via `shutdown`
+pub(crate) fn reboot(params: RebootParams) -> Result<()> {
+    let when = match (params.delay, params.window) {
+        (Some(_), Some(_)) => bail!("delay and window are mutually exclusive"),
+        (Some(delay), None) => format!("+{delay}"),
+        (None, Some(window)) if !in_window(&window)? => window.start,
+        (None, _) => {
+            // give the response a chance to reach the server before we go down
+            thread::spawn(|| {
+                thread::sleep(Duration::from_secs(1));
+                if let Err(err) = shutdown("now") {
+                    tracing::error!(?err, "failed to reboot");
+                }
+            });
+            tracing::info!("rebooting");
+            return Ok(());
+        }
+    };
+
+    shutdown(&when)?;
+    tracing::info!(when, "reboot scheduled");
+    Ok(())
+}
+
+fn shutdown(when: &str) -> Result<()> {
+    let output = Command::new("shutdown").args(["--reboot", when]).output()?;
+    ensure!(
+        output.status.success(),
+        "scheduling reboot failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(())
+}
+
+/// Returns `true` if the local time is within `window`
+fn in_window(window: &RebootWindow) -> Result<bool> {
+    let start = parse_time(&window.start)?;
+    let end = parse_time(&window.end)?;
+
+    let output = Command::new("date").arg("+%H:%M").output()?;
+    ensure!(output.status.success(), "failed to query local time");
+    let now = parse_time(String::from_utf8_lossy(&output.stdout).trim())?;
+
+    Ok(if start <= end {
+        start <= now && now < end
+    } else {
+        // window spans midnight
+        now >= start || now < end
+    })
+}
+
+/// Parse `HH:MM` into minutes since midnight
+fn parse_time(time: &str) -> Result<u32> {
+    let invalid = || eyre!("invalid time {time:?}, expected HH:MM");
+    let (hours, minutes) = time.split_once(':').ok_or_else(invalid)?;
+    let hours: u32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    ensure!(hours < 24 && minutes < 60, invalid());
+    Ok(hours * 60 + minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    /// Kernel and initrd of the booted and current system, and the reasons to reboot
+    #[test]
+    fn reboot_reasons() {
+        let cases = [
+            (("kernel-1", "initrd-1"), ("kernel-1", "initrd-1"), vec![]),
+            (
+                ("kernel-1", "initrd-1"),
+                ("kernel-2", "initrd-1"),
+                vec![RebootReason::Kernel],
+            ),
+            (
+                ("kernel-1", "initrd-1"),
+                ("kernel-1", "initrd-2"),
+                vec![RebootReason::Initrd],
+            ),
+            (
+                ("kernel-1", "initrd-1"),
+                ("kernel-2", "initrd-2"),
+                vec![RebootReason::Kernel, RebootReason::Initrd],
+            ),
+        ];
+
+        let store = std::env::temp_dir().join(format!("nxy-reboot-{}", std::process::id()));
+        fs::create_dir_all(&store).unwrap();
+        for path in ["kernel-1", "kernel-2", "initrd-1", "initrd-2"] {
+            fs::write(store.join(path), path).unwrap();
+        }
+
+        for (i, (booted, current, expected)) in cases.into_iter().enumerate() {
+            let system = |name: &str, (kernel, initrd): (&str, &str)| {
+                let system = store.join(format!("{name}-{i}"));
+                fs::create_dir(&system).unwrap();
+                symlink(store.join(kernel), system.join("kernel")).unwrap();
+                symlink(store.join(initrd), system.join("initrd")).unwrap();
+                system
+            };
+            let booted_system = system("booted", booted);
+            let current_system = system("current", current);
+            assert_eq!(
+                reboot_required(&booted_system, &current_system),
+                expected,
+                "booted {booted:?}, current {current:?}"
+            );
+        }
+
+        fs::remove_dir_all(&store).unwrap();
+    }
+}
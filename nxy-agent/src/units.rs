@@ -0,0 +1,186 @@
+//! Report systemd unit states, so failing services after an activation are noticed.
+
+use std::{collections::BTreeSet, process::Command, sync::Mutex, thread, time::Duration};
+
+use eyre::{ensure, Result};
+use nxy_common::{
+    methods,
+    types::{UnitStatus, Units},
+};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+/// How often failed units are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Lines of `switch-to-configuration` output that list the units it touched
+const ACTIVATION_PREFIXES: [&str; 3] = [
+    "starting the following units: ",
+    "restarting the following units: ",
+    "reloading the following units: ",
+];
+
+/// Units started, restarted or reloaded by the last activation
+static ACTIVATED: Lazy<Mutex<Vec<String>>> = Lazy::new(Default::default);
+
+/// Remember the units touched by an activation from the output of `switch-to-configuration`
+pub(crate) fn record_activation(output: &str) {
+    *ACTIVATED.lock().unwrap() = activated_units(output);
+}
+
+/// Units listed as started, restarted or reloaded in the output of `switch-to-configuration`
+fn activated_units(output: &str) -> Vec<String> {
+    let units: BTreeSet<String> = output
+        .lines()
+        .filter_map(|line| {
+            ACTIVATION_PREFIXES
+                .iter()
+                .find_map(|p| line.strip_prefix(p))
+        })
+        .flat_map(|units| units.split(", "))
+        .map(|unit| unit.trim().to_string())
+        .filter(|unit| !unit.is_empty())
+        .collect();
+    units.into_iter().collect()
+}
+
+pub(crate) fn collect() -> Result<Units> {
+    let activated = ACTIVATED.lock().unwrap().clone();
+    Ok(Units {
+        failed: failed_units()?,
+        activated: unit_states(&activated)?,
+    })
+}
+
+/// Send the current unit states to the server
+pub(crate) fn notify() {
+    match collect() {
+        Ok(units) => crate::notify::send::<methods::UnitsChanged>(units),
+        Err(err) => tracing::warn!(?err, "failed to collect unit states"),
+    }
+}
+
+/// Notify the server whenever the set of failed units changes
+pub(crate) fn watch() {
+    thread::spawn(|| {
+        let mut last_failed = Vec::new();
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            match failed_units() {
+                Ok(failed) if failed != last_failed => {
+                    tracing::info!(?failed, "failed units changed");
+                    last_failed = failed;
+                    notify();
+                }
+                Ok(_) => {}
+                Err(err) => tracing::warn!(?err, "failed to query failed units"),
+            }
+        }
+    });
+}
+
+#[derive(Deserialize)]
+struct ListedUnit {
+    unit: String,
+    active: String,
+    sub: String,
+}
+
+fn failed_units() -> Result<Vec<UnitStatus>> {
+    let output = Command::new("systemctl")
+        .args(["list-units", "--failed", "--output=json"])
+        .output()?;
+    ensure!(output.status.success(), "systemctl list-units failed");
+
+    let units: Vec<ListedUnit> = serde_json::from_slice(&output.stdout)?;
+    Ok(units
+        .into_iter()
+        .map(|unit| UnitStatus {
+            name: unit.unit,
+            active_state: unit.active,
+            sub_state: unit.sub,
+        })
+        .collect())
+}
+
+fn unit_states(units: &[String]) -> Result<Vec<UnitStatus>> {
+    if units.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let output = Command::new("systemctl")
+        .args(["show", "--property=Id,ActiveState,SubState"])
+        .args(units)
+        .output()?;
+    ensure!(output.status.success(), "systemctl show failed");
+
+    // one block of `key=value` lines per unit, separated by empty lines
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout
+        .split("\n\n")
+        .filter_map(|block| {
+            let mut status = UnitStatus {
+                name: String::new(),
+                active_state: String::new(),
+                sub_state: String::new(),
+            };
+            for (key, value) in block.lines().filter_map(|line| line.split_once('=')) {
+                match key {
+                    "Id" => status.name = value.to_string(),
+                    "ActiveState" => status.active_state = value.to_string(),
+                    "SubState" => status.sub_state = value.to_string(),
+                    _ => {}
+                }
+            }
+            (!status.name.is_empty()).then_some(status)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_of_activation_output() {
+        let cases: [(&str, &[&str]); 4] = [
+            ("", &[]),
+            (
+                "activating the configuration...\n\
+                setting up /etc...\n\
+                reloading user units for root...\n\
+                setting up tmpfiles\n",
+                &[],
+            ),
+            (
+                "stopping the following units: nginx.service\n\
+                activating the configuration...\n\
+                setting up /etc...\n\
+                reloading user units for root...\n\
+                setting up tmpfiles\n\
+                reloading the following units: dbus.service\n\
+                restarting the following units: sshd.service, systemd-journald.service\n\
+                starting the following units: nginx.service, nxy-agent.service\n\
+                the following new units were started: run-credentials-nginx.service.mount\n",
+                &[
+                    "dbus.service",
+                    "nginx.service",
+                    "nxy-agent.service",
+                    "sshd.service",
+                    "systemd-journald.service",
+                ],
+            ),
+            (
+                // dry-activate only tells what it would do
+                "would stop the following units: nginx.service\n\
+                would activate the configuration...\n\
+                would restart the following units: sshd.service\n\
+                would start the following units: nginx.service\n",
+                &[],
+            ),
+        ];
+        for (output, expected) in cases {
+            assert_eq!(activated_units(output), expected, "{output}");
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Notice changes of `/run/current-system` made outside of nxy, eg. `nixos-rebuild switch`.
+
+use std::{
+    ffi::OsStr,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use eyre::Result;
+use inotify::{Inotify, WatchMask};
+use nxy_common::{methods, types::System};
+
+use crate::handler;
+
+/// Delay before watching again after the first failure, doubled on every further failure
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+/// Upper bound of the retry delay
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// Send `$/systemChanged` whenever `/run/current-system` points to a new store path
+///
+/// Failures are retried with a fresh inotify instance, changes made meanwhile are still noticed.
+pub(crate) fn current_system() {
+    thread::spawn(|| {
+        let mut last = None;
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            let started = Instant::now();
+            if let Err(err) = watch(&mut last) {
+                // it worked for a while, this is a new failure
+                if started.elapsed() > MAX_BACKOFF {
+                    backoff = MIN_BACKOFF;
+                }
+                tracing::error!(?err, retry_in = ?backoff, "watching /run/current-system failed");
+            }
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    });
+}
+
+/// Watch until an error occurs, `last` is the last system seen
+fn watch(last: &mut Option<PathBuf>) -> Result<()> {
+    let mut inotify = Inotify::init()?;
+    // the symlink is replaced by renaming a new one over it, so watch the directory
+    inotify
+        .watches()
+        .add("/run", WatchMask::CREATE | WatchMask::MOVED_TO)?;
+
+    // catch up with changes made while not watching
+    if last.is_some() {
+        changed(last)?;
+    } else {
+        *last = Some(handler::current_system()?);
+    }
+
+    let mut buffer = [0; 4096];
+    loop {
+        let mut events = inotify.read_events_blocking(&mut buffer)?;
+        if events.any(|event| event.name == Some(OsStr::new("current-system"))) {
+            changed(last)?;
+        }
+    }
+}
+
+/// Send `$/systemChanged` if the current system isn't `last` anymore
+fn changed(last: &mut Option<PathBuf>) -> Result<()> {
+    let current = handler::current_system()?;
+    if last.as_ref() == Some(&current) {
+        return Ok(());
+    }
+    tracing::info!(?current, "current system changed");
+    *last = Some(current.clone());
+
+    let system = System {
+        current,
+        booted: handler::booted_system()?,
+    };
+    crate::notify::send::<methods::SystemChanged>(system);
+    Ok(())
+}
@@ -1,21 +1,44 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand, ValueEnum};
-use uuid::Uuid;
+use nxy_client::{
+    api,
+    labels::{Selector, SelectorError},
+    types,
+};
+use serde::{Deserialize, Serialize};
 
 #[derive(Parser)]
 pub(crate) struct Args {
-    #[arg(value_enum, short, long, global = true, default_value_t = Format::Table)]
-    pub(crate) format: Format,
+    /// Output format, defaults to the format of the context or `table`
+    #[arg(value_enum, short, long, global = true)]
+    pub(crate) format: Option<Format>,
+
+    /// Context to use instead of the current one, see `nxy context`
+    #[arg(long, global = true)]
+    pub(crate) context: Option<String>,
 
     #[command(subcommand)]
     pub(crate) action: Action,
 }
 
-#[derive(ValueEnum, Clone, Copy)]
+#[derive(ValueEnum, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub(crate) enum Format {
     Table,
     Json,
 }
 
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Table => f.write_str("table"),
+            Format::Json => f.write_str("json"),
+        }
+    }
+}
+
 #[derive(Subcommand)]
 pub(crate) enum Action {
     /// interact with nxy flakes
@@ -33,23 +56,278 @@ pub(crate) enum Action {
         #[command(subcommand)]
         action: ConfigsAction,
     },
+    /// interact with substituter sites
+    Sites {
+        #[command(subcommand)]
+        action: SitesAction,
+    },
+    /// switch between nxy servers
+    Context {
+        #[command(subcommand)]
+        action: ContextAction,
+    },
+    /// show desired and actual system of every agent
+    Status {
+        /// Only agents whose labels match, eg. `env=prod,role in (web,api)`
+        #[arg(short, long)]
+        selector: Option<Selector>,
+    },
+    /// deploy a configuration, all agents matching a selector or a single agent, optionally at an older revision
+    Deploy {
+        /// Configuration name or id, or a label selector, eg. `env=prod,role in (web,api)`
+        #[arg(required_unless_present = "agent")]
+        target: Option<DeployTarget>,
+        /// Deploy a single agent, by id or name, instead of a target
+        #[arg(long, conflicts_with = "target")]
+        agent: Option<String>,
+        /// Git revision to pin to, keeps existing pins if omitted
+        #[arg(long)]
+        revision: Option<String>,
+        #[arg(value_enum, long, default_value = "switch")]
+        mode: ActivationMode,
+        /// Follow the deployment until all agents are done
+        #[arg(long)]
+        wait: bool,
+    },
+    /// show who changed what, newest first
+    Audit {
+        /// Only entries of this user, `token:<fingerprint>` or `server`
+        #[arg(long)]
+        actor: Option<String>,
+        /// Only actions starting with this, eg. `reconcile/` or `POST /api/v1/agent`
+        #[arg(long)]
+        action: Option<String>,
+        /// Only entries about this agent, by id or name
+        #[arg(long, conflicts_with = "target")]
+        agent: Option<String>,
+        /// Only entries about this object, eg. `flake/1`
+        #[arg(long)]
+        target: Option<String>,
+        #[arg(value_enum, long)]
+        outcome: Option<Outcome>,
+        /// Only entries since this time, eg. `2026-10-19T12:00:00Z`
+        #[arg(long)]
+        since: Option<DateTime<Utc>>,
+        /// Maximum number of entries
+        #[arg(long, default_value = "100")]
+        limit: i64,
+    },
+    /// follow server events as they happen
+    Watch {
+        /// Only events about this agent, by id or name
+        #[arg(long)]
+        agent: Option<String>,
+        /// Only events about this flake
+        #[arg(long)]
+        flake: Option<i64>,
+    },
+}
+
+/// A single agent, or a label selector matching any number of agents
+#[derive(Clone)]
+pub(crate) enum Target {
+    /// Agent id, name or unique prefix of the id
+    Agent(String),
+    Selector(Selector),
+}
+
+impl FromStr for Target {
+    type Err = SelectorError;
+
+    /// Anything containing selector syntax is a selector, a plain word an agent
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if is_selector(s) {
+            s.parse().map(Target::Selector)
+        } else {
+            Ok(Target::Agent(s.to_string()))
+        }
+    }
+}
+
+/// A configuration, a label selector matching any number of agents, or a single agent
+#[derive(Clone)]
+pub(crate) enum DeployTarget {
+    /// Configuration name or id
+    Config(String),
+    Selector(Selector),
+    /// Agent id, name or unique prefix of the id, only set through `--agent`
+    Agent(String),
+}
+
+impl FromStr for DeployTarget {
+    type Err = SelectorError;
+
+    /// Anything containing selector syntax is a selector, a plain word a configuration
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if is_selector(s) {
+            s.parse().map(DeployTarget::Selector)
+        } else {
+            Ok(DeployTarget::Config(s.to_string()))
+        }
+    }
+}
+
+fn is_selector(s: &str) -> bool {
+    s.contains(|c: char| matches!(c, '=' | '!' | '(' | ',') || c.is_whitespace())
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub(crate) enum Outcome {
+    Succeeded,
+    Failed,
+}
+
+impl Outcome {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Succeeded => "succeeded",
+            Outcome::Failed => "failed",
+        }
+    }
+}
+
+/// See `switch-to-configuration`
+#[derive(ValueEnum, Clone, Copy)]
+pub(crate) enum ActivationMode {
+    Switch,
+    Boot,
+    Test,
+    DryActivate,
+}
+
+impl From<ActivationMode> for types::ActivationMode {
+    fn from(mode: ActivationMode) -> Self {
+        match mode {
+            ActivationMode::Switch => types::ActivationMode::Switch,
+            ActivationMode::Boot => types::ActivationMode::Boot,
+            ActivationMode::Test => types::ActivationMode::Test,
+            ActivationMode::DryActivate => types::ActivationMode::DryActivate,
+        }
+    }
+}
+
+/// How far the server may go to bring an agent to its desired system
+#[derive(ValueEnum, Clone, Copy)]
+pub(crate) enum DeployPolicy {
+    /// Never deploy automatically
+    Manual,
+    /// Copy the desired system, but don't activate it
+    Download,
+    /// Copy and switch to the desired system
+    Switch,
+    /// Copy the desired system and make it the boot default
+    Boot,
+}
+
+impl From<DeployPolicy> for api::DeployPolicy {
+    fn from(policy: DeployPolicy) -> Self {
+        match policy {
+            DeployPolicy::Manual => api::DeployPolicy::Manual,
+            DeployPolicy::Download => api::DeployPolicy::Download,
+            DeployPolicy::Switch => api::DeployPolicy::Switch,
+            DeployPolicy::Boot => api::DeployPolicy::Boot,
+        }
+    }
 }
 
 #[derive(Subcommand)]
 pub(crate) enum AgentAction {
     /// List all agents
-    List,
+    List {
+        /// Only list agents which need a reboot
+        #[arg(long)]
+        reboot_required: bool,
+        /// Only list agents whose labels match, eg. `env=prod,role in (web,api)`
+        #[arg(short, long)]
+        selector: Option<Selector>,
+    },
+    /// Show details and facts of an agent
+    Show {
+        /// Agent id, name or unique prefix of the id
+        agent: String,
+    },
+    /// Show failed units and the units touched by the last activation
+    Units {
+        /// Agent id, name or unique prefix of the id
+        agent: String,
+    },
     SetConfig {
-        agent_id: Uuid,
+        /// Agent id, name or unique prefix of the id
+        agent: String,
         config_id: i64,
     },
+    /// Copy a store path to an agent, or all agents matching a label selector
     Download {
-        agent_id: Uuid,
+        /// Agent id or name, or a label selector, eg. `env=prod,role in (web,api)`
+        target: Target,
         store_path: String,
     },
+    /// Activate a downloaded system on an agent, or all agents matching a label selector
     Activate {
-        agent_id: Uuid,
+        /// Agent id or name, or a label selector, eg. `env=prod,role in (web,api)`
+        target: Target,
         store_path: String,
+        #[arg(value_enum, short, long, default_value = "switch")]
+        mode: ActivationMode,
+    },
+    /// Switch agent back to the previous system generation
+    Rollback {
+        /// Agent id or name, or a label selector, eg. `env=prod,role in (web,api)`
+        target: Target,
+        #[arg(value_enum, short, long, default_value = "switch")]
+        mode: ActivationMode,
+    },
+    /// Reboot agent now, after a delay or in the next maintenance window
+    Reboot {
+        /// Agent id or name, or a label selector, eg. `env=prod,role in (web,api)`
+        target: Target,
+        /// Minutes to wait before rebooting
+        #[arg(short, long, conflicts_with = "window")]
+        delay: Option<u32>,
+        /// Daily maintenance window in the agent's local time, eg. `02:00-04:00`
+        #[arg(short, long)]
+        window: Option<String>,
+    },
+    /// Pin agent to a flake revision, overriding the pin of its configuration
+    Pin {
+        /// Agent id, name or unique prefix of the id
+        agent: String,
+        /// Git revision, or a unique prefix of it
+        revision: String,
+    },
+    /// Let agent follow its configuration again
+    Unpin {
+        /// Agent id, name or unique prefix of the id
+        agent: String,
+    },
+    /// Set how far the server may go to bring the agent to its desired system
+    SetPolicy {
+        /// Agent id, name or unique prefix of the id
+        agent: String,
+        #[arg(value_enum)]
+        policy: DeployPolicy,
+    },
+    /// Assign agent to a substituter site, omit site to unassign
+    SetSite {
+        /// Agent id, name or unique prefix of the id
+        agent: String,
+        site: Option<String>,
+    },
+    /// Rename agent, omit name to name it after its hostname again
+    Rename {
+        /// Agent id, name or unique prefix of the id
+        agent: String,
+        name: Option<String>,
+    },
+    /// Set labels of an agent, overriding labels reported by the agent
+    Label {
+        /// Agent id, name or unique prefix of the id
+        agent: String,
+        /// Labels to set, eg. `env=prod`
+        labels: Vec<String>,
+        /// Keys of labels to remove
+        #[arg(short, long)]
+        remove: Vec<String>,
     },
 }
 
@@ -61,10 +339,107 @@ pub(crate) enum FlakeAction {
         /// flake uri to add to nxy
         flake_url: String,
     },
+    /// Show a flake and its configurations
+    Show {
+        /// Flake id
+        id: i64,
+    },
+    /// Change the url of a flake, eg. after its repository moved
+    Rename {
+        /// Flake id
+        id: i64,
+        /// New flake uri
+        flake_url: String,
+    },
+    /// Remove a flake, refused while agents use one of its configurations
+    Remove {
+        /// Flake id
+        id: i64,
+    },
+    /// Fetch and evaluate the latest revision
+    Update {
+        /// Flake id, updates all flakes if omitted
+        id: Option<i64>,
+    },
+    /// List all revisions of a flake with their evaluation status
+    Revisions {
+        /// Flake id
+        id: i64,
+    },
 }
 
 #[derive(Subcommand)]
 pub(crate) enum ConfigsAction {
     /// List all configs
     List,
+    /// Pin all agents of a config to a flake revision
+    Pin {
+        /// Configuration name or id
+        config: String,
+        /// Git revision, or a unique prefix of it
+        revision: String,
+    },
+    /// Let agents of a config follow the latest revision again
+    Unpin {
+        /// Configuration name or id
+        config: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub(crate) enum ContextAction {
+    /// List all contexts, the current one is marked with `*`
+    List,
+    /// Use a context for all further commands
+    Use { name: String },
+    /// Add a context, or replace an existing one
+    Set {
+        name: String,
+        /// Server url, eg. `https://nxy.example.com`
+        #[arg(long)]
+        server: String,
+        /// Bearer token sent with every request
+        #[arg(long)]
+        token: Option<String>,
+        /// Output format used without `--format`
+        #[arg(value_enum, long)]
+        default_format: Option<Format>,
+    },
+    /// Remove a context
+    Remove { name: String },
+}
+
+#[derive(Subcommand)]
+pub(crate) enum SitesAction {
+    /// List all sites
+    List,
+}
+
+#[test]
+fn parse_flakes_rename() {
+    let args = Args::try_parse_from(["nxy", "flakes", "rename", "1", "github:org/repo"]).unwrap();
+    match args.action {
+        Action::Flakes {
+            action: FlakeAction::Rename { id, flake_url },
+        } => {
+            assert_eq!(id, 1);
+            assert_eq!(flake_url, "github:org/repo");
+        }
+        _ => panic!("expected flakes rename"),
+    }
+}
+
+#[test]
+fn parse_deploy_agent() {
+    let args = Args::try_parse_from(["nxy", "deploy", "--agent", "web-1"]).unwrap();
+    match args.action {
+        Action::Deploy {
+            target: None,
+            agent: Some(agent),
+            ..
+        } => assert_eq!(agent, "web-1"),
+        _ => panic!("expected deploy --agent"),
+    }
+    assert!(Args::try_parse_from(["nxy", "deploy", "web", "--agent", "web-1"]).is_err());
+    assert!(Args::try_parse_from(["nxy", "deploy"]).is_err());
 }
@@ -0,0 +1,168 @@
+//! Named contexts, each one a server with its token and output format, kubectl-style.
+//!
+//! Stored as JSON in `$NXY_CONFIG`, or `$XDG_CONFIG_HOME/nxy/config.json` by default.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions, Permissions},
+    io::{ErrorKind, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::PathBuf,
+};
+
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
+use nxy_client::{blocking::Client, ClientBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::args::Format;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Config {
+    /// Context used without `--context`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) current_context: Option<String>,
+    #[serde(default)]
+    pub(crate) contexts: BTreeMap<String, Context>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Context {
+    /// Server url without the `/api/v1` prefix
+    pub(crate) server: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) token: Option<String>,
+    /// Output format used without `--format`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) format: Option<Format>,
+}
+
+impl Config {
+    pub(crate) fn path() -> Result<PathBuf> {
+        if let Some(path) = std::env::var_os("NXY_CONFIG") {
+            return Ok(path.into());
+        }
+        let dir = dirs::config_dir().ok_or_else(|| eyre!("no config directory, set NXY_CONFIG"))?;
+        Ok(dir.join("nxy").join("config.json"))
+    }
+
+    /// Load the config, a missing file is an empty config
+    pub(crate) fn load() -> Result<Self> {
+        let path = Self::path()?;
+        match fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data)
+                .wrap_err_with(|| format!("invalid config {}", path.display())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).wrap_err_with(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    /// Write the config, only readable by the user as it contains tokens
+    pub(crate) fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)
+            .wrap_err_with(|| format!("failed to write {}", path.display()))?;
+        // the mode only applies to new files
+        file.set_permissions(Permissions::from_mode(0o600))?;
+        writeln!(file, "{}", serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Result<&Context> {
+        self.contexts
+            .get(name)
+            .ok_or_else(|| eyre!("no context named {name}"))
+    }
+
+    /// The context to use, `None` to fall back to `NXY_SERVER` and `NXY_TOKEN`
+    ///
+    /// `name`, given by `--context`, takes precedence over `NXY_SERVER`, which takes precedence
+    /// over the current context.
+    pub(crate) fn select(&self, name: Option<&str>) -> Result<Option<&Context>> {
+        self.select_with(name, std::env::var_os("NXY_SERVER").is_some())
+    }
+
+    fn select_with(&self, name: Option<&str>, server_from_env: bool) -> Result<Option<&Context>> {
+        match (name, &self.current_context) {
+            (Some(name), _) => self.get(name).map(Some),
+            (None, _) if server_from_env => Ok(None),
+            (None, Some(current)) => self.get(current).map(Some),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+impl Context {
+    pub(crate) fn client(&self) -> Result<Client> {
+        let mut builder = ClientBuilder::new(&self.server);
+        if let Some(ref token) = self.token {
+            builder = builder.token(token);
+        }
+        Ok(builder.build_blocking()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        serde_json::from_str(
+            r#"{
+                "current_context": "staging",
+                "contexts": {
+                    "staging": { "server": "https://staging.example.com" },
+                    "production": { "server": "https://example.com", "token": "secret", "format": "json" }
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn select_context() {
+        let config = config();
+        let production = config
+            .select_with(Some("production"), false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(production.server, "https://example.com");
+        assert!(matches!(production.format, Some(Format::Json)));
+        assert!(config.select_with(Some("dev"), false).is_err());
+    }
+
+    /// `--context` over `NXY_SERVER` over the current context
+    #[test]
+    fn select_precedence() {
+        let config = config();
+        let server = |name: Option<&str>, server_from_env: bool| {
+            config
+                .select_with(name, server_from_env)
+                .unwrap()
+                .map(|context| context.server.as_str())
+        };
+        assert_eq!(
+            server(Some("production"), true),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            server(Some("production"), false),
+            Some("https://example.com")
+        );
+        assert_eq!(server(None, true), None);
+        assert_eq!(server(None, false), Some("https://staging.example.com"));
+
+        let empty = Config::default();
+        assert!(empty.select_with(None, false).unwrap().is_none());
+    }
+}
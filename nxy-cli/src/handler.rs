@@ -1,3 +1,9 @@
 pub(crate) mod agent;
+pub(crate) mod audit;
 pub(crate) mod configuration;
+pub(crate) mod context;
+pub(crate) mod deploy;
 pub(crate) mod flake;
+pub(crate) mod site;
+pub(crate) mod status;
+pub(crate) mod watch;
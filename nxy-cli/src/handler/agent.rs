@@ -1,65 +1,361 @@
 use crate::{
-    args::{AgentAction, Format},
-    utils::{format_output, format_url},
+    args::{AgentAction, Format, Target},
+    utils::{display_option, format_output},
 };
-use color_eyre::Result;
-use serde::{Deserialize, Serialize};
+use color_eyre::{
+    eyre::{bail, eyre},
+    Result,
+};
+use nxy_client::{
+    api::{self, AgentFilter, DeployPolicy},
+    blocking::Client,
+    labels::{Labels, Selector},
+    types::RebootWindow,
+};
+use serde::Serialize;
 use tabled::Tabled;
 use uuid::Uuid;
 
-pub(crate) fn handle(action: AgentAction, format: Format) -> Result<()> {
+pub(crate) fn handle(client: &Client, action: AgentAction, format: Format) -> Result<()> {
     match action {
-        AgentAction::List => list_agents(format),
-        AgentAction::SetConfig {
-            agent_id,
-            config_id,
-        } => set_configuration(agent_id, config_id),
-        AgentAction::Download {
-            agent_id,
-            store_path,
-        } => download_store_path(agent_id, store_path),
+        AgentAction::List {
+            reboot_required,
+            selector,
+        } => {
+            let filter = AgentFilter {
+                reboot_required,
+                selector,
+            };
+            list_agents(client, &filter, format)
+        }
+        AgentAction::Show { agent } => show_agent(client, &agent, format),
+        AgentAction::Units { agent } => show_units(client, &agent, format),
+        AgentAction::SetConfig { agent, config_id } => {
+            Ok(client.set_configuration(&agent, config_id)?)
+        }
+        AgentAction::Download { target, store_path } => match target {
+            Target::Agent(agent) => Ok(client.download(&agent, &store_path)?),
+            Target::Selector(selector) => {
+                let results = client.download_selected(&selector, &store_path)?;
+                print_results(&selector, results, format)
+            }
+        },
         AgentAction::Activate {
-            agent_id,
+            target,
             store_path,
-        } => activate(agent_id, store_path),
+            mode,
+        } => match target {
+            Target::Agent(agent) => Ok(client.activate(&agent, &store_path, mode.into())?),
+            Target::Selector(selector) => {
+                let results = client.activate_selected(&selector, &store_path, mode.into())?;
+                print_results(&selector, results, format)
+            }
+        },
+        AgentAction::Rollback { target, mode } => match target {
+            Target::Agent(agent) => {
+                let rolled_back = client.rollback(&agent, mode.into())?;
+                println!("{}", rolled_back.store_path.display());
+                Ok(())
+            }
+            Target::Selector(selector) => {
+                let results = client.rollback_selected(&selector, mode.into())?;
+                print_results(&selector, results, format)
+            }
+        },
+        AgentAction::Reboot {
+            target,
+            delay,
+            window,
+        } => reboot(client, target, delay, window, format),
+        AgentAction::Pin { agent, revision } => Ok(client.pin_agent(&agent, Some(&revision))?),
+        AgentAction::Unpin { agent } => Ok(client.pin_agent(&agent, None)?),
+        AgentAction::SetPolicy { agent, policy } => {
+            Ok(client.set_deploy_policy(&agent, policy.into())?)
+        }
+        AgentAction::SetSite { agent, site } => Ok(client.set_site(&agent, site.as_deref())?),
+        AgentAction::Rename { agent, name } => Ok(client.set_name(&agent, name.as_deref())?),
+        AgentAction::Label {
+            agent,
+            labels,
+            remove,
+        } => {
+            let labels = labels
+                .iter()
+                .map(|label| {
+                    label
+                        .split_once('=')
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .ok_or_else(|| eyre!("invalid label {label:?}, expected key=value"))
+                })
+                .collect::<Result<_>>()?;
+            Ok(client.set_labels(&agent, labels, remove)?)
+        }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Tabled)]
+#[derive(Serialize, Tabled)]
 struct Agent {
     #[tabled(rename = "Id")]
     id: uuid::Uuid,
 
-    #[tabled(rename = "Current System")]
-    current_system: String,
+    #[tabled(rename = "Name", display_with = "display_option")]
+    name: Option<String>,
+
+    #[tabled(rename = "Current System", display_with = "display_option")]
+    current_system: Option<String>,
+
+    #[tabled(rename = "Site", display_with = "display_option")]
+    site: Option<String>,
+
+    #[tabled(rename = "Reboot Required", display_with = "display_reboot_required")]
+    reboot_required: Vec<String>,
+
+    #[tabled(rename = "Drifted")]
+    drifted: bool,
+
+    #[tabled(rename = "Deploy Policy")]
+    deploy_policy: DeployPolicy,
+
+    #[tabled(rename = "Labels", display_with = "display_labels")]
+    labels: Labels,
 }
 
-fn list_agents(format: Format) -> Result<()> {
-    let agents: Vec<Agent> = ureq::get(&format_url("/api/v1/agent"))
-        .call()?
-        .into_json()?;
+impl From<api::Agent> for Agent {
+    fn from(agent: api::Agent) -> Self {
+        Self {
+            id: agent.id,
+            name: agent.name,
+            current_system: agent.current_system,
+            site: agent.site,
+            reboot_required: agent.reboot_required,
+            drifted: agent.drifted,
+            deploy_policy: agent.deploy_policy,
+            labels: agent.labels,
+        }
+    }
+}
+
+fn display_reboot_required(reasons: &[String]) -> String {
+    reasons.join(", ")
+}
+
+fn display_labels(labels: &Labels) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn list_agents(client: &Client, filter: &AgentFilter, format: Format) -> Result<()> {
+    let agents: Vec<Agent> = client
+        .agents(filter)?
+        .into_iter()
+        .map(Agent::from)
+        .collect();
 
     println!("{}", format_output(agents, format));
     Ok(())
 }
 
-fn set_configuration(agent_id: Uuid, config_id: i64) -> Result<()> {
-    ureq::post(&format_url(&format!("/api/v1/agent/{agent_id}")))
-        .send_json(ureq::json!({ "config_id": config_id }))
-        .unwrap();
+#[derive(Serialize, Tabled)]
+struct Property {
+    #[tabled(rename = "Property")]
+    name: &'static str,
+
+    #[tabled(rename = "Value")]
+    value: String,
+}
+
+impl Property {
+    fn new(name: &'static str, value: impl ToString) -> Self {
+        Self {
+            name,
+            value: value.to_string(),
+        }
+    }
+}
+
+fn show_agent(client: &Client, agent: &str, format: Format) -> Result<()> {
+    let agent = client.agent(agent)?;
+
+    if let Format::Json = format {
+        println!("{}", serde_json::to_string(&agent)?);
+        return Ok(());
+    }
+
+    let mut properties = vec![
+        Property::new("Id", agent.id),
+        Property::new("Name", display_option(&agent.name)),
+        Property::new("Connected", agent.connected),
+        Property::new("Site", display_option(&agent.site)),
+        Property::new("Current System", display_option(&agent.current_system)),
+        Property::new(
+            "Reboot Required",
+            display_reboot_required(&agent.reboot_required),
+        ),
+        Property::new("Drifted", agent.drifted),
+        Property::new("Pinned Revision", display_option(&agent.pinned_revision)),
+        Property::new("Labels", display_labels(&agent.labels)),
+    ];
+    if let Some(facts) = agent.facts {
+        properties.extend([
+            Property::new("Hostname", facts.hostname),
+            Property::new("Machine Id", facts.machine_id),
+            Property::new("NixOS Version", display_option(&facts.nixos_version)),
+            Property::new("Nix Version", facts.nix_version),
+            Property::new("Kernel", facts.kernel_version),
+            Property::new("Architecture", facts.architecture),
+            Property::new("CPUs", facts.cpu_count),
+            Property::new("Memory", format_bytes(facts.memory_total)),
+            Property::new(
+                "/nix",
+                format!(
+                    "{} of {} available",
+                    format_bytes(facts.nix_disk.available),
+                    format_bytes(facts.nix_disk.total)
+                ),
+            ),
+            Property::new("Uptime", format_uptime(facts.uptime)),
+            Property::new(
+                "IP Addresses",
+                facts
+                    .ip_addresses
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            Property::new("Facts Updated", display_option(&agent.facts_updated_at)),
+        ]);
+    }
+
+    println!("{}", format_output(properties, format));
     Ok(())
 }
 
-fn download_store_path(agent_id: Uuid, store_path: String) -> Result<()> {
-    ureq::post(&format_url(&format!("/api/v1/agent/{agent_id}/download")))
-        .send_json(ureq::json!({ "store_path": store_path }))
-        .unwrap();
+/// Format `bytes` in GiB
+fn format_bytes(bytes: u64) -> String {
+    format!("{:.1} GiB", bytes as f64 / (1024 * 1024 * 1024) as f64)
+}
+
+/// Format `seconds` as days, hours and minutes
+fn format_uptime(seconds: u64) -> String {
+    let minutes = seconds / 60;
+    format!(
+        "{}d {}h {}m",
+        minutes / (60 * 24),
+        minutes / 60 % 24,
+        minutes % 60
+    )
+}
+
+#[derive(Serialize, Tabled)]
+struct Unit {
+    #[tabled(rename = "Unit")]
+    name: String,
+
+    #[tabled(rename = "Active")]
+    active_state: String,
+
+    #[tabled(rename = "Sub")]
+    sub_state: String,
+
+    /// Whether the unit is failed or was touched by the last activation
+    #[tabled(rename = "Reported As")]
+    reported_as: &'static str,
+}
+
+fn show_units(client: &Client, agent: &str, format: Format) -> Result<()> {
+    let units = client.agent_units(agent)?;
+
+    let failed = units.failed.into_iter().map(|unit| (unit, "failed"));
+    let activated = units.activated.into_iter().map(|unit| (unit, "activated"));
+    let units: Vec<Unit> = failed
+        .chain(activated)
+        .map(|(unit, reported_as)| Unit {
+            name: unit.name,
+            active_state: unit.active_state,
+            sub_state: unit.sub_state,
+            reported_as,
+        })
+        .collect();
+
+    println!("{}", format_output(units, format));
     Ok(())
 }
 
-fn activate(agent_id: Uuid, store_path: String) -> Result<()> {
-    ureq::post(&format_url(&format!("/api/v1/agent/{agent_id}/activate")))
-        .send_json(ureq::json!({ "store_path": store_path }))
-        .unwrap();
+fn reboot(
+    client: &Client,
+    target: Target,
+    delay: Option<u32>,
+    window: Option<String>,
+    format: Format,
+) -> Result<()> {
+    let window = window
+        .map(|window| {
+            window
+                .split_once('-')
+                .map(|(start, end)| RebootWindow {
+                    start: start.to_string(),
+                    end: end.to_string(),
+                })
+                .ok_or_else(|| eyre!("invalid window {window:?}, expected HH:MM-HH:MM"))
+        })
+        .transpose()?;
+
+    let reboot = api::Reboot { delay, window };
+    match target {
+        Target::Agent(agent) => Ok(client.reboot(&agent, reboot)?),
+        Target::Selector(selector) => {
+            let results = client.reboot_selected(&selector, reboot)?;
+            print_results(&selector, results, format)
+        }
+    }
+}
+
+#[derive(Serialize, Tabled)]
+pub(crate) struct AgentResult {
+    #[tabled(rename = "Agent")]
+    agent_id: Uuid,
+
+    #[tabled(rename = "Store Path", display_with = "display_option")]
+    store_path: Option<String>,
+
+    #[tabled(rename = "Error", display_with = "display_option")]
+    error: Option<String>,
+}
+
+impl From<api::AgentResult> for AgentResult {
+    fn from(result: api::AgentResult) -> Self {
+        Self {
+            agent_id: result.agent_id,
+            store_path: result.store_path,
+            error: result.error.map(|error| error.message),
+        }
+    }
+}
+
+/// Print the outcome of a request on every agent matching `selector`, fails if any agent failed
+fn print_results(
+    selector: &Selector,
+    results: Vec<api::AgentResult>,
+    format: Format,
+) -> Result<()> {
+    if results.is_empty() {
+        bail!("no agent matches {selector}");
+    }
+
+    let total = results.len();
+    let results: Vec<AgentResult> = results.into_iter().map(AgentResult::from).collect();
+    let failed = results
+        .iter()
+        .filter(|result| result.error.is_some())
+        .count();
+
+    println!("{}", format_output(results, format));
+    if failed > 0 {
+        bail!("failed on {failed} of {total} agents");
+    }
     Ok(())
 }
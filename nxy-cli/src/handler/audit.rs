@@ -0,0 +1,57 @@
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use nxy_client::{
+    api::{self, AuditFilter},
+    blocking::Client,
+};
+use serde::Serialize;
+use tabled::Tabled;
+
+use crate::{
+    args::Format,
+    utils::{display_option, format_output},
+};
+
+#[derive(Serialize, Tabled)]
+struct AuditEntry {
+    #[tabled(rename = "Time")]
+    created_at: DateTime<Utc>,
+    #[tabled(rename = "Actor")]
+    actor: String,
+    #[tabled(rename = "Action")]
+    action: String,
+    #[tabled(rename = "Target", display_with = "display_option")]
+    target: Option<String>,
+    #[tabled(rename = "Outcome")]
+    outcome: String,
+    #[tabled(rename = "Error", display_with = "display_option")]
+    error: Option<String>,
+}
+
+impl From<api::AuditEntry> for AuditEntry {
+    fn from(entry: api::AuditEntry) -> Self {
+        Self {
+            created_at: entry.created_at,
+            actor: entry.actor,
+            action: entry.action,
+            target: entry.target,
+            outcome: entry.outcome,
+            error: entry.error,
+        }
+    }
+}
+
+/// Show audit log entries, newest first
+pub(crate) fn handle(client: &Client, filter: AuditFilter, format: Format) -> Result<()> {
+    let entries = client.audit(&filter)?;
+
+    // keep parameters and source ip of the full entries
+    if let Format::Json = format {
+        println!("{}", serde_json::to_string(&entries)?);
+        return Ok(());
+    }
+
+    let entries: Vec<AuditEntry> = entries.into_iter().map(AuditEntry::from).collect();
+    println!("{}", format_output(entries, format));
+    Ok(())
+}
@@ -1,32 +1,81 @@
-use color_eyre::Result;
-use serde::{Deserialize, Serialize};
+use color_eyre::{eyre::eyre, Result};
+use nxy_client::{api, blocking::Client};
+use serde::Serialize;
 use tabled::Tabled;
 
 use crate::{
     args::{ConfigsAction, Format},
-    utils::{format_output, format_url},
+    utils::{display_option, format_output},
 };
 
-pub(crate) fn handle(action: ConfigsAction, format: Format) -> Result<()> {
+pub(crate) fn handle(client: &Client, action: ConfigsAction, format: Format) -> Result<()> {
     match action {
-        ConfigsAction::List => list_configs(format),
+        ConfigsAction::List => list_configs(client, format),
+        ConfigsAction::Pin { config, revision } => pin(client, &config, Some(&revision)),
+        ConfigsAction::Unpin { config } => pin(client, &config, None),
     }
 }
 
-#[derive(Deserialize, Serialize, Tabled)]
+#[derive(Serialize, Tabled)]
 struct Config {
     id: i64,
     #[tabled(rename = "flake url")]
     flake_url: String,
     name: String,
+    #[tabled(rename = "pinned revision", display_with = "display_option")]
+    pinned_revision: Option<String>,
 }
 
-fn list_configs(format: Format) -> Result<()> {
-    let configs: Vec<Config> = ureq::get(&format_url("/api/v1/configuration"))
-        .call()?
-        .into_json()?;
+impl From<api::Configuration> for Config {
+    fn from(config: api::Configuration) -> Self {
+        Self {
+            id: config.id,
+            flake_url: config.flake_url,
+            name: config.name,
+            pinned_revision: config.pinned_revision,
+        }
+    }
+}
+
+fn list_configs(client: &Client, format: Format) -> Result<()> {
+    let configs: Vec<Config> = client
+        .configurations()?
+        .into_iter()
+        .map(Config::from)
+        .collect();
 
     println!("{}", format_output(configs, format));
 
     Ok(())
 }
+
+/// Resolve a configuration name or id to its id
+fn resolve_config(client: &Client, selector: &str) -> Result<i64> {
+    if let Ok(id) = selector.parse() {
+        return Ok(id);
+    }
+
+    let matches: Vec<api::Configuration> = client
+        .configurations()?
+        .into_iter()
+        .filter(|config| config.name == selector)
+        .collect();
+    match &matches[..] {
+        [config] => Ok(config.id),
+        [] => Err(eyre!("no configuration named {selector}")),
+        _ => Err(eyre!(
+            "configuration name {selector} is ambiguous, use one of the ids: {}",
+            matches
+                .iter()
+                .map(|config| format!("{} ({})", config.id, config.flake_url))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+fn pin(client: &Client, config: &str, revision: Option<&str>) -> Result<()> {
+    let config_id = resolve_config(client, config)?;
+    client.pin_configuration(config_id, revision)?;
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use color_eyre::Result;
+use serde::Serialize;
+use tabled::Tabled;
+
+use crate::{
+    args::{ContextAction, Format},
+    config::{Config, Context},
+    utils::{display_option, format_output},
+};
+
+pub(crate) fn handle(mut config: Config, action: ContextAction, format: Format) -> Result<()> {
+    match action {
+        ContextAction::List => list_contexts(&config, format),
+        ContextAction::Use { name } => {
+            config.get(&name)?;
+            config.current_context = Some(name);
+            config.save()
+        }
+        ContextAction::Set {
+            name,
+            server,
+            token,
+            default_format,
+        } => {
+            let context = Context {
+                server,
+                token,
+                format: default_format,
+            };
+            config.contexts.insert(name.clone(), context);
+            // the first context is used right away
+            config.current_context.get_or_insert(name);
+            config.save()
+        }
+        ContextAction::Remove { name } => {
+            config.get(&name)?;
+            config.contexts.remove(&name);
+            if config.current_context.as_ref() == Some(&name) {
+                config.current_context = None;
+            }
+            config.save()
+        }
+    }
+}
+
+#[derive(Serialize, Tabled)]
+struct ContextRow {
+    #[tabled(rename = "Current", display_with = "display_current")]
+    current: bool,
+    #[tabled(rename = "Name")]
+    name: String,
+    #[tabled(rename = "Server")]
+    server: String,
+    #[tabled(rename = "Format", display_with = "display_option")]
+    format: Option<Format>,
+}
+
+fn display_current(current: &bool) -> String {
+    if *current { "*" } else { "" }.to_string()
+}
+
+fn list_contexts(config: &Config, format: Format) -> Result<()> {
+    let contexts: Vec<ContextRow> = config
+        .contexts
+        .iter()
+        .map(|(name, context)| ContextRow {
+            current: config.current_context.as_ref() == Some(name),
+            name: name.clone(),
+            server: context.server.clone(),
+            format: context.format,
+        })
+        .collect();
+
+    println!("{}", format_output(contexts, format));
+    Ok(())
+}
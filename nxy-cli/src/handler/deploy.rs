@@ -0,0 +1,134 @@
+use color_eyre::{eyre::bail, Result};
+use nxy_client::{
+    api::{self, Deploy, Event, EventFilter},
+    blocking::Client,
+};
+use serde::Serialize;
+use tabled::Tabled;
+use uuid::Uuid;
+
+use crate::{
+    args::{ActivationMode, DeployTarget, Format},
+    handler::{agent::AgentResult, watch::describe},
+    utils::{display_option, format_output},
+};
+
+/// Deploy `target` and, with `wait`, follow the deployment until all agents are done
+///
+/// Fails if any agent was skipped or, with `wait`, failed.
+pub(crate) fn handle(
+    client: &Client,
+    target: DeployTarget,
+    revision: Option<String>,
+    mode: ActivationMode,
+    wait: bool,
+    format: Format,
+) -> Result<()> {
+    let (config, selector, agent) = match target {
+        DeployTarget::Config(config) => (Some(config), None, None),
+        DeployTarget::Selector(selector) => (None, Some(selector), None),
+        DeployTarget::Agent(agent) => (None, None, Some(agent)),
+    };
+    let deploy = Deploy {
+        config,
+        selector,
+        agent,
+        revision,
+        mode: mode.into(),
+    };
+
+    // subscribe first, the deployment may finish before its id is known
+    let events = if wait {
+        Some(client.events(&EventFilter::default())?)
+    } else {
+        None
+    };
+
+    let started = client.deploy(&deploy)?;
+    if started.agents.is_empty() {
+        bail!("no agents to deploy to");
+    }
+    let total = started.agents.len();
+    let skipped = started
+        .agents
+        .iter()
+        .filter(|agent| agent.error.is_some())
+        .count();
+    let agents: Vec<AgentResult> = started.agents.into_iter().map(AgentResult::from).collect();
+    if let Format::Table = format {
+        println!("deployment {}", started.id);
+    }
+    println!("{}", format_output(agents, format));
+
+    let Some(events) = events else {
+        if skipped > 0 {
+            bail!("skipped {skipped} of {total} agents");
+        }
+        return Ok(());
+    };
+
+    for event in events {
+        let event = event?;
+        if event.deployment_id() != Some(started.id) {
+            continue;
+        }
+        match format {
+            Format::Table => println!("{}", describe(&event)),
+            Format::Json => println!("{}", serde_json::to_string(&event)?),
+        }
+        if let Event::DeploymentFinished { .. } = event {
+            break;
+        }
+    }
+
+    // the stream may have ended early, the outcome is only trusted from the server
+    let deployment = client.deployment(started.id)?;
+    if deployment.finished_at.is_none() {
+        bail!(
+            "event stream ended before deployment {} finished",
+            started.id
+        );
+    }
+    let failed = deployment
+        .agents
+        .iter()
+        .filter(|agent| agent.status != "succeeded")
+        .count();
+    let agents: Vec<DeploymentAgent> = deployment
+        .agents
+        .into_iter()
+        .map(DeploymentAgent::from)
+        .collect();
+    println!("{}", format_output(agents, format));
+
+    if failed > 0 {
+        bail!("failed on {failed} of {total} agents");
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Tabled)]
+struct DeploymentAgent {
+    #[tabled(rename = "Agent")]
+    agent_id: Uuid,
+
+    #[tabled(rename = "Status")]
+    status: String,
+
+    #[tabled(rename = "Store Path", display_with = "display_option")]
+    store_path: Option<String>,
+
+    #[tabled(rename = "Error", display_with = "display_option")]
+    error: Option<String>,
+}
+
+impl From<api::DeploymentAgent> for DeploymentAgent {
+    fn from(agent: api::DeploymentAgent) -> Self {
+        Self {
+            agent_id: agent.agent_id,
+            status: agent.status,
+            store_path: agent.store_path,
+            error: agent.error,
+        }
+    }
+}
@@ -1,57 +1,133 @@
-use std::fmt::Display;
-
 use color_eyre::Result;
-use serde::{Deserialize, Serialize};
+use nxy_client::{api, blocking::Client};
+use serde::Serialize;
 use tabled::Tabled;
 
 use crate::{
     args::{FlakeAction, Format},
-    utils::{format_output, format_url},
+    utils::format_output,
 };
 
-pub(crate) fn handle(action: FlakeAction, format: Format) -> Result<()> {
+pub(crate) fn handle(client: &Client, action: FlakeAction, format: Format) -> Result<()> {
     match action {
-        FlakeAction::List => list_flakes(format),
-        FlakeAction::Add { flake_url } => add_flake(flake_url),
+        FlakeAction::List => list_flakes(client, format),
+        FlakeAction::Add { flake_url } => add_flake(client, &flake_url),
+        FlakeAction::Show { id } => show_flake(client, id, format),
+        FlakeAction::Rename { id, flake_url } => Ok(client.rename_flake(id, &flake_url)?),
+        FlakeAction::Remove { id } => Ok(client.remove_flake(id)?),
+        FlakeAction::Update { id: Some(id) } => Ok(client.update_flake(id)?),
+        FlakeAction::Update { id: None } => Ok(client.update_flakes()?),
+        FlakeAction::Revisions { id } => list_revisions(client, id, format),
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Tabled)]
+#[derive(Serialize, Tabled)]
 struct Flake {
     #[tabled(rename = "id")]
     flake_id: i64,
     #[tabled(rename = "url")]
     flake_url: String,
     #[tabled(rename = "current revision")]
-    lastest_revision: FlakeRevision,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct FlakeRevision {
     revision: String,
 }
 
-impl Display for FlakeRevision {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.revision)
+impl From<api::Flake> for Flake {
+    fn from(flake: api::Flake) -> Self {
+        Self {
+            flake_id: flake.flake_id,
+            flake_url: flake.flake_url,
+            revision: flake.lastest_revision.revision,
+        }
     }
 }
 
-fn list_flakes(format: Format) -> Result<()> {
-    let flakes: Vec<Flake> = ureq::get(&format_url("/api/v1/flake"))
-        .call()?
-        .into_json()?;
+fn list_flakes(client: &Client, format: Format) -> Result<()> {
+    let flakes: Vec<Flake> = client.flakes()?.into_iter().map(Flake::from).collect();
 
     println!("{}", format_output(flakes, format));
 
     Ok(())
 }
 
-fn add_flake(flake_url: String) -> Result<()> {
-    ureq::post(&format_url("/api/v1/flake")).send_json(ureq::json!({
-        "flake": {
-            "flake_url": flake_url
+fn add_flake(client: &Client, flake_url: &str) -> Result<()> {
+    client.add_flake(flake_url)?;
+    Ok(())
+}
+
+#[derive(Serialize, Tabled)]
+struct FlakeConfiguration {
+    id: i64,
+    name: String,
+    agents: i64,
+}
+
+impl From<api::FlakeConfiguration> for FlakeConfiguration {
+    fn from(config: api::FlakeConfiguration) -> Self {
+        Self {
+            id: config.id,
+            name: config.name,
+            agents: config.agents,
         }
-    }))?;
+    }
+}
+
+fn show_flake(client: &Client, id: i64, format: Format) -> Result<()> {
+    let details = client.flake(id)?;
+
+    if let Format::Json = format {
+        println!("{}", serde_json::to_string(&details)?);
+        return Ok(());
+    }
+
+    let configurations: Vec<FlakeConfiguration> = details
+        .configurations
+        .into_iter()
+        .map(FlakeConfiguration::from)
+        .collect();
+    println!("{}", format_output([Flake::from(details.flake)], format));
+    println!("{}", format_output(configurations, format));
+    Ok(())
+}
+
+#[derive(Serialize, Tabled)]
+struct Revision {
+    #[tabled(rename = "id")]
+    flake_revision_id: i64,
+    revision: String,
+    #[tabled(rename = "last modified")]
+    last_modified: String,
+    #[tabled(rename = "evaluation")]
+    evaluation_status: String,
+    #[tabled(rename = "error", display_with = "display_error")]
+    evaluation_error: Option<String>,
+    #[tabled(rename = "configurations")]
+    evaluations: i64,
+}
+
+impl From<api::Revision> for Revision {
+    fn from(revision: api::Revision) -> Self {
+        Self {
+            flake_revision_id: revision.flake_revision_id,
+            revision: revision.revision,
+            last_modified: revision.last_modified,
+            evaluation_status: revision.evaluation_status,
+            evaluation_error: revision.evaluation_error,
+            evaluations: revision.evaluations,
+        }
+    }
+}
+
+fn display_error(error: &Option<String>) -> String {
+    error.clone().unwrap_or_default()
+}
+
+fn list_revisions(client: &Client, id: i64, format: Format) -> Result<()> {
+    let revisions: Vec<Revision> = client
+        .flake_revisions(id)?
+        .into_iter()
+        .map(Revision::from)
+        .collect();
+
+    println!("{}", format_output(revisions, format));
     Ok(())
 }
@@ -0,0 +1,43 @@
+use color_eyre::Result;
+use nxy_client::{api, blocking::Client};
+use serde::Serialize;
+use tabled::Tabled;
+
+use crate::{
+    args::{Format, SitesAction},
+    utils::format_output,
+};
+
+pub(crate) fn handle(client: &Client, action: SitesAction, format: Format) -> Result<()> {
+    match action {
+        SitesAction::List => list_sites(client, format),
+    }
+}
+
+#[derive(Serialize, Tabled)]
+struct Site {
+    name: String,
+    #[tabled(display_with = "display_substituters")]
+    substituters: Vec<String>,
+}
+
+impl From<api::Site> for Site {
+    fn from(site: api::Site) -> Self {
+        Self {
+            name: site.name,
+            substituters: site.substituters,
+        }
+    }
+}
+
+fn display_substituters(substituters: &[String]) -> String {
+    substituters.join("\n")
+}
+
+fn list_sites(client: &Client, format: Format) -> Result<()> {
+    let sites: Vec<Site> = client.sites()?.into_iter().map(Site::from).collect();
+
+    println!("{}", format_output(sites, format));
+
+    Ok(())
+}
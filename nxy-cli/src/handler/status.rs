@@ -0,0 +1,68 @@
+use color_eyre::Result;
+use nxy_client::{
+    api::{self, AgentFilter, DriftStatus},
+    blocking::Client,
+    labels::Selector,
+};
+use serde::Serialize;
+use tabled::Tabled;
+use uuid::Uuid;
+
+use crate::{
+    args::Format,
+    utils::{display_option, format_output},
+};
+
+#[derive(Serialize, Tabled)]
+struct Drift {
+    #[tabled(rename = "Agent")]
+    agent_id: Uuid,
+
+    #[tabled(rename = "Name", display_with = "display_option")]
+    agent_name: Option<String>,
+
+    #[tabled(rename = "Status")]
+    status: DriftStatus,
+
+    #[tabled(rename = "Config", display_with = "display_option")]
+    nixos_configuration_id: Option<i64>,
+
+    #[tabled(rename = "Desired System", display_with = "display_option")]
+    desired_system: Option<String>,
+
+    #[tabled(rename = "Current System", display_with = "display_option")]
+    current_system: Option<String>,
+
+    #[tabled(rename = "Booted System", display_with = "display_option")]
+    booted_system: Option<String>,
+}
+
+impl From<api::Drift> for Drift {
+    fn from(drift: api::Drift) -> Self {
+        Self {
+            agent_id: drift.agent_id,
+            agent_name: drift.agent_name,
+            status: drift.status,
+            nixos_configuration_id: drift.nixos_configuration_id,
+            desired_system: drift.desired_system,
+            current_system: drift.current_system,
+            booted_system: drift.booted_system,
+        }
+    }
+}
+
+/// Show whether each agent runs the system it should
+pub(crate) fn handle(client: &Client, selector: Option<Selector>, format: Format) -> Result<()> {
+    let filter = AgentFilter {
+        selector,
+        ..Default::default()
+    };
+    let drift: Vec<Drift> = client
+        .drift(&filter)?
+        .into_iter()
+        .map(Drift::from)
+        .collect();
+
+    println!("{}", format_output(drift, format));
+    Ok(())
+}
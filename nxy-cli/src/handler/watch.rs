@@ -0,0 +1,62 @@
+use color_eyre::Result;
+use nxy_client::{
+    api::{Event, EventFilter},
+    blocking::Client,
+};
+
+use crate::args::Format;
+
+/// Print events as they happen until the server closes the stream
+pub(crate) fn handle(client: &Client, filter: EventFilter, format: Format) -> Result<()> {
+    for event in client.events(&filter)? {
+        let event = event?;
+        match format {
+            Format::Table => println!("{}", describe(&event)),
+            Format::Json => println!("{}", serde_json::to_string(&event)?),
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn describe(event: &Event) -> String {
+    match event {
+        Event::AgentConnected { agent_id } => format!("agent {agent_id} connected"),
+        Event::AgentDisconnected { agent_id } => format!("agent {agent_id} disconnected"),
+        Event::FlakeRevision {
+            flake_id, revision, ..
+        } => format!("flake {flake_id} has new revision {revision}"),
+        Event::EvaluationStarted {
+            flake_id,
+            flake_revision_id,
+        } => format!("flake {flake_id} evaluating revision {flake_revision_id}"),
+        Event::EvaluationSucceeded {
+            flake_id,
+            flake_revision_id,
+        } => format!("flake {flake_id} evaluated revision {flake_revision_id}"),
+        Event::EvaluationFailed {
+            flake_id,
+            flake_revision_id,
+            error,
+        } => format!("flake {flake_id} failed to evaluate revision {flake_revision_id}: {error}"),
+        Event::Deployment {
+            agent_id,
+            store_path,
+            action,
+            phase,
+            error,
+            ..
+        } => match error {
+            Some(error) => format!("agent {agent_id} {action} {store_path} {phase}: {error}"),
+            None => format!("agent {agent_id} {action} {store_path} {phase}"),
+        },
+        Event::DeploymentFinished {
+            deployment_id,
+            agents,
+            failed,
+        } => format!("deployment {deployment_id} finished, {failed} of {agents} agents failed"),
+        Event::Drifted {
+            agent_id,
+            current_system,
+        } => format!("agent {agent_id} drifted to {current_system}"),
+    }
+}
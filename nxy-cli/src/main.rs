@@ -1,19 +1,91 @@
 mod args;
+mod config;
 mod handler;
 mod utils;
 
-use args::{Action, Args};
+use args::{Action, Args, DeployTarget, Format};
+use config::Config;
 
 use clap::Parser;
 use color_eyre::Result;
+use nxy_client::{
+    api::{AuditFilter, EventFilter},
+    blocking::Client,
+};
 
 fn main() -> Result<()> {
     color_eyre::install()?;
     let args = Args::parse();
+    let config = Config::load()?;
+
+    // contexts are managed without connecting to a server, and work with a broken current one
+    if let Action::Context { action } = args.action {
+        let format = args.format.unwrap_or(Format::Table);
+        return handler::context::handle(config, action, format);
+    }
+
+    let context = config.select(args.context.as_deref())?;
+    let format = args
+        .format
+        .or(context.and_then(|context| context.format))
+        .unwrap_or(Format::Table);
+    let client = match context {
+        Some(context) => context.client()?,
+        None => Client::from_env()?,
+    };
 
     match args.action {
-        Action::Agents { action } => handler::agent::handle(action, args.format),
-        Action::Flakes { action } => handler::flake::handle(action, args.format),
-        Action::Configs { action } => handler::configuration::handle(action, args.format),
+        Action::Context { .. } => unreachable!("handled without a client"),
+        Action::Agents { action } => handler::agent::handle(&client, action, format),
+        Action::Flakes { action } => handler::flake::handle(&client, action, format),
+        Action::Configs { action } => handler::configuration::handle(&client, action, format),
+        Action::Sites { action } => handler::site::handle(&client, action, format),
+        Action::Status { selector } => handler::status::handle(&client, selector, format),
+        Action::Deploy {
+            target,
+            agent,
+            revision,
+            mode,
+            wait,
+        } => {
+            // clap requires exactly one of both
+            let target = match (target, agent) {
+                (_, Some(agent)) => DeployTarget::Agent(agent),
+                (Some(target), None) => target,
+                (None, None) => unreachable!("deploy without target or agent"),
+            };
+            handler::deploy::handle(&client, target, revision, mode, wait, format)
+        }
+        Action::Audit {
+            actor,
+            action,
+            agent,
+            target,
+            outcome,
+            since,
+            limit,
+        } => {
+            // the audit log refers to agents by id
+            let agent = agent.map(|agent| client.agent(&agent)).transpose()?;
+            let filter = AuditFilter {
+                actor,
+                action,
+                target: agent.map(|agent| format!("agent/{}", agent.id)).or(target),
+                outcome: outcome.map(|outcome| outcome.as_str().to_string()),
+                since,
+                until: None,
+                limit: Some(limit),
+            };
+            handler::audit::handle(&client, filter, format)
+        }
+        Action::Watch { agent, flake } => {
+            let agent = agent.map(|agent| client.agent(&agent)).transpose()?;
+            let filter = EventFilter {
+                agent_id: agent.map(|agent| agent.id),
+                flake_id: flake,
+                deployment_id: None,
+            };
+            handler::watch::handle(&client, filter, format)
+        }
     }
 }
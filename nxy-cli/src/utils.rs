@@ -1,32 +1,11 @@
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use tabled::{Style, Table, Tabled};
 
 use crate::args::Format;
 
-/// Returns value of the `NXY_SERVER` enviorment variable or `http://localhost:8080`
-#[must_use]
-pub(crate) fn server_url() -> String {
-    std::env::var("NXY_SERVER").unwrap_or_else(|_| "http://localhost:8080".to_string())
-}
-
-/// Prefix an request path with the server url.
-///
-/// # Arguments
-///
-/// * `path` - URL path to be prefixed. eg. `/api/v1/flakes`
-///
-/// # Returns
-///
-/// Path prefixed with server url
-///
-/// # Notes
-///
-/// The server url is provided by [`server_url`].
-#[must_use]
-pub(crate) fn format_url(path: &str) -> String {
-    let host = server_url();
-    let path = path.strip_prefix('/').unwrap_or(path);
-    format!("{host}/{path}")
+/// Display helper for optional table columns, renders `None` as an empty cell.
+pub(crate) fn display_option<T: std::fmt::Display>(value: &Option<T>) -> String {
+    value.as_ref().map(ToString::to_string).unwrap_or_default()
 }
 
 pub(crate) fn format_output<I, T>(data: I, format: Format) -> String
@@ -0,0 +1,282 @@
+//! Typed methods for every endpoint of the REST API.
+//!
+//! Each endpoint is declared once and expands to a method of [`crate::Client`] and, with the
+//! `blocking` feature, of [`crate::blocking::Client`].
+//!
+//! Agents are addressed by id, name or a unique prefix of their id.
+
+use nxy_common::{
+    api::{
+        Activate, Agent, AgentDetails, AgentFilter, AgentResult, AgentUnits, AuditEntry,
+        AuditFilter, Configuration, Deploy, DeployPolicy, Deployment, DeploymentDetails,
+        DeploymentStarted, DownloadStorePath, Drift, Flake, FlakeBody, FlakeDetails, NewFlake, Pin,
+        Reboot, Revision, Rollback, RolledBack, Selected, SetConfiguration, SetDeployPolicy,
+        SetLabels, SetName, SetSite, Site,
+    },
+    labels::{Labels, Selector},
+    types::ActivationMode,
+};
+
+use crate::{request::Request, Result};
+
+macro_rules! endpoints {
+    ($(
+        $(#[$meta:meta])*
+        fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty $request:block
+    )*) => {
+        impl crate::Client {
+            $(
+                $(#[$meta])*
+                pub async fn $name(&self, $($arg: $ty),*) -> Result<$ret> {
+                    let request: Request<$ret> = $request;
+                    self.send(request).await
+                }
+            )*
+        }
+
+        #[cfg(feature = "blocking")]
+        impl crate::blocking::Client {
+            $(
+                $(#[$meta])*
+                pub fn $name(&self, $($arg: $ty),*) -> Result<$ret> {
+                    let request: Request<$ret> = $request;
+                    self.send(request)
+                }
+            )*
+        }
+    };
+}
+
+endpoints! {
+    /// List all flakes with their latest revision
+    fn flakes() -> Vec<Flake> {
+        Request::get("/api/v1/flake")
+    }
+
+    /// Add a flake, its configurations are evaluated in the background
+    fn add_flake(flake_url: &str) -> Flake {
+        let body = FlakeBody {
+            flake: NewFlake {
+                flake_url: flake_url.to_string(),
+            },
+        };
+        Request::post("/api/v1/flake", body).map(|body: FlakeBody<Flake>| body.flake)
+    }
+
+    /// Fetch and evaluate the latest revision of all flakes
+    fn update_flakes() -> () {
+        Request::put("/api/v1/flake")
+    }
+
+    /// Show a flake and its configurations
+    fn flake(flake_id: i64) -> FlakeDetails {
+        Request::get(format!("/api/v1/flake/{flake_id}"))
+            .map(|body: FlakeBody<FlakeDetails>| body.flake)
+    }
+
+    /// Fetch and evaluate the latest revision of a single flake
+    fn update_flake(flake_id: i64) -> () {
+        Request::put(format!("/api/v1/flake/{flake_id}"))
+    }
+
+    /// Change the url of a flake, fails with code `conflict` if another flake has the url
+    fn rename_flake(flake_id: i64, flake_url: &str) -> () {
+        let body = FlakeBody {
+            flake: NewFlake {
+                flake_url: flake_url.to_string(),
+            },
+        };
+        Request::patch(format!("/api/v1/flake/{flake_id}"), body)
+    }
+
+    /// Remove a flake, fails with code `conflict` while agents use its configurations
+    fn remove_flake(flake_id: i64) -> () {
+        Request::delete(format!("/api/v1/flake/{flake_id}"))
+    }
+
+    /// List all revisions of a flake, newest first
+    fn flake_revisions(flake_id: i64) -> Vec<Revision> {
+        Request::get(format!("/api/v1/flake/{flake_id}/revisions"))
+    }
+
+    /// List all configurations of all flakes
+    fn configurations() -> Vec<Configuration> {
+        Request::get("/api/v1/configuration")
+    }
+
+    /// Pin all agents of a configuration to a revision, `None` to follow the latest revision
+    fn pin_configuration(config_id: i64, revision: Option<&str>) -> () {
+        let body = Pin {
+            revision: revision.map(String::from),
+        };
+        Request::post(format!("/api/v1/configuration/{config_id}/pin"), body)
+    }
+
+    /// List all agents matching `filter`
+    fn agents(filter: &AgentFilter) -> Vec<Agent> {
+        Request::get("/api/v1/agent").filter(filter)
+    }
+
+    /// Show an agent with its last reported facts
+    fn agent(agent: &str) -> AgentDetails {
+        Request::get(format!("/api/v1/agent/{agent}"))
+    }
+
+    /// Last reported systemd unit states of an agent
+    fn agent_units(agent: &str) -> AgentUnits {
+        Request::get(format!("/api/v1/agent/{agent}/units"))
+    }
+
+    /// Deployment steps the reconciler ran on an agent, newest first
+    fn agent_deployments(agent: &str) -> Vec<Deployment> {
+        Request::get(format!("/api/v1/agent/{agent}/deployments"))
+    }
+
+    /// Assign a configuration to an agent
+    fn set_configuration(agent: &str, config_id: i64) -> () {
+        Request::post(
+            format!("/api/v1/agent/{agent}"),
+            SetConfiguration { config_id },
+        )
+    }
+
+    /// Assign an agent to a substituter site, `None` to only use the nxy server
+    fn set_site(agent: &str, site: Option<&str>) -> () {
+        let body = SetSite {
+            site: site.map(String::from),
+        };
+        Request::post(format!("/api/v1/agent/{agent}/site"), body)
+    }
+
+    /// Change how far the reconciler may go to deploy an agent
+    fn set_deploy_policy(agent: &str, policy: DeployPolicy) -> () {
+        Request::post(
+            format!("/api/v1/agent/{agent}/deploy-policy"),
+            SetDeployPolicy { policy },
+        )
+    }
+
+    /// Pin an agent to a revision of its configuration, `None` to unpin it
+    fn pin_agent(agent: &str, revision: Option<&str>) -> () {
+        let body = Pin {
+            revision: revision.map(String::from),
+        };
+        Request::post(format!("/api/v1/agent/{agent}/pin"), body)
+    }
+
+    /// Rename an agent, `None` to name it after its hostname again
+    fn set_name(agent: &str, name: Option<&str>) -> () {
+        let body = SetName {
+            name: name.map(String::from),
+        };
+        Request::post(format!("/api/v1/agent/{agent}/name"), body)
+    }
+
+    /// Set and remove labels of an agent
+    fn set_labels(agent: &str, set: Labels, remove: Vec<String>) -> () {
+        Request::post(
+            format!("/api/v1/agent/{agent}/labels"),
+            SetLabels { set, remove },
+        )
+    }
+
+    /// Copy a store path to an agent
+    fn download(agent: &str, store_path: &str) -> () {
+        let body = DownloadStorePath {
+            store_path: store_path.to_string(),
+        };
+        Request::post(format!("/api/v1/agent/{agent}/download"), body)
+    }
+
+    /// Activate a system on an agent, it has to be downloaded first
+    fn activate(agent: &str, store_path: &str, mode: ActivationMode) -> () {
+        let body = Activate {
+            store_path: store_path.to_string(),
+            mode,
+        };
+        Request::post(format!("/api/v1/agent/{agent}/activate"), body)
+    }
+
+    /// Activate the previous system generation of an agent
+    fn rollback(agent: &str, mode: ActivationMode) -> RolledBack {
+        Request::post(format!("/api/v1/agent/{agent}/rollback"), Rollback { mode })
+    }
+
+    /// Reboot an agent now, after a delay or in a maintenance window
+    fn reboot(agent: &str, reboot: Reboot) -> () {
+        Request::post(format!("/api/v1/agent/{agent}/reboot"), reboot)
+    }
+
+    /// Copy a store path to every agent matching `selector`
+    fn download_selected(selector: &Selector, store_path: &str) -> Vec<AgentResult> {
+        let body = Selected {
+            selector: selector.clone(),
+            request: DownloadStorePath {
+                store_path: store_path.to_string(),
+            },
+        };
+        Request::post("/api/v1/agent/download", body)
+    }
+
+    /// Activate a system on every agent matching `selector`
+    fn activate_selected(
+        selector: &Selector,
+        store_path: &str,
+        mode: ActivationMode
+    ) -> Vec<AgentResult> {
+        let body = Selected {
+            selector: selector.clone(),
+            request: Activate {
+                store_path: store_path.to_string(),
+                mode,
+            },
+        };
+        Request::post("/api/v1/agent/activate", body)
+    }
+
+    /// Activate the previous system generation of every agent matching `selector`
+    fn rollback_selected(selector: &Selector, mode: ActivationMode) -> Vec<AgentResult> {
+        let body = Selected {
+            selector: selector.clone(),
+            request: Rollback { mode },
+        };
+        Request::post("/api/v1/agent/rollback", body)
+    }
+
+    /// Reboot every agent matching `selector`
+    fn reboot_selected(selector: &Selector, reboot: Reboot) -> Vec<AgentResult> {
+        let body = Selected {
+            selector: selector.clone(),
+            request: reboot,
+        };
+        Request::post("/api/v1/agent/reboot", body)
+    }
+
+    /// Deploy a configuration, or all agents matching a selector, in the background
+    ///
+    /// Follow its progress with [`crate::Client::events`], filtered by the returned id, or
+    /// [`crate::Client::deployment`].
+    fn deploy(deploy: &Deploy) -> DeploymentStarted {
+        Request::post("/api/v1/deployment", deploy)
+    }
+
+    /// A deployment with its outcome on every agent
+    fn deployment(deployment_id: i64) -> DeploymentDetails {
+        Request::get(format!("/api/v1/deployment/{deployment_id}"))
+    }
+
+    /// List all configured substituter sites
+    fn sites() -> Vec<Site> {
+        Request::get("/api/v1/site")
+    }
+
+    /// Desired and actual system of every agent matching `filter`
+    fn drift(filter: &AgentFilter) -> Vec<Drift> {
+        Request::get("/api/v1/drift").filter(filter)
+    }
+
+    /// Audit log entries matching `filter`, newest first
+    fn audit(filter: &AuditFilter) -> Vec<AuditEntry> {
+        Request::get("/api/v1/audit").filter(filter)
+    }
+}
@@ -0,0 +1,42 @@
+use reqwest::StatusCode;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The server rejected the request, see [`nxy_common::api::ErrorBody`] for the codes
+    #[error("{message} ({status} {code})")]
+    Api {
+        status: StatusCode,
+        /// Machine-readable error code, eg. `not-found` or `agent-offline`
+        code: String,
+        message: String,
+    },
+
+    /// The server answered with an error status but without an error body, e.g. a proxy in
+    /// front of it
+    #[error("server responded with status {0}")]
+    Status(StatusCode),
+
+    /// The server couldn't be reached
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+
+    /// Reading a streamed response failed
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// The response didn't match the expected model
+    #[error("invalid response: {0}")]
+    Decode(#[from] serde_json::Error),
+}
+
+impl Error {
+    /// Machine-readable error code, if the server sent one
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Error::Api { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Reading the server-sent event stream of `GET /api/v1/events`.
+
+use nxy_common::api::{Event, EventFilter};
+
+use crate::{request::Request, Result};
+
+pub(crate) fn request(filter: &EventFilter) -> Request<Event> {
+    Request::get("/api/v1/events").filter(filter)
+}
+
+/// Incremental parser for the `text/event-stream` format
+///
+/// Only `data` fields are of interest, the event type is part of the payload.
+#[derive(Debug, Default)]
+pub(crate) struct Parser {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl Parser {
+    pub(crate) fn feed(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// Next complete event, `None` if more input is needed
+    pub(crate) fn next_event(&mut self) -> Option<Result<Event>> {
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                // a blank line dispatches the event, keep-alive comments have no data
+                if self.data.is_empty() {
+                    continue;
+                }
+                let data = std::mem::take(&mut self.data).join("\n");
+                return Some(serde_json::from_str(&data).map_err(Into::into));
+            }
+            if let Some(value) = line.strip_prefix("data:") {
+                self.data
+                    .push(value.strip_prefix(' ').unwrap_or(value).to_string());
+            }
+        }
+        None
+    }
+}
+
+/// Events received by [`crate::Client::events`]
+#[derive(Debug)]
+pub struct EventStream {
+    pub(crate) response: reqwest::Response,
+    pub(crate) parser: Parser,
+}
+
+impl EventStream {
+    /// Wait for the next event, `None` once the server closed the stream
+    pub async fn next(&mut self) -> Option<Result<Event>> {
+        loop {
+            if let Some(event) = self.parser.next_event() {
+                return Some(event);
+            }
+            match self.response.chunk().await {
+                Ok(Some(chunk)) => self.parser.feed(&chunk),
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nxy_common::api::Event;
+    use uuid::Uuid;
+
+    use super::Parser;
+
+    #[test]
+    fn parse_split_events() {
+        let agent_id = Uuid::nil();
+        let mut parser = Parser::default();
+        parser.feed(b": keep-alive\n\nevent: agent-connected\ndata: {\"type\":\"agent-conn");
+        assert!(parser.next_event().is_none());
+
+        parser.feed(b"ected\",\"agent_id\":\"00000000-0000-0000-0000-000000000000\"}\r\n\r\n");
+        let event = parser.next_event().unwrap().unwrap();
+        assert_eq!(event, Event::AgentConnected { agent_id });
+        assert!(parser.next_event().is_none());
+    }
+}
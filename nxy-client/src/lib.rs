@@ -0,0 +1,244 @@
+//! Typed client for the nxy server REST API.
+//!
+//! [`Client`] is async, [`blocking::Client`] offers the same methods for callers without an
+//! async runtime. Models are shared with the server, see [`api`].
+//!
+//! ```no_run
+//! # async fn example() -> nxy_client::Result<()> {
+//! let client = nxy_client::Client::from_env()?;
+//! for flake in client.flakes().await? {
+//!     println!("{} {}", flake.flake_id, flake.flake_url);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod endpoints;
+mod error;
+mod events;
+mod request;
+
+use std::time::Duration;
+
+pub use error::{Error, Result};
+pub use events::EventStream;
+pub use nxy_common::{api, labels, types};
+
+use nxy_common::api::EventFilter;
+use request::Request;
+use serde::de::DeserializeOwned;
+
+/// Server used by [`Client::from_env`] if `NXY_SERVER` isn't set
+pub const DEFAULT_SERVER_URL: &str = "http://localhost:8085";
+
+/// Connection settings shared by [`Client`] and [`blocking::Client`]
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    url: String,
+    token: Option<String>,
+    timeout: Option<Duration>,
+}
+
+impl ClientBuilder {
+    /// Server url without the `/api/v1` prefix, eg. `https://nxy.example.com`
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into().trim_end_matches('/').to_string(),
+            token: None,
+            timeout: None,
+        }
+    }
+
+    /// Send `token` as bearer token with every request, e.g. for a server behind an
+    /// authenticating reverse proxy
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Fail requests which take longer than `timeout`, deployments may take a while
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn build(self) -> Result<Client> {
+        let mut http = reqwest::Client::builder();
+        if let Some(timeout) = self.timeout {
+            http = http.timeout(timeout);
+        }
+        Ok(Client {
+            http: http.build()?,
+            settings: self,
+        })
+    }
+
+    #[cfg(feature = "blocking")]
+    pub fn build_blocking(self) -> Result<blocking::Client> {
+        // the blocking client defaults to a 30s timeout, agent calls may take longer
+        let http = reqwest::blocking::Client::builder()
+            .timeout(self.timeout)
+            .build()?;
+        Ok(blocking::Client {
+            http,
+            settings: self,
+        })
+    }
+
+    /// Settings from `NXY_SERVER` and `NXY_TOKEN`
+    pub fn from_env() -> Self {
+        let url = std::env::var("NXY_SERVER").unwrap_or_else(|_| DEFAULT_SERVER_URL.to_string());
+        let builder = Self::new(url);
+        match std::env::var("NXY_TOKEN") {
+            Ok(token) => builder.token(token),
+            Err(_) => builder,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.url)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    settings: ClientBuilder,
+}
+
+impl Client {
+    pub fn builder(url: impl Into<String>) -> ClientBuilder {
+        ClientBuilder::new(url)
+    }
+
+    /// Client for the server in `NXY_SERVER`, see [`ClientBuilder::from_env`]
+    pub fn from_env() -> Result<Self> {
+        ClientBuilder::from_env().build()
+    }
+
+    /// Follow the events of the server, optionally restricted by `filter`
+    pub async fn events(&self, filter: &EventFilter) -> Result<EventStream> {
+        let request = events::request(filter);
+        let response = self.prepare(&request).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.bytes().await?;
+            return Err(request.error(status, &body));
+        }
+        Ok(EventStream {
+            response,
+            parser: Default::default(),
+        })
+    }
+
+    fn prepare<T>(&self, request: &Request<T>) -> reqwest::RequestBuilder {
+        let mut builder = self
+            .http
+            .request(request.method.clone(), self.settings.url(&request.path))
+            .query(&request.query);
+        if let Some(ref token) = self.settings.token {
+            builder = builder.bearer_auth(token);
+        }
+        if let Some(ref body) = request.body {
+            builder = builder.json(body);
+        }
+        builder
+    }
+
+    async fn send<T: DeserializeOwned + 'static>(&self, request: Request<T>) -> Result<T> {
+        let response = self.prepare(&request).send().await?;
+        let status = response.status();
+        let body = response.bytes().await?;
+        request.response(status, &body)
+    }
+}
+
+#[cfg(feature = "blocking")]
+pub mod blocking {
+    //! Blocking variant of [`crate::Client`].
+
+    use std::io::Read;
+
+    use nxy_common::api::{Event, EventFilter};
+    use serde::de::DeserializeOwned;
+
+    use crate::{events, request::Request, ClientBuilder, Result};
+
+    #[derive(Debug, Clone)]
+    pub struct Client {
+        pub(crate) http: reqwest::blocking::Client,
+        pub(crate) settings: ClientBuilder,
+    }
+
+    impl Client {
+        pub fn builder(url: impl Into<String>) -> ClientBuilder {
+            ClientBuilder::new(url)
+        }
+
+        /// Client for the server in `NXY_SERVER`, see [`ClientBuilder::from_env`]
+        pub fn from_env() -> Result<Self> {
+            ClientBuilder::from_env().build_blocking()
+        }
+
+        /// Follow the events of the server, optionally restricted by `filter`
+        pub fn events(&self, filter: &EventFilter) -> Result<Events> {
+            let request = events::request(filter);
+            let response = self.prepare(&request).send()?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.bytes()?;
+                return Err(request.error(status, &body));
+            }
+            Ok(Events {
+                response,
+                parser: Default::default(),
+            })
+        }
+
+        fn prepare<T>(&self, request: &Request<T>) -> reqwest::blocking::RequestBuilder {
+            let mut builder = self
+                .http
+                .request(request.method.clone(), self.settings.url(&request.path))
+                .query(&request.query);
+            if let Some(ref token) = self.settings.token {
+                builder = builder.bearer_auth(token);
+            }
+            if let Some(ref body) = request.body {
+                builder = builder.json(body);
+            }
+            builder
+        }
+
+        pub(crate) fn send<T: DeserializeOwned + 'static>(&self, request: Request<T>) -> Result<T> {
+            let response = self.prepare(&request).send()?;
+            let status = response.status();
+            let body = response.bytes()?;
+            request.response(status, &body)
+        }
+    }
+
+    /// Events received by [`Client::events`], ends once the server closed the stream
+    #[derive(Debug)]
+    pub struct Events {
+        response: reqwest::blocking::Response,
+        parser: events::Parser,
+    }
+
+    impl Iterator for Events {
+        type Item = Result<Event>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            let mut chunk = [0; 4096];
+            loop {
+                if let Some(event) = self.parser.next_event() {
+                    return Some(event);
+                }
+                match self.response.read(&mut chunk) {
+                    Ok(0) => return None,
+                    Ok(read) => self.parser.feed(&chunk[..read]),
+                    Err(err) => return Some(Err(err.into())),
+                }
+            }
+        }
+    }
+}
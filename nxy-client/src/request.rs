@@ -0,0 +1,107 @@
+use nxy_common::api::ErrorBody;
+use reqwest::{Method, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{Error, Result};
+
+type Decode<T> = Box<dyn FnOnce(&[u8]) -> serde_json::Result<T> + Send>;
+
+/// A request to the nxy server, independent of the client executing it
+pub(crate) struct Request<T> {
+    pub(crate) method: Method,
+    pub(crate) path: String,
+    pub(crate) query: Vec<(String, String)>,
+    pub(crate) body: Option<serde_json::Value>,
+    decode: Decode<T>,
+}
+
+impl<T: DeserializeOwned + 'static> Request<T> {
+    fn new(method: Method, path: impl Into<String>) -> Self {
+        Self {
+            method,
+            path: path.into(),
+            query: Vec::new(),
+            body: None,
+            decode: Box::new(|body| {
+                // handlers without a result answer with an empty body
+                let body = if body.is_empty() { b"null" } else { body };
+                serde_json::from_slice(body)
+            }),
+        }
+    }
+
+    pub(crate) fn get(path: impl Into<String>) -> Self {
+        Self::new(Method::GET, path)
+    }
+
+    pub(crate) fn post(path: impl Into<String>, body: impl Serialize) -> Self {
+        Self::new(Method::POST, path).json(body)
+    }
+
+    pub(crate) fn put(path: impl Into<String>) -> Self {
+        Self::new(Method::PUT, path)
+    }
+
+    pub(crate) fn patch(path: impl Into<String>, body: impl Serialize) -> Self {
+        Self::new(Method::PATCH, path).json(body)
+    }
+
+    pub(crate) fn delete(path: impl Into<String>) -> Self {
+        Self::new(Method::DELETE, path)
+    }
+}
+
+impl<T: 'static> Request<T> {
+    fn json(mut self, body: impl Serialize) -> Self {
+        // all models serialize infallibly
+        self.body = Some(serde_json::to_value(body).unwrap());
+        self
+    }
+
+    /// Add all set fields of `filter` to the query
+    pub(crate) fn filter(mut self, filter: impl Serialize) -> Self {
+        // filters are flat structs of optional scalars
+        if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(filter) {
+            for (key, value) in fields {
+                match value {
+                    serde_json::Value::Null => {}
+                    serde_json::Value::String(value) => self.query.push((key, value)),
+                    value => self.query.push((key, value.to_string())),
+                }
+            }
+        }
+        self
+    }
+
+    /// Transform the decoded response, e.g. to strip an envelope
+    pub(crate) fn map<U: 'static>(self, f: fn(T) -> U) -> Request<U> {
+        let decode = self.decode;
+        Request {
+            method: self.method,
+            path: self.path,
+            query: self.query,
+            body: self.body,
+            decode: Box::new(move |body| decode(body).map(f)),
+        }
+    }
+
+    /// Turn the response of the server into the result of this request
+    pub(crate) fn response(self, status: StatusCode, body: &[u8]) -> Result<T> {
+        if !status.is_success() {
+            return Err(self.error(status, body));
+        }
+        Ok((self.decode)(body)?)
+    }
+
+    /// Error for an unsuccessful response
+    pub(crate) fn error(&self, status: StatusCode, body: &[u8]) -> Error {
+        match serde_json::from_slice::<ErrorBody>(body) {
+            Ok(error) => Error::Api {
+                status,
+                code: error.code,
+                message: error.message,
+            },
+            Err(_) => Error::Status(status),
+        }
+    }
+}
@@ -0,0 +1,650 @@
+//! Request and response bodies of the nxy server REST API.
+//!
+//! Shared by `nxy-server` and `nxy-client`, so both sides agree on the wire format.
+
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    labels::{Labels, Selector},
+    types::{ActivationMode, Facts, RebootWindow, UnitStatus},
+};
+
+/// Body of all errors returned by the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct ErrorBody {
+    /// Machine-readable error code, eg. `not-found` or `agent-offline`
+    pub code: String,
+    pub message: String,
+}
+
+/// Envelope of single flakes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "utoipa",
+    derive(utoipa::ToSchema),
+    aliases(
+        FlakeBodyFlake = FlakeBody<Flake>,
+        FlakeBodyNewFlake = FlakeBody<NewFlake>,
+        FlakeBodyFlakeDetails = FlakeBody<FlakeDetails>
+    )
+)]
+pub struct FlakeBody<T> {
+    pub flake: T,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Flake {
+    pub flake_id: i64,
+    pub flake_url: String,
+    pub lastest_revision: FlakeRevision,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct FlakeRevision {
+    pub flake_revision_id: i64,
+    pub revision: String,
+    pub last_modified: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct NewFlake {
+    pub flake_url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct FlakeDetails {
+    #[serde(flatten)]
+    pub flake: Flake,
+    pub configurations: Vec<FlakeConfiguration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct FlakeConfiguration {
+    pub id: i64,
+    pub name: String,
+    /// Number of agents assigned to this configuration
+    pub agents: i64,
+}
+
+/// A flake revision and the outcome of its evaluation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Revision {
+    pub flake_revision_id: i64,
+    pub revision: String,
+    pub last_modified: String,
+    pub url: String,
+    /// `pending`, `succeeded` or `failed`
+    pub evaluation_status: String,
+    pub evaluation_error: Option<String>,
+    /// Number of configurations evaluated at this revision
+    pub evaluations: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Configuration {
+    pub id: i64,
+    pub name: String,
+    pub flake_id: i64,
+    pub flake_url: String,
+    /// Revision all agents of this configuration are pinned to
+    pub pinned_revision: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Pin {
+    /// Git revision, or a unique prefix of it, `None` to follow the latest revision again
+    pub revision: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Agent {
+    pub id: Uuid,
+    /// Unique name, the hostname unless set through the API, `None` until facts are reported
+    pub name: Option<String>,
+    pub current_system: Option<String>,
+    pub site: Option<String>,
+    /// Components requiring a reboot to take effect, empty if no reboot is required
+    pub reboot_required: Vec<String>,
+    /// The current system was changed outside of nxy
+    pub drifted: bool,
+    pub deploy_policy: DeployPolicy,
+    pub connected: bool,
+    #[cfg_attr(feature = "utoipa", schema(value_type = BTreeMap<String, String>))]
+    pub labels: Labels,
+}
+
+/// Restricts a list of agents, all set fields have to match
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "utoipa", into_params(parameter_in = Query))]
+pub struct AgentFilter {
+    /// Only agents which require a reboot
+    #[serde(default)]
+    pub reboot_required: bool,
+    /// Only agents whose labels match this selector, eg. `env=prod,role in (web,api)`
+    #[cfg_attr(feature = "utoipa", param(value_type = Option<String>))]
+    pub selector: Option<Selector>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct AgentDetails {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub current_system: Option<String>,
+    pub site: Option<String>,
+    pub reboot_required: Vec<String>,
+    pub drifted: bool,
+    /// Revision the agent is pinned to, overrides the pin of its configuration
+    pub pinned_revision: Option<String>,
+    pub connected: bool,
+    /// Labels set through the API or reported by the agent
+    #[cfg_attr(feature = "utoipa", schema(value_type = BTreeMap<String, String>))]
+    pub labels: Labels,
+    /// Last reported facts, `None` if the agent never reported any
+    pub facts: Option<Facts>,
+    pub facts_updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct AgentUnits {
+    /// Units in the `failed` state
+    pub failed: Vec<UnitStatus>,
+    /// Units started, restarted or reloaded by the last activation
+    pub activated: Vec<UnitStatus>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A deployment step the reconciler ran on an agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Deployment {
+    pub store_path: String,
+    /// `download`, or the activation mode
+    pub action: String,
+    /// `None` if the step succeeded
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct SetConfiguration {
+    pub config_id: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct SetSite {
+    pub site: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct SetDeployPolicy {
+    pub policy: DeployPolicy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct DownloadStorePath {
+    pub store_path: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Activate {
+    pub store_path: String,
+    #[serde(default)]
+    pub mode: ActivationMode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Rollback {
+    #[serde(default)]
+    pub mode: ActivationMode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct RolledBack {
+    /// System the agent rolled back to
+    #[cfg_attr(feature = "utoipa", schema(value_type = String))]
+    pub store_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Reboot {
+    /// Minutes to wait before rebooting
+    pub delay: Option<u32>,
+    /// Daily window, in the agent's local time, to reboot in
+    pub window: Option<RebootWindow>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct SetName {
+    /// `None` to name the agent after its hostname again
+    pub name: Option<String>,
+}
+
+/// Labels to change, labels set through the API override those reported by the agent
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct SetLabels {
+    #[serde(default)]
+    #[cfg_attr(feature = "utoipa", schema(value_type = BTreeMap<String, String>))]
+    pub set: Labels,
+    /// Keys of labels to remove
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+/// A request sent to every agent matching `selector`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "utoipa",
+    derive(utoipa::ToSchema),
+    aliases(
+        SelectedDownloadStorePath = Selected<DownloadStorePath>,
+        SelectedActivate = Selected<Activate>,
+        SelectedRollback = Selected<Rollback>,
+        SelectedReboot = Selected<Reboot>
+    )
+)]
+pub struct Selected<T> {
+    /// Label selector, eg. `env=prod,role in (web,api)`
+    #[cfg_attr(feature = "utoipa", schema(value_type = String))]
+    pub selector: Selector,
+    #[serde(flatten)]
+    pub request: T,
+}
+
+/// Deploy a configuration, all agents matching a selector, or a single agent
+///
+/// Exactly one of `config`, `selector` and `agent` has to be set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Deploy {
+    /// Configuration name or id
+    pub config: Option<String>,
+    /// Label selector, eg. `env=prod,role in (web,api)`
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<String>))]
+    pub selector: Option<Selector>,
+    /// Agent id, name or a unique prefix of its id
+    #[serde(default)]
+    pub agent: Option<String>,
+    /// Git revision, or a unique prefix of it, to pin to, `None` to keep the current pins
+    pub revision: Option<String>,
+    #[serde(default)]
+    pub mode: ActivationMode,
+}
+
+/// A deployment running in the background, its progress is streamed as events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct DeploymentStarted {
+    pub id: i64,
+    /// Every targeted agent with the system deployed to it, or the error why it's skipped
+    pub agents: Vec<AgentResult>,
+}
+
+/// A deployment and its outcome on every targeted agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct DeploymentDetails {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub config: Option<String>,
+    pub selector: Option<String>,
+    pub agent: Option<String>,
+    pub revision: Option<String>,
+    pub mode: String,
+    /// `None` while agents are still being deployed to
+    pub finished_at: Option<DateTime<Utc>>,
+    pub agents: Vec<DeploymentAgent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct DeploymentAgent {
+    pub agent_id: Uuid,
+    /// `None` if the agent was skipped before its system was known
+    pub store_path: Option<String>,
+    /// `skipped`, `running`, `succeeded` or `failed`
+    pub status: String,
+    pub error: Option<String>,
+}
+
+/// Outcome of a request sent to one of the agents matching a selector
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct AgentResult {
+    pub agent_id: Uuid,
+    /// System the agent rolled back to, or is deployed to
+    pub store_path: Option<String>,
+    /// `None` if the request succeeded on this agent
+    pub error: Option<ErrorBody>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Site {
+    pub name: String,
+    pub substituters: Vec<String>,
+}
+
+/// Whether an agent runs the system it should
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum DriftStatus {
+    /// The agent runs the desired system
+    InSync,
+    /// The desired system isn't on the agent yet
+    PendingDownload,
+    /// The desired system was copied to the agent, but isn't active
+    DownloadedNotActivated,
+    /// The current system was changed outside of nxy
+    Drifted,
+    /// The agent has no configuration assigned or it wasn't evaluated yet
+    UnknownConfiguration,
+}
+
+impl DriftStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DriftStatus::InSync => "in-sync",
+            DriftStatus::PendingDownload => "pending-download",
+            DriftStatus::DownloadedNotActivated => "downloaded-not-activated",
+            DriftStatus::Drifted => "drifted",
+            DriftStatus::UnknownConfiguration => "unknown-configuration",
+        }
+    }
+}
+
+impl Display for DriftStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Drift {
+    pub agent_id: Uuid,
+    pub agent_name: Option<String>,
+    pub nixos_configuration_id: Option<i64>,
+    /// Store path of the latest evaluation of the assigned configuration
+    pub desired_system: Option<String>,
+    pub current_system: Option<String>,
+    pub booted_system: Option<String>,
+    pub status: DriftStatus,
+}
+
+/// How far the reconciler may go to bring an agent to its desired system
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum DeployPolicy {
+    /// Never touch the agent, deployments are triggered by hand
+    Manual,
+    /// Copy the desired system to the agent, but don't activate it
+    #[default]
+    Download,
+    /// Copy and switch to the desired system
+    Switch,
+    /// Copy the desired system and make it the boot default
+    Boot,
+}
+
+impl DeployPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeployPolicy::Manual => "manual",
+            DeployPolicy::Download => "download",
+            DeployPolicy::Switch => "switch",
+            DeployPolicy::Boot => "boot",
+        }
+    }
+}
+
+impl Display for DeployPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown deploy policy {0:?}")]
+pub struct UnknownDeployPolicy(String);
+
+impl FromStr for DeployPolicy {
+    type Err = UnknownDeployPolicy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "manual" => Ok(DeployPolicy::Manual),
+            "download" => Ok(DeployPolicy::Download),
+            "switch" => Ok(DeployPolicy::Switch),
+            "boot" => Ok(DeployPolicy::Boot),
+            _ => Err(UnknownDeployPolicy(s.to_string())),
+        }
+    }
+}
+
+/// Something that happened on the server, streamed by `GET /api/v1/events`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Event {
+    AgentConnected {
+        agent_id: Uuid,
+    },
+    AgentDisconnected {
+        agent_id: Uuid,
+    },
+    /// A new revision of a flake was fetched, its evaluation follows
+    FlakeRevision {
+        flake_id: i64,
+        flake_revision_id: i64,
+        revision: String,
+    },
+    EvaluationStarted {
+        flake_id: i64,
+        flake_revision_id: i64,
+    },
+    EvaluationSucceeded {
+        flake_id: i64,
+        flake_revision_id: i64,
+    },
+    EvaluationFailed {
+        flake_id: i64,
+        flake_revision_id: i64,
+        error: String,
+    },
+    /// The reconciler or a deployment started or finished a deployment step of an agent
+    Deployment {
+        agent_id: Uuid,
+        store_path: String,
+        /// `download`, or the activation mode
+        action: String,
+        phase: DeploymentPhase,
+        error: Option<String>,
+        /// Deployment the step belongs to, `None` for steps of the reconciler
+        #[serde(default)]
+        deployment_id: Option<i64>,
+    },
+    /// All agents of a deployment finished
+    DeploymentFinished {
+        deployment_id: i64,
+        /// Number of agents deployed to
+        agents: usize,
+        /// Number of agents which failed
+        failed: usize,
+    },
+    /// An agent switched to a system that wasn't deployed by nxy
+    Drifted {
+        agent_id: Uuid,
+        current_system: String,
+    },
+}
+
+impl Event {
+    /// Name of the event, used as SSE event type
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::AgentConnected { .. } => "agent-connected",
+            Event::AgentDisconnected { .. } => "agent-disconnected",
+            Event::FlakeRevision { .. } => "flake-revision",
+            Event::EvaluationStarted { .. } => "evaluation-started",
+            Event::EvaluationSucceeded { .. } => "evaluation-succeeded",
+            Event::EvaluationFailed { .. } => "evaluation-failed",
+            Event::Deployment { .. } => "deployment",
+            Event::DeploymentFinished { .. } => "deployment-finished",
+            Event::Drifted { .. } => "drifted",
+        }
+    }
+
+    /// Agent the event is about, if any
+    pub fn agent_id(&self) -> Option<Uuid> {
+        match self {
+            Event::AgentConnected { agent_id }
+            | Event::AgentDisconnected { agent_id }
+            | Event::Deployment { agent_id, .. }
+            | Event::Drifted { agent_id, .. } => Some(*agent_id),
+            _ => None,
+        }
+    }
+
+    /// Deployment the event belongs to, if any
+    pub fn deployment_id(&self) -> Option<i64> {
+        match self {
+            Event::Deployment { deployment_id, .. } => *deployment_id,
+            Event::DeploymentFinished { deployment_id, .. } => Some(*deployment_id),
+            _ => None,
+        }
+    }
+
+    /// Flake the event is about, if any
+    pub fn flake_id(&self) -> Option<i64> {
+        match self {
+            Event::FlakeRevision { flake_id, .. }
+            | Event::EvaluationStarted { flake_id, .. }
+            | Event::EvaluationSucceeded { flake_id, .. }
+            | Event::EvaluationFailed { flake_id, .. } => Some(*flake_id),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum DeploymentPhase {
+    Started,
+    Succeeded,
+    Failed,
+}
+
+impl DeploymentPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeploymentPhase::Started => "started",
+            DeploymentPhase::Succeeded => "succeeded",
+            DeploymentPhase::Failed => "failed",
+        }
+    }
+}
+
+impl Display for DeploymentPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Restricts an event stream, events about other agents or flakes are skipped
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "utoipa", into_params(parameter_in = Query))]
+pub struct EventFilter {
+    /// Only events about this agent
+    pub agent_id: Option<Uuid>,
+    /// Only events about this flake
+    pub flake_id: Option<i64>,
+    /// Only events of this deployment
+    pub deployment_id: Option<i64>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        self.agent_id
+            .map_or(true, |id| event.agent_id() == Some(id))
+            && self
+                .flake_id
+                .map_or(true, |id| event.flake_id() == Some(id))
+            && self
+                .deployment_id
+                .map_or(true, |id| event.deployment_id() == Some(id))
+    }
+}
+
+/// A change made through the API or by the server on its own
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    /// `server` for automatic actions, the user or token of the caller otherwise
+    pub actor: String,
+    /// `<METHOD> <route>` for REST calls, eg. `reconcile/switch` for automatic actions
+    pub action: String,
+    /// Object the action changed, eg. `agent/<id>` or `flake/<id>`
+    pub target: Option<String>,
+    /// Request body of REST calls
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<Object>))]
+    pub parameters: Option<serde_json::Value>,
+    pub source_ip: Option<String>,
+    /// HTTP status of REST calls
+    pub status: Option<i32>,
+    /// `succeeded` or `failed`
+    pub outcome: String,
+    pub error: Option<String>,
+}
+
+/// Restricts the audit log, all set fields have to match
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "utoipa", into_params(parameter_in = Query))]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    /// Prefix of the action, eg. `POST /api/v1/agent`
+    pub action: Option<String>,
+    pub target: Option<String>,
+    /// `succeeded` or `failed`
+    pub outcome: Option<String>,
+    /// Only entries created at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only entries created before this time
+    pub until: Option<DateTime<Utc>>,
+    /// Maximum number of entries, newest first, defaults to 100
+    pub limit: Option<i64>,
+}
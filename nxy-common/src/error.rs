@@ -4,4 +4,6 @@ use thiserror::Error;
 pub enum Error {
     #[error(transparent)]
     SerdeJson(#[from] serde_json::Error),
+    #[error("invalid JSON-RPC message: {}", .0.data.as_ref().unwrap_or(&serde_json::Value::Null))]
+    InvalidMessage(crate::jsonrpc::ResponseError),
 }
@@ -1,8 +1,16 @@
-use std::{fmt::Display, str::FromStr};
+use std::{
+    fmt::{Debug, Display},
+    str::FromStr,
+    time::Duration,
+};
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Value of the `jsonrpc` member, messages with any other version are rejected.
+pub const VERSION: &str = "2.0";
+
+#[derive(Serialize, Clone, Debug)]
 #[serde(untagged)]
 pub enum JsonRPC {
     Request(Request),
@@ -28,13 +36,169 @@ impl From<Notification> for JsonRPC {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-#[serde(transparent)]
-pub struct RequestId(u64);
+/// A single message or a batch of messages
+#[derive(Clone, Debug)]
+pub enum Message<T = JsonRPC> {
+    Single(T),
+    Batch(Vec<T>),
+}
+
+impl<T> From<T> for Message<T> {
+    fn from(msg: T) -> Self {
+        Message::Single(msg)
+    }
+}
+
+impl<T> IntoIterator for Message<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        match self {
+            Message::Single(msg) => vec![msg].into_iter(),
+            Message::Batch(msgs) => msgs.into_iter(),
+        }
+    }
+}
+
+impl<T> Message<T> {
+    /// Applies `f` to every message and drops `None`s, keeping the batch structure.
+    ///
+    /// Returns `None` if no message is left, in which case nothing must be send to the peer.
+    pub fn filter_map<U>(self, f: impl FnMut(T) -> Option<U>) -> Option<Message<U>> {
+        match self {
+            Message::Single(msg) => [msg].into_iter().filter_map(f).next().map(Message::Single),
+            Message::Batch(msgs) => {
+                let msgs: Vec<U> = msgs.into_iter().filter_map(f).collect();
+                (!msgs.is_empty()).then_some(Message::Batch(msgs))
+            }
+        }
+    }
+}
+
+impl Message<Result<JsonRPC, Response>> {
+    /// Decode `text` into a single message or a batch.
+    ///
+    /// Messages that are not valid JSON-RPC 2.0 are returned as `Err` containing the error
+    /// response which has to be send back to the peer.
+    pub fn parse(text: &str) -> Self {
+        let value: Value = match serde_json::from_str(text) {
+            Ok(value) => value,
+            Err(err) => {
+                return Message::Single(Err(Response::invalid(
+                    ErrorCode::ParseError,
+                    err.to_string(),
+                )))
+            }
+        };
+
+        match value {
+            Value::Array(values) if values.is_empty() => Message::Single(Err(Response::invalid(
+                ErrorCode::InvalidRequest,
+                "empty batch",
+            ))),
+            Value::Array(values) => Message::Batch(values.into_iter().map(decode).collect()),
+            value => Message::Single(decode(value)),
+        }
+    }
+}
+
+impl<T: Clone + Into<JsonRPC>> Display for Message<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Message::Single(msg) => serde_json::to_string(&Versioned::new(msg.clone().into())),
+            Message::Batch(msgs) => serde_json::to_string(
+                &msgs
+                    .iter()
+                    .map(|msg| Versioned::new(msg.clone().into()))
+                    .collect::<Vec<_>>(),
+            ),
+        }
+        .unwrap();
+        write!(f, "{text}")
+    }
+}
+
+/// [`JsonRPC`] message including the `jsonrpc` member
+#[derive(Serialize)]
+struct Versioned {
+    jsonrpc: &'static str,
+    #[serde(flatten)]
+    msg: JsonRPC,
+}
+
+impl Versioned {
+    fn new(msg: JsonRPC) -> Self {
+        Self {
+            jsonrpc: VERSION,
+            msg,
+        }
+    }
+}
+
+/// Validate and decode a single message.
+///
+/// Returns the error response to send back to the peer if `value` is not a valid message.
+fn decode(value: Value) -> Result<JsonRPC, Response> {
+    let invalid = |data: &str| Response::invalid(ErrorCode::InvalidRequest, data);
+
+    let Value::Object(ref obj) = value else {
+        return Err(invalid("message is not an object"));
+    };
+    if obj.get("jsonrpc").and_then(Value::as_str) != Some(VERSION) {
+        return Err(invalid("unsupported or missing jsonrpc version"));
+    }
+    if let Some(id) = obj.get("id") {
+        if !matches!(id, Value::Null | Value::Number(_) | Value::String(_)) {
+            return Err(invalid("id must be a string, number or null"));
+        }
+    }
+
+    let msg = if obj.contains_key("method") {
+        if !obj["method"].is_string() {
+            return Err(invalid("method must be a string"));
+        }
+        if !matches!(
+            obj.get("params"),
+            None | Some(Value::Array(_) | Value::Object(_))
+        ) {
+            return Err(invalid("params must be an array or object"));
+        }
+
+        if obj.contains_key("id") {
+            serde_json::from_value(value).map(JsonRPC::Request)
+        } else {
+            serde_json::from_value(value).map(JsonRPC::Notification)
+        }
+    } else {
+        if obj.contains_key("result") == obj.contains_key("error") {
+            return Err(invalid("response must contain either result or error"));
+        }
+        if !obj.contains_key("id") {
+            return Err(invalid("response is missing id"));
+        }
+        serde_json::from_value(value).map(JsonRPC::Response)
+    };
+
+    msg.map_err(|err| invalid(&err.to_string()))
+}
+
+/// Request ids are either numbers, strings or `null`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(untagged)]
+pub enum RequestId {
+    Number(i64),
+    String(String),
+    Null,
+}
 
 impl Display for RequestId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        match self {
+            RequestId::Number(id) => write!(f, "{id}"),
+            RequestId::String(id) => write!(f, "{id:?}"),
+            RequestId::Null => write!(f, "null"),
+        }
     }
 }
 
@@ -76,6 +240,23 @@ pub enum ErrorCode {
     InternalError = -32603,
     ServerErrorStart = -32099,
     ServerErrorEnd = -32000,
+    // Defined by nxy, same as LSP:
+    RequestCancelled = -32800,
+}
+
+impl ErrorCode {
+    /// Message used by the JSON-RPC specification for this error
+    pub fn message(&self) -> &'static str {
+        match self {
+            ErrorCode::ParseError => "Parse error",
+            ErrorCode::InvalidRequest => "Invalid Request",
+            ErrorCode::MethodNotFound => "Method not found",
+            ErrorCode::InvalidParams => "Invalid params",
+            ErrorCode::InternalError => "Internal error",
+            ErrorCode::ServerErrorStart | ErrorCode::ServerErrorEnd => "Server error",
+            ErrorCode::RequestCancelled => "Request cancelled",
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -86,33 +267,31 @@ pub struct Notification {
     pub params: serde_json::Value,
 }
 
-impl From<u64> for RequestId {
-    fn from(v: u64) -> Self {
-        RequestId(v)
+impl From<i64> for RequestId {
+    fn from(v: i64) -> Self {
+        RequestId::Number(v)
+    }
+}
+
+impl From<String> for RequestId {
+    fn from(v: String) -> Self {
+        RequestId::String(v)
     }
 }
 
 impl FromStr for JsonRPC {
     type Err = crate::error::Error;
 
+    /// Decode a single message, batches are rejected. See [`Message::parse`].
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        serde_json::from_str(s).map_err(Into::into)
+        decode(serde_json::from_str(s)?)
+            .map_err(|res| crate::error::Error::InvalidMessage(res.error.expect("error response")))
     }
 }
 
 impl Display for JsonRPC {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        #[derive(Serialize)]
-        struct JsonRpc {
-            jsonrpc: &'static str,
-            #[serde(flatten)]
-            msg: JsonRPC,
-        }
-        let text = serde_json::to_string(&JsonRpc {
-            jsonrpc: "2.0",
-            msg: self.clone(),
-        })
-        .unwrap();
+        let text = serde_json::to_string(&Versioned::new(self.clone())).unwrap();
         write!(f, "{text}")
     }
 }
@@ -136,6 +315,26 @@ impl Response {
             error: None,
         }
     }
+    /// Error response with the message defined by the JSON-RPC specification for `code`, `data`
+    /// contains the details.
+    pub fn new_spec_err(id: RequestId, code: ErrorCode, data: impl Display) -> Response {
+        let error = ResponseError {
+            code: code as i32,
+            message: code.message().to_string(),
+            data: Some(Value::String(data.to_string())),
+        };
+        Response {
+            id,
+            result: None,
+            error: Some(error),
+        }
+    }
+    /// Error response for a message that couldn't be decoded.
+    ///
+    /// As the request id can't be determined for invalid messages, `id` is always `null`.
+    pub fn invalid(code: ErrorCode, data: impl Display) -> Response {
+        Response::new_spec_err(RequestId::Null, code, data)
+    }
     pub fn new_err(id: RequestId, code: i32, message: String) -> Response {
         let error = ResponseError {
             code,
@@ -157,4 +356,489 @@ impl Notification {
             params: serde_json::to_value(params).unwrap(),
         }
     }
+
+    /// Create a notification for method `N`
+    pub fn typed<N: NotificationMethod>(params: N::Params) -> Notification {
+        Notification::new(N::NAME.to_string(), params)
+    }
+
+    /// Decode the params of this notification if it is a `N` notification
+    pub fn extract<N: NotificationMethod>(&self) -> Option<Result<N::Params, serde_json::Error>> {
+        (self.method == N::NAME).then(|| serde_json::from_value(self.params.clone()))
+    }
+}
+
+/// A JSON-RPC method, declared once and shared by caller and callee.
+pub trait Method {
+    /// Method name as send over the wire, eg. `$/ping`
+    const NAME: &'static str;
+
+    type Params: Serialize + DeserializeOwned;
+    type Result: Serialize + DeserializeOwned;
+    /// Type of [`ResponseError::data`] returned by failed calls.
+    type Error: Serialize + DeserializeOwned + Debug + Send + Sync + 'static;
+
+    /// How long the caller waits for a response before giving up and cancelling the request
+    const TIMEOUT: Duration = Duration::from_secs(30);
+}
+
+/// A JSON-RPC notification, declared once and shared by sender and receiver.
+pub trait NotificationMethod {
+    /// Method name as send over the wire, eg. `$/cancelRequest`
+    const NAME: &'static str;
+
+    type Params: Serialize + DeserializeOwned;
+}
+
+/// Typed counterpart of [`ResponseError`] for method `M`.
+#[derive(Debug, thiserror::Error)]
+#[error("{message} (code: {code})")]
+pub struct MethodError<E> {
+    pub code: i32,
+    pub message: String,
+    pub data: Option<E>,
+}
+
+impl<E> MethodError<E> {
+    pub fn new(code: ErrorCode, message: impl Display) -> Self {
+        Self {
+            code: code as i32,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+
+    /// Shorthand for an [`ErrorCode::InternalError`] without data
+    pub fn internal(message: impl Display) -> Self {
+        Self::new(ErrorCode::InternalError, message)
+    }
+}
+
+impl<E: Serialize> From<MethodError<E>> for ResponseError {
+    fn from(error: MethodError<E>) -> Self {
+        ResponseError {
+            code: error.code,
+            message: error.message,
+            data: error.data.map(|data| serde_json::to_value(data).unwrap()),
+        }
+    }
+}
+
+impl<E: DeserializeOwned> From<ResponseError> for MethodError<E> {
+    fn from(error: ResponseError) -> Self {
+        MethodError {
+            code: error.code,
+            message: error.message,
+            // error data we don't understand is dropped instead of failing the whole response
+            data: error
+                .data
+                .and_then(|data| serde_json::from_value(data).ok()),
+        }
+    }
+}
+
+impl Request {
+    /// Create a request for method `M`
+    pub fn typed<M: Method>(id: RequestId, params: M::Params) -> Request {
+        Request::new(id, M::NAME, params)
+    }
+}
+
+impl Response {
+    /// Interpret this response as the response to a call of method `M`
+    pub fn into_result<M: Method>(self) -> Result<M::Result, MethodError<M::Error>> {
+        if let Some(error) = self.error {
+            return Err(error.into());
+        }
+        let result = self.result.unwrap_or_default();
+        serde_json::from_value(result)
+            .map_err(|err| MethodError::internal(format!("invalid result for {}: {err}", M::NAME)))
+    }
+}
+
+/// Routes a [`Request`] to the handler of its method.
+///
+/// ```rust,ignore
+/// let response = Dispatcher::new(request)
+///     .on::<methods::Ping>(|()| Ok("pong".to_string()))
+///     .finish();
+/// ```
+///
+/// The default dispatcher has no request, it only collects the [`Dispatcher::methods`] handled.
+#[derive(Debug, Default)]
+pub struct Dispatcher {
+    request: Option<Request>,
+    response: Option<Response>,
+    methods: Vec<&'static str>,
+}
+
+impl Dispatcher {
+    pub fn new(request: Request) -> Self {
+        Self {
+            request: Some(request),
+            ..Default::default()
+        }
+    }
+
+    /// Handle the request with `handler` if it is a call of method `M`
+    pub fn on<M: Method>(
+        mut self,
+        handler: impl FnOnce(M::Params) -> Result<M::Result, MethodError<M::Error>>,
+    ) -> Self {
+        self.methods.push(M::NAME);
+        let request = match self.request.take() {
+            Some(request) if request.method == M::NAME => request,
+            request => {
+                self.request = request;
+                return self;
+            }
+        };
+
+        let response = match serde_json::from_value(request.params) {
+            Ok(params) => match handler(params) {
+                Ok(result) => Response::new_ok(request.id, result),
+                Err(error) => Response {
+                    id: request.id,
+                    result: None,
+                    error: Some(error.into()),
+                },
+            },
+            Err(err) => Response::new_spec_err(request.id, ErrorCode::InvalidParams, err),
+        };
+        self.response = Some(response);
+        self
+    }
+
+    /// Names of all methods with a handler so far
+    pub fn methods(&self) -> &[&'static str] {
+        &self.methods
+    }
+
+    /// Returns the handlers response or [`ErrorCode::MethodNotFound`] if no handler matched.
+    ///
+    /// # Panics
+    ///
+    /// If the dispatcher was created without a request.
+    pub fn finish(self) -> Response {
+        match (self.response, self.request) {
+            (Some(response), _) => response,
+            (None, Some(request)) => {
+                Response::new_spec_err(request.id, ErrorCode::MethodNotFound, request.method)
+            }
+            (None, None) => panic!("no request to respond to"),
+        }
+    }
+}
+
+/// Examples from the JSON-RPC 2.0 specification, see <https://www.jsonrpc.org/specification#examples>
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    enum Subtract {}
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum SubtractParams {
+        Positional(i64, i64),
+        Named { minuend: i64, subtrahend: i64 },
+    }
+
+    impl Method for Subtract {
+        const NAME: &'static str = "subtract";
+        type Params = SubtractParams;
+        type Result = i64;
+        type Error = ();
+    }
+
+    enum Sum {}
+
+    impl Method for Sum {
+        const NAME: &'static str = "sum";
+        type Params = Vec<i64>;
+        type Result = i64;
+        type Error = ();
+    }
+
+    enum GetData {}
+
+    impl Method for GetData {
+        const NAME: &'static str = "get_data";
+        type Params = ();
+        type Result = Value;
+        type Error = ();
+    }
+
+    /// Answers `request` like the server used in the specification examples
+    fn handle(request: &str) -> Option<Value> {
+        let responses = Message::parse(request).filter_map(|msg| match msg {
+            Ok(JsonRPC::Request(request)) => Some(
+                Dispatcher::new(request)
+                    .on::<Subtract>(|params| match params {
+                        SubtractParams::Positional(minuend, subtrahend)
+                        | SubtractParams::Named {
+                            minuend,
+                            subtrahend,
+                        } => Ok(minuend - subtrahend),
+                    })
+                    .on::<Sum>(|params| Ok(params.iter().sum()))
+                    .on::<GetData>(|()| Ok(json!(["hello", 5])))
+                    .finish(),
+            ),
+            Ok(_) => None,
+            Err(response) => Some(response),
+        })?;
+
+        let mut value: Value = serde_json::from_str(&responses.to_string()).unwrap();
+        // the examples don't include error data
+        let responses = match value {
+            Value::Array(ref mut responses) => responses.iter_mut().collect(),
+            ref mut response => vec![response],
+        };
+        for response in responses {
+            if let Some(error) = response.get_mut("error") {
+                error.as_object_mut().unwrap().remove("data");
+            }
+        }
+        Some(value)
+    }
+
+    #[test]
+    fn positional_parameters() {
+        assert_eq!(
+            handle(r#"{"jsonrpc": "2.0", "method": "subtract", "params": [42, 23], "id": 1}"#),
+            Some(json!({"jsonrpc": "2.0", "result": 19, "id": 1}))
+        );
+        assert_eq!(
+            handle(r#"{"jsonrpc": "2.0", "method": "subtract", "params": [23, 42], "id": 2}"#),
+            Some(json!({"jsonrpc": "2.0", "result": -19, "id": 2}))
+        );
+    }
+
+    #[test]
+    fn named_parameters() {
+        assert_eq!(
+            handle(
+                r#"{"jsonrpc": "2.0", "method": "subtract", "params": {"subtrahend": 23, "minuend": 42}, "id": 3}"#
+            ),
+            Some(json!({"jsonrpc": "2.0", "result": 19, "id": 3}))
+        );
+        assert_eq!(
+            handle(
+                r#"{"jsonrpc": "2.0", "method": "subtract", "params": {"minuend": 42, "subtrahend": 23}, "id": 4}"#
+            ),
+            Some(json!({"jsonrpc": "2.0", "result": 19, "id": 4}))
+        );
+    }
+
+    #[test]
+    fn notification() {
+        assert_eq!(
+            handle(r#"{"jsonrpc": "2.0", "method": "update", "params": [1,2,3,4,5]}"#),
+            None
+        );
+        assert_eq!(handle(r#"{"jsonrpc": "2.0", "method": "foobar"}"#), None);
+    }
+
+    #[test]
+    fn non_existent_method() {
+        assert_eq!(
+            handle(r#"{"jsonrpc": "2.0", "method": "foobar", "id": "1"}"#),
+            Some(json!({
+                "jsonrpc": "2.0",
+                "error": {"code": -32601, "message": "Method not found"},
+                "id": "1"
+            }))
+        );
+    }
+
+    #[test]
+    fn invalid_json() {
+        assert_eq!(
+            handle(r#"{"jsonrpc": "2.0", "method": "foobar, "params": "bar", "baz]"#),
+            Some(json!({
+                "jsonrpc": "2.0",
+                "error": {"code": -32700, "message": "Parse error"},
+                "id": null
+            }))
+        );
+    }
+
+    #[test]
+    fn invalid_request_object() {
+        assert_eq!(
+            handle(r#"{"jsonrpc": "2.0", "method": 1, "params": "bar"}"#),
+            Some(json!({
+                "jsonrpc": "2.0",
+                "error": {"code": -32600, "message": "Invalid Request"},
+                "id": null
+            }))
+        );
+    }
+
+    #[test]
+    fn batch_invalid_json() {
+        assert_eq!(
+            handle(
+                r#"[
+                  {"jsonrpc": "2.0", "method": "sum", "params": [1,2,4], "id": "1"},
+                  {"jsonrpc": "2.0", "method"
+                ]"#
+            ),
+            Some(json!({
+                "jsonrpc": "2.0",
+                "error": {"code": -32700, "message": "Parse error"},
+                "id": null
+            }))
+        );
+    }
+
+    #[test]
+    fn empty_batch() {
+        assert_eq!(
+            handle("[]"),
+            Some(json!({
+                "jsonrpc": "2.0",
+                "error": {"code": -32600, "message": "Invalid Request"},
+                "id": null
+            }))
+        );
+    }
+
+    #[test]
+    fn invalid_batch() {
+        let invalid = json!({
+            "jsonrpc": "2.0",
+            "error": {"code": -32600, "message": "Invalid Request"},
+            "id": null
+        });
+        assert_eq!(handle("[1]"), Some(json!([invalid])));
+        assert_eq!(handle("[1,2,3]"), Some(json!([invalid, invalid, invalid])));
+    }
+
+    #[test]
+    fn batch() {
+        assert_eq!(
+            handle(
+                r#"[
+                    {"jsonrpc": "2.0", "method": "sum", "params": [1,2,4], "id": "1"},
+                    {"jsonrpc": "2.0", "method": "notify_hello", "params": [7]},
+                    {"jsonrpc": "2.0", "method": "subtract", "params": [42,23], "id": "2"},
+                    {"foo": "boo"},
+                    {"jsonrpc": "2.0", "method": "foo.get", "params": {"name": "myself"}, "id": "5"},
+                    {"jsonrpc": "2.0", "method": "get_data", "id": "9"}
+                ]"#
+            ),
+            Some(json!([
+                {"jsonrpc": "2.0", "result": 7, "id": "1"},
+                {"jsonrpc": "2.0", "result": 19, "id": "2"},
+                {"jsonrpc": "2.0", "error": {"code": -32600, "message": "Invalid Request"}, "id": null},
+                {"jsonrpc": "2.0", "error": {"code": -32601, "message": "Method not found"}, "id": "5"},
+                {"jsonrpc": "2.0", "result": ["hello", 5], "id": "9"}
+            ]))
+        );
+    }
+
+    #[test]
+    fn batch_all_notifications() {
+        assert_eq!(
+            handle(
+                r#"[
+                    {"jsonrpc": "2.0", "method": "notify_sum", "params": [1,2,4]},
+                    {"jsonrpc": "2.0", "method": "notify_hello", "params": [7]}
+                ]"#
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn version_is_checked() {
+        assert!(r#"{"method": "foobar", "id": 1}"#.parse::<JsonRPC>().is_err());
+        assert!(r#"{"jsonrpc": "1.0", "method": "foobar", "id": 1}"#
+            .parse::<JsonRPC>()
+            .is_err());
+        assert!(r#"{"jsonrpc": "2.0", "method": "foobar", "id": 1}"#.parse::<JsonRPC>().is_ok());
+    }
+
+    #[test]
+    fn response_ids() {
+        for (id, expected) in [
+            (json!(1), RequestId::Number(1)),
+            (json!("abc"), RequestId::String("abc".to_string())),
+            (json!(null), RequestId::Null),
+        ] {
+            let msg = json!({"jsonrpc": "2.0", "result": 19, "id": id}).to_string();
+            let Ok(JsonRPC::Response(response)) = msg.parse() else {
+                panic!("{msg} is not a response");
+            };
+            assert_eq!(response.id, expected);
+        }
+    }
+
+    enum Divide {}
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct DivisionByZero {
+        dividend: i64,
+    }
+
+    impl Method for Divide {
+        const NAME: &'static str = "divide";
+        type Params = (i64, i64);
+        type Result = i64;
+        type Error = DivisionByZero;
+    }
+
+    fn dispatch(request: Request) -> Response {
+        Dispatcher::new(request)
+            .on::<Sum>(|params| Ok(params.iter().sum()))
+            .on::<Divide>(|(dividend, divisor)| match divisor {
+                0 => Err(MethodError {
+                    code: ErrorCode::ServerErrorStart as i32,
+                    message: "division by zero".to_string(),
+                    data: Some(DivisionByZero { dividend }),
+                }),
+                _ => Ok(dividend / divisor),
+            })
+            .finish()
+    }
+
+    #[test]
+    fn dispatch_unknown_method() {
+        let response = dispatch(Request::new(RequestId::Number(1), "multiply", (6, 7)));
+        let error = response.error.unwrap();
+        assert_eq!(error.code, ErrorCode::MethodNotFound as i32);
+        assert_eq!(error.data, Some(json!("multiply")));
+    }
+
+    #[test]
+    fn dispatch_invalid_params() {
+        let response = dispatch(Request::new(RequestId::Number(1), "divide", ["six", "two"]));
+        assert_eq!(
+            response.error.unwrap().code,
+            ErrorCode::InvalidParams as i32
+        );
+    }
+
+    #[test]
+    fn dispatch_typed_error() {
+        let response = dispatch(Request::typed::<Divide>(RequestId::Number(1), (42, 6)));
+        assert_eq!(response.into_result::<Divide>().unwrap(), 7);
+
+        let response = dispatch(Request::typed::<Divide>(RequestId::Number(2), (42, 0)));
+        let error = response.into_result::<Divide>().unwrap_err();
+        assert_eq!(error.code, ErrorCode::ServerErrorStart as i32);
+        assert_eq!(error.data, Some(DivisionByZero { dividend: 42 }));
+    }
+
+    #[test]
+    fn dispatch_collects_methods() {
+        let dispatcher = Dispatcher::default()
+            .on::<Sum>(|params| Ok(params.iter().sum()))
+            .on::<Divide>(|(dividend, divisor)| Ok(dividend / divisor));
+        assert_eq!(dispatcher.methods(), ["sum", "divide"]);
+    }
 }
@@ -0,0 +1,300 @@
+//! Key/value labels of agents and the selectors matching them.
+//!
+//! Selectors are comma separated requirements, all of which have to hold:
+//!
+//! - `key=value`, `key==value`: the label is set to `value`
+//! - `key!=value`: the label isn't set or set to something else
+//! - `key in (a,b)`, `key notin (a,b)`: the label is, or isn't, one of the values
+//! - `key`, `!key`: the label is, or isn't, set
+//!
+//! eg. `env=prod,role in (web,api)`
+
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Labels of a single agent
+pub type Labels = BTreeMap<String, String>;
+
+/// Longest allowed label key or value
+const MAX_LENGTH: usize = 63;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum LabelError {
+    #[error("invalid label key {0:?}, allowed are up to 63 alphanumerics, '-', '_', '.' and '/'")]
+    Key(String),
+    #[error(
+        "invalid value {0:?} of label {1:?}, allowed are up to 63 alphanumerics, '-', '_' and '.'"
+    )]
+    Value(String, String),
+}
+
+/// Check that `key` can be used as label key
+///
+/// Keys start and end with an alphanumeric character, `/` allows prefixes like `nxy.dev/role`.
+pub fn validate_key(key: &str) -> Result<(), LabelError> {
+    let valid = !key.is_empty()
+        && key.len() <= MAX_LENGTH
+        && key.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && key.ends_with(|c: char| c.is_ascii_alphanumeric())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+    if valid {
+        Ok(())
+    } else {
+        Err(LabelError::Key(key.to_string()))
+    }
+}
+
+/// Check that `value` can be used as value of the label `key`, values may be empty
+pub fn validate_value(key: &str, value: &str) -> Result<(), LabelError> {
+    let valid = value.len() <= MAX_LENGTH
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(LabelError::Value(value.to_string(), key.to_string()))
+    }
+}
+
+/// Selects agents by their labels, see the [module docs](self) for the syntax
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector(Vec<Requirement>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    In(String, Vec<String>),
+    NotIn(String, Vec<String>),
+    Exists(String),
+    NotExists(String),
+}
+
+impl Selector {
+    pub fn requirements(&self) -> &[Requirement] {
+        &self.0
+    }
+
+    pub fn matches(&self, labels: &Labels) -> bool {
+        self.0.iter().all(|requirement| requirement.matches(labels))
+    }
+}
+
+impl Requirement {
+    pub fn matches(&self, labels: &Labels) -> bool {
+        match self {
+            Requirement::Equals(key, value) => labels.get(key) == Some(value),
+            Requirement::NotEquals(key, value) => labels.get(key) != Some(value),
+            Requirement::In(key, values) => labels.get(key).map_or(false, |v| values.contains(v)),
+            Requirement::NotIn(key, values) => {
+                labels.get(key).map_or(true, |v| !values.contains(v))
+            }
+            Requirement::Exists(key) => labels.contains_key(key),
+            Requirement::NotExists(key) => !labels.contains_key(key),
+        }
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, requirement) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{requirement}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Requirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Requirement::Equals(key, value) => write!(f, "{key}={value}"),
+            Requirement::NotEquals(key, value) => write!(f, "{key}!={value}"),
+            Requirement::In(key, values) => write!(f, "{key} in ({})", values.join(",")),
+            Requirement::NotIn(key, values) => write!(f, "{key} notin ({})", values.join(",")),
+            Requirement::Exists(key) => f.write_str(key),
+            Requirement::NotExists(key) => write!(f, "!{key}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SelectorError {
+    #[error("empty selector")]
+    Empty,
+    #[error("invalid requirement {0:?}")]
+    Requirement(String),
+    #[error("unbalanced parentheses in selector")]
+    Parentheses,
+    #[error(transparent)]
+    Label(#[from] LabelError),
+}
+
+impl FromStr for Selector {
+    type Err = SelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Err(SelectorError::Empty);
+        }
+        split_requirements(s)?
+            .into_iter()
+            .map(parse_requirement)
+            .collect::<Result<_, _>>()
+            .map(Selector)
+    }
+}
+
+/// Split `s` at commas outside of parentheses
+fn split_requirements(s: &str) -> Result<Vec<&str>, SelectorError> {
+    let mut requirements = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' if depth == 0 => depth += 1,
+            ')' if depth == 1 => depth -= 1,
+            '(' | ')' => return Err(SelectorError::Parentheses),
+            ',' if depth == 0 => {
+                requirements.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(SelectorError::Parentheses);
+    }
+    requirements.push(&s[start..]);
+    Ok(requirements)
+}
+
+fn parse_requirement(s: &str) -> Result<Requirement, SelectorError> {
+    let s = s.trim();
+    let invalid = || SelectorError::Requirement(s.to_string());
+
+    let requirement = if let Some((key, value)) = s.split_once("!=") {
+        Requirement::NotEquals(key.trim().to_string(), value.trim().to_string())
+    } else if let Some((key, value)) = s.split_once("==").or_else(|| s.split_once('=')) {
+        Requirement::Equals(key.trim().to_string(), value.trim().to_string())
+    } else if let Some((key, values)) = s.split_once('(') {
+        let values = values.strip_suffix(')').ok_or_else(invalid)?;
+        let values: Vec<String> = values.split(',').map(|v| v.trim().to_string()).collect();
+        match key.split_whitespace().collect::<Vec<_>>()[..] {
+            [key, "in"] => Requirement::In(key.to_string(), values),
+            [key, "notin"] => Requirement::NotIn(key.to_string(), values),
+            _ => return Err(invalid()),
+        }
+    } else if let Some(key) = s.strip_prefix('!') {
+        Requirement::NotExists(key.trim().to_string())
+    } else {
+        Requirement::Exists(s.to_string())
+    };
+
+    match requirement {
+        Requirement::Equals(ref key, ref value) | Requirement::NotEquals(ref key, ref value) => {
+            validate_key(key)?;
+            validate_value(key, value)?;
+        }
+        Requirement::In(ref key, ref values) | Requirement::NotIn(ref key, ref values) => {
+            validate_key(key)?;
+            for value in values {
+                validate_value(key, value)?;
+            }
+        }
+        Requirement::Exists(ref key) | Requirement::NotExists(ref key) => validate_key(key)?,
+    }
+    Ok(requirement)
+}
+
+impl Serialize for Selector {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Selector {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parse() {
+        let selector: Selector = "env=prod, role in (web, api),!legacy,tier!=db,gpu,zone notin (a)"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            selector.requirements(),
+            &[
+                Requirement::Equals("env".into(), "prod".into()),
+                Requirement::In("role".into(), vec!["web".into(), "api".into()]),
+                Requirement::NotExists("legacy".into()),
+                Requirement::NotEquals("tier".into(), "db".into()),
+                Requirement::Exists("gpu".into()),
+                Requirement::NotIn("zone".into(), vec!["a".into()]),
+            ]
+        );
+        assert_eq!(
+            selector.to_string(),
+            "env=prod,role in (web,api),!legacy,tier!=db,gpu,zone notin (a)"
+        );
+        assert_eq!(
+            "env==prod".parse::<Selector>().unwrap().to_string(),
+            "env=prod"
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!("".parse::<Selector>(), Err(SelectorError::Empty));
+        assert_eq!(
+            "role in (web".parse::<Selector>(),
+            Err(SelectorError::Parentheses)
+        );
+        assert!(matches!(
+            "role within (web)".parse::<Selector>(),
+            Err(SelectorError::Requirement(_))
+        ));
+        assert!(matches!(
+            "env=prod,".parse::<Selector>(),
+            Err(SelectorError::Label(LabelError::Key(_)))
+        ));
+        assert!(matches!(
+            "env=pr od".parse::<Selector>(),
+            Err(SelectorError::Label(LabelError::Value(..)))
+        ));
+    }
+
+    #[test]
+    fn matches() {
+        let selector: Selector = "env=prod,role in (web,api),!legacy".parse().unwrap();
+        assert!(selector.matches(&labels(&[("env", "prod"), ("role", "web")])));
+        assert!(!selector.matches(&labels(&[("env", "prod"), ("role", "db")])));
+        assert!(!selector.matches(&labels(&[("env", "prod")])));
+        assert!(!selector.matches(&labels(&[("env", "prod"), ("role", "api"), ("legacy", "")])));
+
+        let selector: Selector = "tier!=db,zone notin (a,b)".parse().unwrap();
+        assert!(selector.matches(&labels(&[])));
+        assert!(selector.matches(&labels(&[("tier", "web"), ("zone", "c")])));
+        assert!(!selector.matches(&labels(&[("zone", "a")])));
+    }
+}
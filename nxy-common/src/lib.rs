@@ -1,5 +1,8 @@
+pub mod api;
 pub mod error;
 pub mod jsonrpc;
+pub mod labels;
+pub mod methods;
 pub mod types;
 
 pub use jsonrpc::*;
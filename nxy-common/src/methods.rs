@@ -0,0 +1,149 @@
+//! RPC methods implemented by nxy-agent.
+
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    types::{
+        self, ActivateParams, CancelParams, DownloadParams, InitializeParams, InitializeResult,
+        RebootParams, RollbackParams,
+    },
+    Method, NotificationMethod,
+};
+
+/// First request send by the server on a new connection, used to negotiate protocol version and
+/// capabilities
+#[derive(Debug)]
+pub enum Initialize {}
+
+impl Method for Initialize {
+    const NAME: &'static str = "$/initialize";
+    type Params = InitializeParams;
+    type Result = InitializeResult;
+    type Error = ();
+}
+
+/// Liveness check, answered with `"pong"`
+#[derive(Debug)]
+pub enum Ping {}
+
+impl Method for Ping {
+    const NAME: &'static str = "$/ping";
+    type Params = ();
+    type Result = String;
+    type Error = ();
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+}
+
+/// Query the agent id and its current system
+#[derive(Debug)]
+pub enum Status {}
+
+impl Method for Status {
+    const NAME: &'static str = "$/status";
+    type Params = ();
+    type Result = types::Status;
+    type Error = ();
+}
+
+/// Query an inventory of the agent's host
+#[derive(Debug)]
+pub enum Facts {}
+
+impl Method for Facts {
+    const NAME: &'static str = "$/facts";
+    type Params = ();
+    type Result = types::Facts;
+    type Error = ();
+}
+
+/// Query failed units and the units touched by the last activation
+#[derive(Debug)]
+pub enum Units {}
+
+impl Method for Units {
+    const NAME: &'static str = "$/units";
+    type Params = ();
+    type Result = types::Units;
+    type Error = ();
+}
+
+/// Copy a store path to the agent
+#[derive(Debug)]
+pub enum Download {}
+
+impl Method for Download {
+    const NAME: &'static str = "$/download";
+    type Params = DownloadParams;
+    type Result = ();
+    type Error = ();
+
+    const TIMEOUT: Duration = Duration::from_secs(60 * 60);
+}
+
+/// Switch the agent to a system configuration
+#[derive(Debug)]
+pub enum Activate {}
+
+impl Method for Activate {
+    const NAME: &'static str = "$/activate";
+    type Params = ActivateParams;
+    type Result = ();
+    type Error = ();
+
+    const TIMEOUT: Duration = Duration::from_secs(15 * 60);
+}
+
+/// Switch the agent back to the previous generation of its system profile, returns the store
+/// path of the activated generation
+#[derive(Debug)]
+pub enum Rollback {}
+
+impl Method for Rollback {
+    const NAME: &'static str = "$/rollback";
+    type Params = RollbackParams;
+    type Result = PathBuf;
+    type Error = ();
+
+    const TIMEOUT: Duration = Duration::from_secs(15 * 60);
+}
+
+/// Reboot the agent's host, either immediately or scheduled
+#[derive(Debug)]
+pub enum Reboot {}
+
+impl Method for Reboot {
+    const NAME: &'static str = "$/reboot";
+    type Params = RebootParams;
+    type Result = ();
+    type Error = ();
+}
+
+/// Ask the agent to abort a running request, eg. because the caller stopped waiting for it.
+///
+/// The cancelled request is answered with [`crate::ErrorCode::RequestCancelled`].
+#[derive(Debug)]
+pub enum CancelRequest {}
+
+impl NotificationMethod for CancelRequest {
+    const NAME: &'static str = "$/cancelRequest";
+    type Params = CancelParams;
+}
+
+/// Send by the agent after every activation and whenever the set of failed units changes
+#[derive(Debug)]
+pub enum UnitsChanged {}
+
+impl NotificationMethod for UnitsChanged {
+    const NAME: &'static str = "$/unitsChanged";
+    type Params = types::Units;
+}
+
+/// Send by the agent when `/run/current-system` changed, eg. by a manual `nixos-rebuild switch`
+#[derive(Debug)]
+pub enum SystemChanged {}
+
+impl NotificationMethod for SystemChanged {
+    const NAME: &'static str = "$/systemChanged";
+    type Params = types::System;
+}
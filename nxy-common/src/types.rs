@@ -1,13 +1,50 @@
-use std::path::PathBuf;
+use std::{fmt::Display, net::IpAddr, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::{labels::Labels, RequestId};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Status {
     pub id: Uuid,
     pub system: System,
     pub version: String,
+    /// Url under which this agent exposes its nix store to peers as a read-only substituter.
+    #[serde(default)]
+    pub substituter: Option<String>,
+    /// Components that differ between the booted and the current system, empty if no reboot is
+    /// required
+    #[serde(default)]
+    pub reboot_required: Vec<RebootReason>,
+}
+
+/// Component of a system that only takes effect after a reboot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum RebootReason {
+    Kernel,
+    Initrd,
+    KernelModules,
+    Systemd,
+}
+
+impl RebootReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RebootReason::Kernel => "kernel",
+            RebootReason::Initrd => "initrd",
+            RebootReason::KernelModules => "kernel-modules",
+            RebootReason::Systemd => "systemd",
+        }
+    }
+}
+
+impl Display for RebootReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,10 +56,236 @@ pub struct System {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DownloadParams {
     pub store_path: PathBuf,
-    pub from: String,
+    /// Substituters to copy `store_path` from, tried in order until one succeeds.
+    pub from: Substituters,
+}
+
+/// A single substituter, as understood by all agents, or several for agents announcing
+/// [`Capabilities::multi_source_download`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Substituters {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Substituters {
+    pub fn as_slice(&self) -> &[String] {
+        match self {
+            Substituters::One(substituter) => std::slice::from_ref(substituter),
+            Substituters::Many(substituters) => substituters,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActivateParams {
     pub store_path: PathBuf,
+    #[serde(default)]
+    pub mode: ActivationMode,
+}
+
+/// Version of the protocol spoken between nxy-server and nxy-agent.
+///
+/// Bumped whenever a change requires both sides to know about each other, additions that can be
+/// detected via [`Capabilities`] don't require a bump.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InitializeParams {
+    pub protocol_version: u32,
+    pub capabilities: Capabilities,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InitializeResult {
+    pub protocol_version: u32,
+    pub capabilities: Capabilities,
+}
+
+/// Features supported by one side of the connection
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Request and notification methods handled
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// Modes supported by `$/activate`
+    #[serde(default)]
+    pub activation_modes: Vec<ActivationMode>,
+    /// `$/download` accepts several substituters, otherwise only the first one is sent
+    #[serde(default)]
+    pub multi_source_download: bool,
+}
+
+impl Capabilities {
+    /// Returns `true` if `method` is handled
+    pub fn supports(&self, method: &str) -> bool {
+        self.methods.iter().any(|m| m == method)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum ActivationMode {
+    /// Make the configuration the boot default and activate it now
+    #[default]
+    Switch,
+    /// Make the configuration the boot default
+    Boot,
+    /// Activate the configuration, but don't make it the boot default
+    Test,
+    /// Show what would be changed by `switch`
+    DryActivate,
+}
+
+impl ActivationMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActivationMode::Switch => "switch",
+            ActivationMode::Boot => "boot",
+            ActivationMode::Test => "test",
+            ActivationMode::DryActivate => "dry-activate",
+        }
+    }
+
+    /// Returns `true` if this mode changes the system profile
+    pub fn sets_profile(&self) -> bool {
+        matches!(self, ActivationMode::Switch | ActivationMode::Boot)
+    }
+}
+
+impl Display for ActivationMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CancelParams {
+    pub id: RequestId,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RollbackParams {
+    #[serde(default)]
+    pub mode: ActivationMode,
+}
+
+/// Inventory of the host an agent runs on, see [`crate::methods::Facts`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Facts {
+    pub hostname: String,
+    /// Content of `/etc/machine-id`
+    pub machine_id: String,
+    /// `None` if the current system isn't NixOS
+    pub nixos_version: Option<String>,
+    pub kernel_version: String,
+    /// CPU architecture, eg. `x86_64`
+    pub architecture: String,
+    pub cpu_count: usize,
+    /// Total memory in bytes
+    pub memory_total: u64,
+    /// Disk usage of the filesystem containing `/nix`
+    pub nix_disk: DiskUsage,
+    /// Seconds since boot
+    pub uptime: u64,
+    /// Addresses of all interfaces, except loopback
+    #[cfg_attr(feature = "utoipa", schema(value_type = Vec<String>))]
+    pub ip_addresses: Vec<IpAddr>,
+    /// Output of `nix --version`
+    pub nix_version: String,
+    /// Labels set in the agent's NixOS configuration, see `services.nxy-agent.labels`
+    #[serde(default)]
+    #[cfg_attr(feature = "utoipa", schema(value_type = BTreeMap<String, String>))]
+    pub labels: Labels,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct DiskUsage {
+    /// Size in bytes
+    pub total: u64,
+    /// Available bytes
+    pub available: u64,
+}
+
+/// When to reboot, reboots immediately if neither `delay` nor `window` is set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RebootParams {
+    /// Minutes to wait before rebooting
+    #[serde(default)]
+    pub delay: Option<u32>,
+    /// Reboot at the start of the next maintenance window, or now if it's already open
+    #[serde(default)]
+    pub window: Option<RebootWindow>,
+}
+
+/// Daily time window in the agent's local time, may span midnight
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct RebootWindow {
+    /// Start of the window as `HH:MM`
+    pub start: String,
+    /// End of the window as `HH:MM`
+    pub end: String,
+}
+
+/// Systemd unit states reported by `$/units` and `$/unitsChanged`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Units {
+    /// Units in the `failed` state
+    pub failed: Vec<UnitStatus>,
+    /// Units started, restarted or reloaded by the last activation
+    pub activated: Vec<UnitStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct UnitStatus {
+    /// Unit name, eg. `nginx.service`
+    pub name: String,
+    /// High-level state, eg. `active` or `failed`
+    pub active_state: String,
+    /// Unit type specific state, eg. `running` or `exited`
+    pub sub_state: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Older servers send a single substituter, newer ones a list
+    #[test]
+    fn download_from_one_or_many() {
+        for (from, expected) in [
+            (
+                json!("https://cache.example"),
+                vec!["https://cache.example"],
+            ),
+            (
+                json!(["https://peer.example", "https://cache.example"]),
+                vec!["https://peer.example", "https://cache.example"],
+            ),
+        ] {
+            let params: DownloadParams = serde_json::from_value(
+                json!({"store_path": "/nix/store/abc-system", "from": from}),
+            )
+            .unwrap();
+            assert_eq!(params.from.as_slice(), expected);
+        }
+
+        let one = DownloadParams {
+            store_path: "/nix/store/abc-system".into(),
+            from: Substituters::One("https://cache.example".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_value(one).unwrap()["from"],
+            json!("https://cache.example")
+        );
+    }
 }
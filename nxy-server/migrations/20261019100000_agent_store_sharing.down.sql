@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE agent_store_paths;
+ALTER TABLE agents
+	DROP COLUMN substituter;
@@ -0,0 +1,10 @@
+-- Add up migration script here
+ALTER TABLE agents
+        ADD COLUMN substituter TEXT;
+
+CREATE TABLE agent_store_paths (
+	agent_id UUID NOT NULL REFERENCES agents,
+	store_path TEXT NOT NULL,
+
+	PRIMARY KEY (agent_id, store_path)
+);
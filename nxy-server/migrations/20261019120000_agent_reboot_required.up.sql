@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE agents
+	ADD COLUMN reboot_required TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,6 @@
+-- Add up migration script here
+CREATE TABLE agent_units (
+	agent_id UUID PRIMARY KEY REFERENCES agents,
+	units JSONB NOT NULL,
+	updated_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
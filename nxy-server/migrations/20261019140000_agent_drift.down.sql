@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE agent_events;
+ALTER TABLE agents
+	DROP COLUMN drifted;
@@ -0,0 +1,11 @@
+-- Add up migration script here
+ALTER TABLE agents
+	ADD COLUMN drifted BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE agent_events (
+	agent_event_id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	agent_id UUID NOT NULL REFERENCES agents,
+	kind TEXT NOT NULL,
+	store_path TEXT,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
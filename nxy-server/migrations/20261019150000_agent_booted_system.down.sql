@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE agents
+	DROP COLUMN booted_system;
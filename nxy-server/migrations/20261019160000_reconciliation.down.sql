@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP TABLE agent_deployments;
+DROP VIEW agent_desired_systems;
+ALTER TABLE agents
+	DROP COLUMN deploy_policy;
@@ -0,0 +1,26 @@
+-- Add up migration script here
+ALTER TABLE agents
+	ADD COLUMN deploy_policy TEXT NOT NULL DEFAULT 'download'
+		CHECK (deploy_policy IN ('manual', 'download', 'switch', 'boot'));
+
+-- latest evaluation of the configuration assigned to each agent
+CREATE VIEW agent_desired_systems AS
+	SELECT agents.agent_id, desired.flake_revision_id, desired.store_path
+	FROM agents
+	JOIN LATERAL (
+		SELECT e.flake_revision_id, e.store_path
+		FROM nixos_configuration_evaluations AS e
+		JOIN flake_revisions AS r USING (flake_revision_id)
+		WHERE e.nixos_configuration_id = agents.nixos_configuration_id
+		ORDER BY r.last_modified DESC, r.flake_revision_id DESC
+		LIMIT 1
+	) AS desired ON true;
+
+CREATE TABLE agent_deployments (
+	agent_deployment_id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	agent_id UUID NOT NULL REFERENCES agents,
+	store_path TEXT NOT NULL,
+	action TEXT NOT NULL,
+	error TEXT,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
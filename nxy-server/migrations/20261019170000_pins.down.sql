@@ -0,0 +1,17 @@
+-- Add down migration script here
+CREATE OR REPLACE VIEW agent_desired_systems AS
+	SELECT agents.agent_id, desired.flake_revision_id, desired.store_path
+	FROM agents
+	JOIN LATERAL (
+		SELECT e.flake_revision_id, e.store_path
+		FROM nixos_configuration_evaluations AS e
+		JOIN flake_revisions AS r USING (flake_revision_id)
+		WHERE e.nixos_configuration_id = agents.nixos_configuration_id
+		ORDER BY r.last_modified DESC, r.flake_revision_id DESC
+		LIMIT 1
+	) AS desired ON true;
+
+ALTER TABLE nixos_configurations
+	DROP COLUMN pinned_flake_revision_id;
+ALTER TABLE agents
+	DROP COLUMN pinned_flake_revision_id;
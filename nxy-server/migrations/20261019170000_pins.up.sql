@@ -0,0 +1,24 @@
+-- Add up migration script here
+ALTER TABLE agents
+	ADD COLUMN pinned_flake_revision_id BIGINT REFERENCES flake_revisions;
+ALTER TABLE nixos_configurations
+	ADD COLUMN pinned_flake_revision_id BIGINT REFERENCES flake_revisions;
+
+-- pins of the agent take precedence over pins of its configuration
+CREATE OR REPLACE VIEW agent_desired_systems AS
+	SELECT agents.agent_id, desired.flake_revision_id, desired.store_path
+	FROM agents
+	JOIN nixos_configurations AS c USING (nixos_configuration_id)
+	JOIN LATERAL (
+		SELECT e.flake_revision_id, e.store_path
+		FROM nixos_configuration_evaluations AS e
+		JOIN flake_revisions AS r USING (flake_revision_id)
+		WHERE e.nixos_configuration_id = agents.nixos_configuration_id
+			AND e.flake_revision_id = COALESCE(
+				agents.pinned_flake_revision_id,
+				c.pinned_flake_revision_id,
+				e.flake_revision_id
+			)
+		ORDER BY r.last_modified DESC, r.flake_revision_id DESC
+		LIMIT 1
+	) AS desired ON true;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE flake_revisions
+	DROP COLUMN evaluation_status,
+	DROP COLUMN evaluation_error;
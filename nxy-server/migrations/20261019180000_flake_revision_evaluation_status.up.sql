@@ -0,0 +1,11 @@
+-- Add up migration script here
+ALTER TABLE flake_revisions
+	ADD COLUMN evaluation_status TEXT NOT NULL DEFAULT 'pending'
+		CHECK (evaluation_status IN ('pending', 'succeeded', 'failed')),
+	ADD COLUMN evaluation_error TEXT;
+
+UPDATE flake_revisions SET evaluation_status = 'succeeded'
+WHERE EXISTS (
+	SELECT 1 FROM nixos_configuration_evaluations AS e
+	WHERE e.flake_revision_id = flake_revisions.flake_revision_id
+);
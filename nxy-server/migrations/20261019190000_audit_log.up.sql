@@ -0,0 +1,31 @@
+-- Add up migration script here
+CREATE TABLE audit_log (
+	audit_log_id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+	-- `server` for automatic actions, otherwise the user or token of the caller
+	actor TEXT NOT NULL,
+	action TEXT NOT NULL,
+	-- eg. `agent/<id>` or `flake/<id>`
+	target TEXT,
+	parameters JSONB,
+	source_ip TEXT,
+	-- HTTP status of REST calls
+	status INTEGER,
+	outcome TEXT NOT NULL CHECK (outcome IN ('succeeded', 'failed')),
+	error TEXT
+);
+
+CREATE INDEX audit_log_target_idx ON audit_log (target, created_at);
+
+CREATE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+BEGIN
+	RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+	BEFORE UPDATE OR DELETE ON audit_log
+	FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
+CREATE TRIGGER audit_log_no_truncate
+	BEFORE TRUNCATE ON audit_log
+	FOR EACH STATEMENT EXECUTE FUNCTION audit_log_append_only();
@@ -0,0 +1,9 @@
+-- Add up migration script here
+CREATE TABLE agent_labels (
+	agent_id UUID NOT NULL REFERENCES agents,
+	key TEXT NOT NULL,
+	value TEXT NOT NULL,
+	-- labels set through the API take precedence over those reported by the agent
+	source TEXT NOT NULL CHECK (source IN ('api', 'agent')),
+	PRIMARY KEY (agent_id, key)
+);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE agents
+	DROP COLUMN name,
+	DROP COLUMN name_overridden;
@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE agents
+	-- derived from the hostname, unless set through the API
+	ADD COLUMN name TEXT UNIQUE,
+	ADD COLUMN name_overridden BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,5 @@
+-- Add down migration script here
+ALTER TABLE agent_deployments
+	DROP COLUMN deployment_id;
+
+DROP TABLE deployments;
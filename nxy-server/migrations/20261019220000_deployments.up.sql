@@ -0,0 +1,15 @@
+-- Add up migration script here
+CREATE TABLE deployments (
+	deployment_id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+	-- kept as text, deployments outlive the configurations and revisions they deployed
+	config TEXT,
+	selector TEXT,
+	revision TEXT,
+	mode TEXT NOT NULL,
+	CHECK ((config IS NULL) <> (selector IS NULL))
+);
+
+-- NULL for steps of the reconciler
+ALTER TABLE agent_deployments
+	ADD COLUMN deployment_id BIGINT REFERENCES deployments;
@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP TABLE deployment_agents;
+
+ALTER TABLE deployments
+	DROP COLUMN finished_at;
@@ -0,0 +1,17 @@
+-- Add up migration script here
+-- NULL while agents are still being deployed to
+ALTER TABLE deployments
+	ADD COLUMN finished_at TIMESTAMP WITH TIME ZONE;
+
+-- the outcome of older deployments is unknown
+UPDATE deployments SET finished_at = created_at;
+
+CREATE TABLE deployment_agents (
+	deployment_id BIGINT NOT NULL REFERENCES deployments ON DELETE CASCADE,
+	-- no reference, deployments outlive the agents they deployed to
+	agent_id UUID NOT NULL,
+	store_path TEXT,
+	status TEXT NOT NULL CHECK (status IN ('skipped', 'running', 'succeeded', 'failed')),
+	error TEXT,
+	PRIMARY KEY (deployment_id, agent_id)
+);
@@ -0,0 +1,7 @@
+-- Add down migration script here
+DELETE FROM deployments WHERE agent IS NOT NULL;
+
+ALTER TABLE deployments
+	DROP CONSTRAINT deployments_target_check,
+	DROP COLUMN agent,
+	ADD CONSTRAINT deployments_check CHECK ((config IS NULL) <> (selector IS NULL));
@@ -0,0 +1,6 @@
+-- Add up migration script here
+-- deployments of a single agent, kept as text like the other targets
+ALTER TABLE deployments
+	ADD COLUMN agent TEXT,
+	DROP CONSTRAINT deployments_check,
+	ADD CONSTRAINT deployments_target_check CHECK (num_nonnulls(config, selector, agent) = 1);
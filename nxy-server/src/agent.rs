@@ -1,60 +1,300 @@
 use std::{
     collections::HashMap,
-    path::PathBuf,
-    sync::{atomic::AtomicU64, Arc, Mutex},
-    time::Duration,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use color_eyre::{eyre::eyre, Result};
 use nxy_common::{
-    types::{ActivateParams, DownloadParams, Status},
-    JsonRPC, Request, RequestId, Response,
+    api::Event,
+    methods,
+    types::{
+        ActivateParams, ActivationMode, CancelParams, Capabilities, DownloadParams,
+        InitializeParams, InitializeResult, Status, Substituters, System, Units, PROTOCOL_VERSION,
+    },
+    ErrorCode, JsonRPC, Message, Method, MethodError, Notification, NotificationMethod, Request,
+    RequestId, Response,
 };
+use once_cell::sync::OnceCell;
 use serde::Serialize;
 use sqlx::PgPool;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{broadcast, mpsc, oneshot, Notify};
 use tracing::{instrument, Level};
 use uuid::Uuid;
 
-use crate::config::Config;
+use crate::{
+    audit,
+    config::Config,
+    deploy, labels,
+    metrics::Metrics,
+    reconcile::{Reconciler, Running},
+};
 
 pub(crate) type Inbox = mpsc::Receiver<JsonRPC>;
-pub(crate) type Outbox = mpsc::Sender<JsonRPC>;
+pub(crate) type Outbox = mpsc::Sender<Message>;
+/// Notifications send by an agent, see [`AgentManager::process_notifications`]
+pub(crate) type Notifications = mpsc::Receiver<Notification>;
+
+/// Maximum number of peers offered to an agent as download source.
+const MAX_PEER_SUBSTITUTERS: usize = 3;
+
+/// How often the facts of connected agents are refreshed.
+const FACTS_REFRESH_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Events buffered per subscriber, slower subscribers miss events.
+const EVENT_BUFFER: usize = 256;
+
+/// Calls to an agent that didn't produce a response.
+#[derive(Debug, thiserror::Error)]
+pub enum RpcError {
+    /// The agent is not connected to this server.
+    #[error("agent {0} is not connected")]
+    NotConnected(Uuid),
+
+    /// The connection to the agent was closed before it answered.
+    #[error("agent disconnected")]
+    Disconnected,
+
+    /// The agent didn't answer within [`Method::TIMEOUT`], the request got cancelled.
+    #[error("agent didn't answer {method} within {timeout:?}")]
+    Timeout {
+        method: &'static str,
+        timeout: Duration,
+    },
+
+    /// The agent didn't announce support for the method, it wasn't sent.
+    #[error("agent doesn't support {0}")]
+    Unsupported(&'static str),
+}
 
 #[derive(Debug)]
 pub struct AgentManager {
     config: Arc<Config>,
     pool: PgPool,
     agents: Mutex<HashMap<Uuid, Agent>>,
+    reconcile: Arc<Notify>,
+    events: broadcast::Sender<Event>,
+    metrics: Arc<Metrics>,
+    running: Running,
 }
 
 impl AgentManager {
     pub async fn start(config: Arc<Config>, pool: PgPool) -> Arc<Self> {
+        // nothing deploys to their agents anymore
+        if let Err(err) = deploy::fail_interrupted(&pool).await {
+            tracing::warn!(?err, "failed to fail interrupted deployments");
+        }
+
+        let reconcile = Arc::new(Notify::new());
         let manager = Arc::new(Self {
             config,
-            pool,
+            pool: pool.clone(),
             agents: Default::default(),
+            reconcile: Arc::clone(&reconcile),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            metrics: Arc::new(Metrics::new()),
+            running: Running::default(),
         });
 
+        let reconciler = Reconciler::new(Arc::clone(&manager), pool, reconcile);
+        tokio::spawn(Arc::new(reconciler).run());
+
         let manager_c = Arc::clone(&manager);
         tokio::spawn(async move { manager_c.heartbeat().await });
 
+        let manager_c = Arc::clone(&manager);
+        tokio::spawn(async move { manager_c.refresh_facts().await });
+
         manager
     }
 
     pub async fn heartbeat(&self) {
         loop {
             let agents = self.agents.lock().unwrap().clone();
-            for agent in agents.values() {
-                agent.ping().await.unwrap();
+            for (agent_id, agent) in agents {
+                let start = Instant::now();
+                match agent.call::<methods::Ping>(()).await {
+                    Ok(_) => self
+                        .metrics
+                        .heartbeat_rtt
+                        .observe(start.elapsed().as_secs_f64()),
+                    Err(err) => tracing::warn!(%agent_id, ?err, "heartbeat failed"),
+                }
             }
             tokio::time::sleep(Duration::from_secs(5)).await
         }
     }
 
-    pub(crate) async fn add_agent(&self, agent: Agent) -> Result<()> {
+    /// Periodically update the facts of all connected agents.
+    pub async fn refresh_facts(&self) {
+        loop {
+            tokio::time::sleep(FACTS_REFRESH_INTERVAL).await;
+            let agents = self.agents.lock().unwrap().clone();
+            for (agent_id, agent) in agents {
+                if let Err(err) = self.update_facts(agent_id, &agent).await {
+                    tracing::warn!(%agent_id, ?err, "failed to refresh facts");
+                }
+            }
+        }
+    }
+
+    /// Query the facts of `agent` and store them, agents without `$/facts` are skipped.
+    async fn update_facts(&self, agent_id: Uuid, agent: &Agent) -> Result<()> {
+        if !agent.supports(methods::Facts::NAME) {
+            return Ok(());
+        }
+        let facts = agent.call::<methods::Facts>(()).await?;
+        // first, the facts are worth keeping even if the agent can't be named
+        sqlx::query!(
+            "INSERT INTO agent_facts (agent_id, facts, updated_at) VALUES ($1, $2, now())
+            ON CONFLICT (agent_id) DO UPDATE
+                SET facts = EXCLUDED.facts, updated_at = EXCLUDED.updated_at",
+            agent_id,
+            serde_json::to_value(&facts)?
+        )
+        .execute(&self.pool)
+        .await?;
+
+        labels::sync_reported(&self.pool, agent_id, &facts.labels).await?;
+        self.name_after_hostname(agent_id, &facts.hostname).await
+    }
+
+    /// Name `agent_id` after its hostname, unless its name was set through the API
+    ///
+    /// If another agent already uses the hostname, the start of the id is appended.
+    pub(crate) async fn name_after_hostname(&self, agent_id: Uuid, hostname: &str) -> Result<()> {
+        match self.set_derived_name(agent_id, hostname).await {
+            // the unique constraint decides, a check before the update would race other agents
+            Err(sqlx::Error::Database(err)) if err.constraint() == Some("agents_name_key") => {
+                let name = format!("{hostname}-{}", &agent_id.simple().to_string()[..8]);
+                self.set_derived_name(agent_id, &name).await?;
+            }
+            result => result?,
+        }
+        Ok(())
+    }
+
+    async fn set_derived_name(&self, agent_id: Uuid, name: &str) -> sqlx::Result<()> {
+        sqlx::query!(
+            "UPDATE agents SET name = $2 WHERE agent_id = $1 AND NOT name_overridden",
+            agent_id,
+            name
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Store the failed units of `agent_id` and the units touched by its last activation.
+    async fn store_units(&self, agent_id: Uuid, units: Units) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO agent_units (agent_id, units, updated_at) VALUES ($1, $2, now())
+            ON CONFLICT (agent_id) DO UPDATE
+                SET units = EXCLUDED.units, updated_at = EXCLUDED.updated_at",
+            agent_id,
+            serde_json::to_value(&units)?
+        )
+        .execute(&self.pool)
+        .await?;
+
+        if !units.failed.is_empty() {
+            let failed: Vec<_> = units.failed.iter().map(|unit| &unit.name).collect();
+            tracing::warn!(%agent_id, ?failed, "agent has failed units");
+        }
+        Ok(())
+    }
+
+    /// Record a change of the current system of `agent_id`.
+    ///
+    /// The agent is flagged as drifted if the new system isn't the result of any known
+    /// evaluation, eg. because someone ran `nixos-rebuild switch` on the host.
+    async fn system_changed(&self, agent_id: Uuid, system: System) -> Result<()> {
+        let current_system = system.current.to_str().unwrap();
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            "INSERT INTO agent_events (agent_id, kind, store_path) VALUES ($1, 'system-changed', $2)",
+            agent_id,
+            current_system
+        )
+        .execute(&mut tx)
+        .await?;
+        let drifted = sqlx::query_scalar!(
+            r#"
+            UPDATE agents SET current_system = $2, drifted = NOT EXISTS (
+                SELECT 1 FROM nixos_configuration_evaluations WHERE store_path = $2
+            )
+            WHERE agent_id = $1
+            RETURNING drifted
+            "#,
+            agent_id,
+            current_system
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        if drifted {
+            self.drift_detected(agent_id, current_system);
+        } else {
+            tracing::info!(%agent_id, current_system, "agent switched system");
+        }
+
+        // reboot requirements might have changed as well
+        self.refresh_status(agent_id).await
+    }
+
+    fn drift_detected(&self, agent_id: Uuid, current_system: &str) {
+        tracing::warn!(%agent_id, current_system, "agent switched to an unknown system");
+        self.metrics.drift_detected.inc();
+        self.publish(Event::Drifted {
+            agent_id,
+            current_system: current_system.to_string(),
+        });
+    }
+
+    /// Handle notifications of `agent_id` until its connection is closed.
+    pub(crate) async fn process_notifications(
+        &self,
+        agent_id: Uuid,
+        mut notifications: Notifications,
+    ) {
+        while let Some(notification) = notifications.recv().await {
+            let result = if let Some(params) = notification.extract::<methods::UnitsChanged>() {
+                match params {
+                    Ok(units) => self.store_units(agent_id, units).await,
+                    Err(err) => Err(err.into()),
+                }
+            } else if let Some(params) = notification.extract::<methods::SystemChanged>() {
+                match params {
+                    Ok(system) => self.system_changed(agent_id, system).await,
+                    Err(err) => Err(err.into()),
+                }
+            } else {
+                tracing::warn!(%agent_id, ?notification, "unknown notification");
+                Ok(())
+            };
+
+            if let Err(err) = result {
+                tracing::warn!(%agent_id, ?err, method = notification.method, "failed to process notification");
+            }
+        }
+    }
+
+    /// Register a newly connected agent.
+    ///
+    /// # Returns
+    ///
+    /// Id of the agent
+    pub(crate) async fn add_agent(&self, agent: Agent) -> Result<Uuid> {
+        agent.initialize().await?;
+
         // request agent status to aquire the agent id
-        let status = agent.status().await?;
+        let status = agent.call::<methods::Status>(()).await?;
 
         let result =
             sqlx::query_scalar!("SELECT agent_id FROM agents WHERE agent_id = $1", status.id)
@@ -69,65 +309,221 @@ impl AgentManager {
         } else {
             tracing::info!(id = ?status.id, "known agent connected");
         }
-        sqlx::query!(
-            "UPDATE agents SET current_system = $2 WHERE agent_id = $1",
-            status.id,
-            status.system.current.to_str().unwrap()
-        )
-        .execute(&self.pool)
-        .await?;
+        self.store_status(&status).await?;
 
         //XXX: this is a hack and should be replaced with something better.
         match_agent_to_configuration(self.pool.clone()).await?;
 
+        if let Err(err) = self.update_facts(status.id, &agent).await {
+            tracing::warn!(id = ?status.id, ?err, "failed to query facts");
+        }
+        if agent.supports(methods::Units::NAME) {
+            match agent.call::<methods::Units>(()).await {
+                Ok(units) => self.store_units(status.id, units).await?,
+                Err(err) => tracing::warn!(id = ?status.id, ?err, "failed to query units"),
+            }
+        }
+
         {
             let mut agents = self.agents.lock().unwrap();
             agents.insert(status.id, agent);
+            self.metrics.connected_agents.set(agents.len() as i64);
         }
-        Ok(())
+        self.publish(Event::AgentConnected {
+            agent_id: status.id,
+        });
+        self.trigger_reconcile();
+        Ok(status.id)
     }
 
-    #[instrument(skip(self))]
-    pub(crate) async fn process_update(
-        &self,
-        config_id: i64,
-        flake_revision_id: i64,
-    ) -> Result<()> {
-        let agent_id = sqlx::query_scalar!(
-            "SELECT agent_id FROM agents WHERE nixos_configuration_id = $1",
-            config_id
+    /// Forget `agent` after its connection was closed.
+    ///
+    /// Does nothing if the agent already reconnected and was registered with a new connection.
+    pub(crate) fn remove_agent(&self, agent_id: Uuid, agent: &Agent) {
+        let mut agents = self.agents.lock().unwrap();
+        if agents
+            .get(&agent_id)
+            .map_or(false, |current| Arc::ptr_eq(&current.0, &agent.0))
+        {
+            tracing::info!(%agent_id, "agent disconnected");
+            agents.remove(&agent_id);
+            self.metrics.connected_agents.set(agents.len() as i64);
+            self.publish(Event::AgentDisconnected { agent_id });
+        }
+    }
+
+    /// Query the status of `agent_id` again, eg. after its system changed.
+    pub(crate) async fn refresh_status(&self, agent_id: Uuid) -> Result<()> {
+        let agent = self.get(agent_id).ok_or(RpcError::NotConnected(agent_id))?;
+        let status = agent.call::<methods::Status>(()).await?;
+        self.store_status(&status).await
+    }
+
+    /// Store the status of an agent, it may have switched systems while it was disconnected
+    async fn store_status(&self, status: &Status) -> Result<()> {
+        let reboot_required: Vec<String> = status
+            .reboot_required
+            .iter()
+            .map(|reason| reason.to_string())
+            .collect();
+        let current_system = status.system.current.to_str().unwrap();
+        let row = sqlx::query!(
+            r#"
+            WITH old AS (SELECT drifted FROM agents WHERE agent_id = $1 FOR UPDATE)
+            UPDATE agents
+            SET current_system = $2, booted_system = $3, substituter = $4, reboot_required = $5,
+                drifted = NOT EXISTS (
+                    SELECT 1 FROM nixos_configuration_evaluations WHERE store_path = $2
+                )
+            FROM old
+            WHERE agent_id = $1
+            RETURNING agents.drifted, old.drifted AS was_drifted
+            "#,
+            status.id,
+            current_system,
+            status.system.booted.to_str().unwrap(),
+            status.substituter,
+            &reboot_required
         )
         .fetch_optional(&self.pool)
         .await?;
 
-        let Some(agent_id) = agent_id else { return Ok(()) };
+        if let Some(row) = row {
+            if row.drifted && !row.was_drifted {
+                self.drift_detected(status.id, current_system);
+            }
+        }
+        Ok(())
+    }
 
-        tracing::info!("Updating configuration on agent {agent_id:?}");
+    /// Copy `store_path` to `agent_id` and remember that the agent has it.
+    pub(crate) async fn download(&self, agent_id: Uuid, store_path: PathBuf) -> Result<()> {
+        let agent = self.get(agent_id).ok_or(RpcError::NotConnected(agent_id))?;
 
-        let store_path = sqlx::query_scalar!(
-            "SELECT store_path 
-            FROM nixos_configuration_evaluations 
-            WHERE flake_revision_id = $1 
-                AND nixos_configuration_id = $2",
-            flake_revision_id,
-            config_id
+        let substituters = self.substituters(agent_id, &store_path).await?;
+        agent
+            .call::<methods::Download>(DownloadParams {
+                from: download_sources(agent.capabilities(), substituters),
+                store_path: store_path.clone(),
+            })
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO agent_store_paths (agent_id, store_path) VALUES ($1, $2)
+            ON CONFLICT DO NOTHING",
+            agent_id,
+            store_path.to_str().unwrap()
         )
-        .fetch_one(&self.pool)
+        .execute(&self.pool)
         .await?;
 
-        let agent = {
-            let agents = self.agents.lock().unwrap();
-            agents.get(&agent_id).unwrap().clone()
-        };
+        Ok(())
+    }
 
+    /// Activate `store_path` on `agent_id`, it has to be downloaded first
+    pub(crate) async fn activate(
+        &self,
+        agent_id: Uuid,
+        store_path: &str,
+        mode: ActivationMode,
+    ) -> Result<()> {
+        let agent = self.get(agent_id).ok_or(RpcError::NotConnected(agent_id))?;
         agent
-            .download(DownloadParams {
-                store_path: PathBuf::from(store_path),
-                from: self.config.external_url.clone(),
+            .call::<methods::Activate>(ActivateParams {
+                store_path: store_path.into(),
+                mode,
             })
             .await?;
+        self.refresh_status(agent_id).await
+    }
 
-        Ok(())
+    /// Returns the substituters `agent_id` should copy `store_path` from.
+    ///
+    /// Connected peers of the same site which already have `store_path` come first, followed by
+    /// the substituters of the agent's site.
+    pub(crate) async fn substituters(
+        &self,
+        agent_id: Uuid,
+        store_path: &Path,
+    ) -> Result<Vec<String>> {
+        let site = sqlx::query_scalar!("SELECT site FROM agents WHERE agent_id = $1", agent_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .flatten();
+
+        let mut substituters = match site {
+            Some(ref site) => self.peer_substituters(agent_id, site, store_path).await?,
+            None => Vec::new(),
+        };
+        substituters.extend(self.config.substituters(site.as_deref()));
+
+        Ok(substituters)
+    }
+
+    /// Returns substituters of connected peers in `site` that already have `store_path`.
+    async fn peer_substituters(
+        &self,
+        agent_id: Uuid,
+        site: &str,
+        store_path: &Path,
+    ) -> Result<Vec<String>> {
+        let peers = sqlx::query!(
+            r#"
+            SELECT agent_id, substituter AS "substituter!"
+            FROM agents
+            WHERE site = $1
+                AND agent_id <> $2
+                AND substituter IS NOT NULL
+                AND (current_system = $3 OR EXISTS (
+                    SELECT 1 FROM agent_store_paths AS p
+                    WHERE p.agent_id = agents.agent_id AND p.store_path = $3
+                ))
+            ORDER BY random()
+            "#,
+            site,
+            agent_id,
+            store_path.to_str().unwrap()
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let agents = self.agents.lock().unwrap();
+        Ok(peers
+            .into_iter()
+            .filter(|peer| agents.contains_key(&peer.agent_id))
+            .take(MAX_PEER_SUBSTITUTERS)
+            .map(|peer| peer.substituter)
+            .collect())
+    }
+
+    /// Ids of all connected agents
+    pub(crate) fn connected(&self) -> Vec<Uuid> {
+        self.agents.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Reconcile all agents with their desired system as soon as possible
+    pub(crate) fn trigger_reconcile(&self) {
+        self.reconcile.notify_one();
+    }
+
+    pub(crate) fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// Agents the reconciler or a deployment is currently deploying to
+    pub(crate) fn running(&self) -> &Running {
+        &self.running
+    }
+
+    /// Send `event` to all subscribers, see [`AgentManager::subscribe`]
+    pub(crate) fn publish(&self, event: Event) {
+        // nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    /// Receive all events published from now on
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
     }
 
     pub(crate) fn get(&self, agent_id: Uuid) -> Option<Agent> {
@@ -141,29 +537,41 @@ pub struct Agent(Arc<AgentInner>);
 
 #[derive(Debug)]
 struct AgentInner {
-    next_request_id: AtomicU64,
+    next_request_id: AtomicI64,
     pending: Mutex<HashMap<RequestId, oneshot::Sender<Response>>>,
+    /// Set once the connection is closed, no further responses will arrive
+    closed: AtomicBool,
+    initialize: OnceCell<InitializeResult>,
     outbox: Outbox,
+    metrics: Arc<Metrics>,
     span: tracing::Span,
 }
 
 impl Agent {
-    pub fn new(inbox: Inbox, outbox: Outbox) -> Self {
+    pub(crate) fn new(
+        inbox: Inbox,
+        outbox: Outbox,
+        metrics: Arc<Metrics>,
+    ) -> (Self, Notifications) {
         let span = tracing::span!(Level::TRACE, "agent connection");
         let agent = Agent(Arc::new(AgentInner {
-            next_request_id: AtomicU64::new(0),
+            next_request_id: AtomicI64::new(0),
             pending: Default::default(),
+            closed: AtomicBool::new(false),
+            initialize: OnceCell::new(),
             outbox,
+            metrics,
             span,
         }));
 
+        let (notification_sender, notifications) = mpsc::channel(64);
         let clone = agent.clone();
-        tokio::spawn(async move { clone.process_inbox(inbox).await });
-        agent
+        tokio::spawn(async move { clone.process_inbox(inbox, notification_sender).await });
+        (agent, notifications)
     }
 
-    #[instrument(parent = &self.0.span, skip(self, inbox))]
-    async fn process_inbox(self, mut inbox: Inbox) {
+    #[instrument(parent = &self.0.span, skip_all)]
+    async fn process_inbox(self, mut inbox: Inbox, notifications: mpsc::Sender<Notification>) {
         while let Some(msg) = inbox.recv().await {
             tracing::trace!(?msg, "receiver message");
             match msg {
@@ -175,7 +583,8 @@ impl Agent {
 
                     let mut pending = self.0.pending.lock().unwrap();
                     if let Some(tx) = pending.remove(&res.id) {
-                        tx.send(res).unwrap();
+                        // the caller might have given up waiting already
+                        let _ = tx.send(res);
                     } else {
                         tracing::warn!(
                             request_id = ?res.id,
@@ -184,89 +593,237 @@ impl Agent {
                     }
                 }
                 JsonRPC::Notification(notification) => {
-                    tracing::warn!(
-                        ?notification,
-                        "server received notification, this should happen"
-                    );
+                    if notifications.send(notification).await.is_err() {
+                        tracing::debug!("nobody is processing notifications, dropping");
+                    }
                 }
             }
         }
+
+        // fail all outstanding requests by dropping their senders
+        self.0.closed.store(true, Ordering::SeqCst);
+        self.0.pending.lock().unwrap().clear();
     }
 
     async fn send_request<S: AsRef<str>, P: Serialize>(
         &self,
         method: S,
         params: P,
-    ) -> oneshot::Receiver<Response> {
-        let id = self
-            .0
-            .next_request_id
-            .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
-            .into();
-        let request: JsonRPC = Request::new(id, method, params).into();
+    ) -> Result<(RequestId, oneshot::Receiver<Response>), RpcError> {
+        let id: RequestId = self.0.next_request_id.fetch_add(1, Ordering::SeqCst).into();
+        let request = JsonRPC::from(Request::new(id.clone(), method, params));
 
         let (sender, receiver) = oneshot::channel();
 
         {
             let mut pending = self.0.pending.lock().unwrap();
-            pending.insert(id, sender);
+            pending.insert(id.clone(), sender);
         }
 
-        self.0.outbox.send(request).await.unwrap();
-        receiver
+        if self.0.closed.load(Ordering::SeqCst) || self.0.outbox.send(request.into()).await.is_err()
+        {
+            self.0.pending.lock().unwrap().remove(&id);
+            return Err(RpcError::Disconnected);
+        }
+        Ok((id, receiver))
     }
 
-    pub async fn ping(&self) -> Result<()> {
-        let res = self.send_request("$/ping", ()).await.await?;
-        if let Some(error) = res.error {
-            Err(eyre!("request error: {:?}", error))
-        } else {
-            Ok(())
+    /// Stop waiting for request `id` and ask the agent to abort it
+    async fn cancel(&self, id: RequestId) {
+        self.0.pending.lock().unwrap().remove(&id);
+
+        if self.supports(methods::CancelRequest::NAME) {
+            let notification =
+                Notification::typed::<methods::CancelRequest>(CancelParams { id: id.clone() });
+            if self
+                .0
+                .outbox
+                .send(JsonRPC::from(notification).into())
+                .await
+                .is_err()
+            {
+                tracing::debug!(%id, "agent disconnected before request could be cancelled");
+            }
         }
     }
 
-    pub async fn status(&self) -> Result<Status> {
-        let res = self.send_request("$/status", ()).await.await?;
-        if let Some(error) = res.error {
-            Err(eyre!("request error: {:?}", error))
-        } else {
-            res.result
-                .ok_or_else(|| eyre!("status result is empty"))
-                .and_then(|v| serde_json::from_value(v).map_err(Into::into))
+    /// Negotiate protocol version and capabilities with the agent.
+    ///
+    /// Agents predating `$/initialize` are assumed to only support the methods they shipped with.
+    async fn initialize(&self) -> Result<()> {
+        let params = InitializeParams {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::default(),
+        };
+        let result = match self.call::<methods::Initialize>(params).await {
+            Ok(result) => result,
+            Err(err) if is_method_not_found(&err) => {
+                tracing::info!("agent doesn't support initialize, assuming legacy capabilities");
+                legacy_initialize_result()
+            }
+            Err(err) => return Err(err),
+        };
+
+        if result.protocol_version != PROTOCOL_VERSION {
+            tracing::warn!(
+                agent = result.protocol_version,
+                server = PROTOCOL_VERSION,
+                "agent speaks a different protocol version"
+            );
         }
+        tracing::debug!(capabilities = ?result.capabilities, "agent initialized");
+
+        self.0
+            .initialize
+            .set(result)
+            .map_err(|_| eyre!("agent is already initialized"))
     }
 
-    pub(crate) async fn download(&self, params: DownloadParams) -> Result<()> {
-        let res = self.send_request("$/download", params).await.await?;
-        if let Some(error) = res.error {
-            Err(eyre!("request error: {:?}", error))
-        } else {
-            Ok(())
-        }
+    /// Capabilities announced by the agent, `None` until the agent is initialized
+    pub(crate) fn capabilities(&self) -> Option<&Capabilities> {
+        self.0.initialize.get().map(|result| &result.capabilities)
     }
 
-    pub(crate) async fn activate(&self, params: ActivateParams) -> Result<()> {
-        let res = self.send_request("$/activate", params).await.await?;
-        if let Some(error) = res.error {
-            Err(eyre!("request error: {:?}", error))
-        } else {
-            Ok(())
+    /// Returns `true` if the agent announced support for `method`
+    pub(crate) fn supports(&self, method: &str) -> bool {
+        self.capabilities().map_or(false, |c| c.supports(method))
+    }
+
+    /// Returns `true` if the agent can activate configurations with `mode`
+    pub(crate) fn supports_activation_mode(&self, mode: ActivationMode) -> bool {
+        self.capabilities()
+            .map_or(false, |c| c.activation_modes.contains(&mode))
+    }
+
+    /// Call method `M` on the agent and wait for its result
+    ///
+    /// Fails with [`RpcError::Unsupported`] without contacting the agent if it didn't announce
+    /// support for `M`. If the agent
+    /// doesn't answer within [`Method::TIMEOUT`] the request is cancelled and
+    /// [`RpcError::Timeout`] returned, if the connection closes [`RpcError::Disconnected`].
+    pub(crate) async fn call<M: Method>(&self, params: M::Params) -> Result<M::Result> {
+        if let Some(capabilities) = self.capabilities() {
+            if !capabilities.supports(M::NAME) {
+                return Err(RpcError::Unsupported(M::NAME).into());
+            }
         }
+        let start = Instant::now();
+        let result = self.request::<M>(params).await;
+        self.0.metrics.record_rpc(M::NAME, start.elapsed(), &result);
+        result
+    }
+
+    async fn request<M: Method>(&self, params: M::Params) -> Result<M::Result> {
+        let (id, receiver) = self.send_request(M::NAME, params).await?;
+        let res = match tokio::time::timeout(M::TIMEOUT, receiver).await {
+            Ok(Ok(res)) => res,
+            Ok(Err(_)) => return Err(RpcError::Disconnected.into()),
+            Err(_) => {
+                tracing::warn!(%id, method = M::NAME, "request timed out, cancelling");
+                self.cancel(id).await;
+                return Err(RpcError::Timeout {
+                    method: M::NAME,
+                    timeout: M::TIMEOUT,
+                }
+                .into());
+            }
+        };
+        res.into_result::<M>().map_err(Into::into)
+    }
+}
+
+fn is_method_not_found(err: &color_eyre::Report) -> bool {
+    err.downcast_ref::<MethodError<()>>()
+        .map_or(false, |e| e.code == ErrorCode::MethodNotFound as i32)
+}
+
+/// The substituters to send, agents without multi-source downloads only get the preferred one
+fn download_sources(
+    capabilities: Option<&Capabilities>,
+    mut substituters: Vec<String>,
+) -> Substituters {
+    if capabilities.map_or(false, |c| c.multi_source_download) || substituters.is_empty() {
+        Substituters::Many(substituters)
+    } else {
+        Substituters::One(substituters.swap_remove(0))
+    }
+}
+
+/// Capabilities of agents released before capability negotiation
+///
+/// Their `$/download` copies from a single substituter, see [`download_sources`].
+fn legacy_initialize_result() -> InitializeResult {
+    InitializeResult {
+        protocol_version: 0,
+        capabilities: Capabilities {
+            methods: [
+                methods::Ping::NAME,
+                methods::Status::NAME,
+                methods::Download::NAME,
+                methods::Activate::NAME,
+            ]
+            .map(String::from)
+            .to_vec(),
+            activation_modes: vec![ActivationMode::Switch],
+            multi_source_download: false,
+        },
     }
 }
 
 /// Try to assign agents a nixos configuration based the store path of the current system
 /// (`/run/current-system`).
 async fn match_agent_to_configuration(pool: PgPool) -> Result<()> {
-    sqlx::query!(
-        "UPDATE agents SET nixos_configuration_id = (
-            SELECT e.nixos_configuration_id 
-                FROM nixos_configuration_evaluations AS e 
+    let assigned = sqlx::query!(
+        r#"UPDATE agents SET nixos_configuration_id = (
+            SELECT e.nixos_configuration_id
+                FROM nixos_configuration_evaluations AS e
             WHERE agents.current_system = e.store_path)
-        WHERE agents.nixos_configuration_id IS NULL"
+        WHERE agents.nixos_configuration_id IS NULL
+        RETURNING agent_id, nixos_configuration_id AS "config_id?""#
     )
-    .execute(&pool)
+    .fetch_all(&pool)
     .await?;
 
+    for agent in assigned {
+        let Some(config_id) = agent.config_id else {
+            continue;
+        };
+        let entry =
+            audit::Entry::server("assign-configuration", format!("agent/{}", agent.agent_id))
+                .parameters(serde_json::json!({ "config_id": config_id }));
+        audit::record(&pool, entry).await?;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_agents_download_from_one_substituter() {
+        let substituters = vec![
+            "http://peer:5000".to_string(),
+            "https://nxy.example".to_string(),
+        ];
+
+        let legacy = legacy_initialize_result().capabilities;
+        assert_eq!(
+            download_sources(Some(&legacy), substituters.clone()),
+            Substituters::One("http://peer:5000".to_string())
+        );
+        assert_eq!(
+            download_sources(None, substituters.clone()),
+            Substituters::One("http://peer:5000".to_string())
+        );
+
+        let current = Capabilities {
+            multi_source_download: true,
+            ..legacy
+        };
+        assert_eq!(
+            download_sources(Some(&current), substituters.clone()),
+            Substituters::Many(substituters)
+        );
+    }
+}
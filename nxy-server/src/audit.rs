@@ -0,0 +1,70 @@
+//! Append-only log of every change made through the REST API or by the server on its own.
+//!
+//! REST calls are recorded by a middleware, see [`crate::http`], automatic actions where they
+//! happen.
+
+use serde_json::Value;
+use sqlx::PgPool;
+
+/// Actor of actions the server takes on its own, eg. reconciliation
+pub(crate) const SERVER: &str = "server";
+
+#[derive(Debug)]
+pub(crate) struct Entry {
+    pub(crate) actor: String,
+    pub(crate) action: String,
+    pub(crate) target: Option<String>,
+    pub(crate) parameters: Option<Value>,
+    pub(crate) source_ip: Option<String>,
+    pub(crate) status: Option<i32>,
+    /// `None` if the action succeeded
+    pub(crate) error: Option<String>,
+}
+
+impl Entry {
+    /// Action taken by the server on its own
+    pub(crate) fn server(action: impl Into<String>, target: impl Into<String>) -> Self {
+        Self {
+            actor: SERVER.to_string(),
+            action: action.into(),
+            target: Some(target.into()),
+            parameters: None,
+            source_ip: None,
+            status: None,
+            error: None,
+        }
+    }
+
+    pub(crate) fn parameters(mut self, parameters: Value) -> Self {
+        self.parameters = Some(parameters);
+        self
+    }
+
+    pub(crate) fn error(mut self, error: Option<String>) -> Self {
+        self.error = error;
+        self
+    }
+}
+
+pub(crate) async fn record(db: &PgPool, entry: Entry) -> sqlx::Result<()> {
+    let outcome = match entry.error {
+        None => "succeeded",
+        Some(_) => "failed",
+    };
+    sqlx::query!(
+        "INSERT INTO audit_log
+            (actor, action, target, parameters, source_ip, status, outcome, error)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        entry.actor,
+        entry.action,
+        entry.target,
+        entry.parameters,
+        entry.source_ip,
+        entry.status,
+        outcome,
+        entry.error
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
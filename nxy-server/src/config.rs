@@ -1,10 +1,49 @@
-use serde::Deserialize;
+use std::{collections::HashMap, net::IpAddr};
+
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     #[serde(default = "default_external_url")]
     pub external_url: String,
+    /// Named sites agents can be assigned to, e.g. one per datacenter.
+    #[serde(default)]
+    pub sites: HashMap<String, Site>,
+    /// Addresses of authenticating reverse proxies, the user they set in the `Remote-User`
+    /// header is recorded in the audit log. The header is ignored on all other requests.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Site {
+    /// Substituters agents of this site copy closures from, in order of preference.
+    ///
+    /// Any store url supported by `nix copy --from` can be used, eg. `https://cache.dc1.example`
+    /// or `s3://nix-cache?region=eu-central-1`.
+    pub substituters: Vec<String>,
+}
+
+impl Config {
+    /// Returns the ordered list of substituters for agents of `site`.
+    ///
+    /// The nxy server itself is always appended as last fallback. Agents without a site or with
+    /// an unknown site only use the nxy server.
+    pub fn substituters(&self, site: Option<&str>) -> Vec<String> {
+        let mut substituters = match site.map(|name| (name, self.sites.get(name))) {
+            Some((_, Some(site))) => site.substituters.clone(),
+            Some((name, None)) => {
+                tracing::warn!(site = name, "agent assigned to unknown site");
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        if !substituters.contains(&self.external_url) {
+            substituters.push(self.external_url.clone());
+        }
+        substituters
+    }
 }
 
 pub fn load_config(path: Option<String>) -> Config {
@@ -21,3 +60,43 @@ pub fn load_config(path: Option<String>) -> Config {
 fn default_external_url() -> String {
     String::from("http://localhost:8080")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substituters_of_sites() {
+        let config: Config = serde_json::from_value(json!({
+            "external_url": "https://nxy.example",
+            "sites": {
+                "dc1": { "substituters": ["https://cache.dc1.example", "s3://nix-cache"] },
+                "dc2": { "substituters": ["https://cache.dc2.example", "https://nxy.example"] },
+                "empty": { "substituters": [] }
+            }
+        }))
+        .unwrap();
+
+        let cases: [(Option<&str>, &[&str]); 5] = [
+            (None, &["https://nxy.example"]),
+            (Some("unknown"), &["https://nxy.example"]),
+            (Some("empty"), &["https://nxy.example"]),
+            (
+                Some("dc1"),
+                &[
+                    "https://cache.dc1.example",
+                    "s3://nix-cache",
+                    "https://nxy.example",
+                ],
+            ),
+            // the server isn't added twice, nor moved to the end
+            (
+                Some("dc2"),
+                &["https://cache.dc2.example", "https://nxy.example"],
+            ),
+        ];
+        for (site, expected) in cases {
+            assert_eq!(config.substituters(site), expected, "site {site:?}");
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Deployment steps on agents, run by the reconciler or by deployments started through the API.
+//!
+//! A step is a download or an activation of a store path. Every step is published as
+//! [`Event::Deployment`] when it starts and finishes, and its outcome is recorded in
+//! `agent_deployments`. The outcome of a deployment on every agent is kept in
+//! `deployment_agents`.
+
+use std::{path::PathBuf, sync::Arc};
+
+use color_eyre::Result;
+use futures_util::future::join_all;
+use nxy_common::{
+    api::{DeploymentPhase, Event},
+    types::ActivationMode,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{agent::AgentManager, reconcile::RunningGuard};
+
+pub(crate) fn started(
+    manager: &AgentManager,
+    deployment_id: Option<i64>,
+    agent_id: Uuid,
+    store_path: &str,
+    action: &str,
+) {
+    manager.publish(Event::Deployment {
+        agent_id,
+        store_path: store_path.to_string(),
+        action: action.to_string(),
+        phase: DeploymentPhase::Started,
+        error: None,
+        deployment_id,
+    });
+}
+
+/// Remember the outcome of a deployment step
+pub(crate) async fn record(
+    manager: &AgentManager,
+    db: &PgPool,
+    deployment_id: Option<i64>,
+    agent_id: Uuid,
+    store_path: &str,
+    action: &str,
+    result: &Result<()>,
+) -> Result<()> {
+    let error = result.as_ref().err().map(|err| format!("{err:#}"));
+    manager.publish(Event::Deployment {
+        agent_id,
+        store_path: store_path.to_string(),
+        action: action.to_string(),
+        phase: match error {
+            None => DeploymentPhase::Succeeded,
+            Some(_) => DeploymentPhase::Failed,
+        },
+        error: error.clone(),
+        deployment_id,
+    });
+    manager.metrics().record_deployment(action, error.is_none());
+    sqlx::query!(
+        "INSERT INTO agent_deployments (agent_id, store_path, action, error, deployment_id)
+        VALUES ($1, $2, $3, $4, $5)",
+        agent_id,
+        store_path,
+        action,
+        error,
+        deployment_id
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Download and activate a system on every agent of a deployment at once
+///
+/// Every agent is marked as running by its guard, released as soon as the agent is done.
+/// Marks the deployment finished and publishes [`Event::DeploymentFinished`] once all agents are
+/// done.
+pub(crate) async fn run(
+    manager: Arc<AgentManager>,
+    db: PgPool,
+    deployment_id: i64,
+    agents: Vec<(Uuid, String, RunningGuard)>,
+    mode: ActivationMode,
+) {
+    let total = agents.len();
+    let results = join_all(agents.into_iter().map(|(agent_id, store_path, guard)| {
+        let (manager, db) = (&manager, &db);
+        async move {
+            let result =
+                deploy_agent(manager, db, deployment_id, agent_id, &store_path, mode).await;
+            drop(guard);
+            if let Err(err) = finish_agent(db, deployment_id, agent_id, &result).await {
+                tracing::warn!(deployment_id, %agent_id, ?err, "failed to record deployment outcome");
+            }
+            (agent_id, result)
+        }
+    }))
+    .await;
+
+    let mut failed = 0;
+    for (agent_id, result) in results {
+        if let Err(err) = result {
+            tracing::warn!(deployment_id, %agent_id, ?err, "deployment failed");
+            failed += 1;
+        }
+    }
+    let finished = sqlx::query!(
+        "UPDATE deployments SET finished_at = now() WHERE deployment_id = $1",
+        deployment_id
+    )
+    .execute(&db)
+    .await;
+    if let Err(err) = finished {
+        tracing::warn!(deployment_id, ?err, "failed to mark deployment as finished");
+    }
+    manager.publish(Event::DeploymentFinished {
+        deployment_id,
+        agents: total,
+        failed,
+    });
+}
+
+async fn finish_agent(
+    db: &PgPool,
+    deployment_id: i64,
+    agent_id: Uuid,
+    result: &Result<()>,
+) -> Result<()> {
+    let (status, error) = match result {
+        Ok(()) => ("succeeded", None),
+        Err(err) => ("failed", Some(format!("{err:#}"))),
+    };
+    sqlx::query!(
+        "UPDATE deployment_agents SET status = $3, error = $4
+        WHERE deployment_id = $1 AND agent_id = $2",
+        deployment_id,
+        agent_id,
+        status,
+        error
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Fail deployments interrupted by a restart of the server
+pub(crate) async fn fail_interrupted(db: &PgPool) -> Result<()> {
+    let mut tx = db.begin().await?;
+    sqlx::query!(
+        "UPDATE deployment_agents SET status = 'failed', error = 'server restarted'
+        WHERE status = 'running'"
+    )
+    .execute(&mut tx)
+    .await?;
+    sqlx::query!("UPDATE deployments SET finished_at = now() WHERE finished_at IS NULL")
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn deploy_agent(
+    manager: &AgentManager,
+    db: &PgPool,
+    deployment_id: i64,
+    agent_id: Uuid,
+    store_path: &str,
+    mode: ActivationMode,
+) -> Result<()> {
+    let downloaded = sqlx::query_scalar!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM agent_store_paths WHERE agent_id = $1 AND store_path = $2
+        ) AS "downloaded!""#,
+        agent_id,
+        store_path
+    )
+    .fetch_one(db)
+    .await?;
+
+    if !downloaded {
+        started(
+            manager,
+            Some(deployment_id),
+            agent_id,
+            store_path,
+            "download",
+        );
+        let result = manager.download(agent_id, PathBuf::from(store_path)).await;
+        record(
+            manager,
+            db,
+            Some(deployment_id),
+            agent_id,
+            store_path,
+            "download",
+            &result,
+        )
+        .await?;
+        result?;
+    }
+
+    started(
+        manager,
+        Some(deployment_id),
+        agent_id,
+        store_path,
+        mode.as_str(),
+    );
+    let result = manager.activate(agent_id, store_path, mode).await;
+    record(
+        manager,
+        db,
+        Some(deployment_id),
+        agent_id,
+        store_path,
+        mode.as_str(),
+        &result,
+    )
+    .await?;
+    result
+}
@@ -0,0 +1,112 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path},
+    http::request::Parts,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::http::{error::Error, ApiContext, Result};
+
+/// The `:agent_id` path parameter, resolved to the id of an agent
+///
+/// Besides the id itself, the name of an agent or a unique prefix of its id are accepted.
+pub(crate) struct AgentId(pub(crate) Uuid);
+
+#[async_trait]
+impl FromRequestParts<ApiContext> for AgentId {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, ctx: &ApiContext) -> Result<Self> {
+        let Path(agent) = Path::<String>::from_request_parts(parts, ctx)
+            .await
+            .map_err(|_| Error::NotFound)?;
+        resolve(&ctx.db, &agent).await.map(AgentId)
+    }
+}
+
+/// Resolve an agent id, name or unique prefix of an id to the agent id
+///
+/// Ids are passed through unchecked, names take precedence over id prefixes.
+pub(crate) async fn resolve(db: &PgPool, agent: &str) -> Result<Uuid> {
+    if let Ok(agent_id) = agent.parse() {
+        return Ok(agent_id);
+    }
+
+    let rows = sqlx::query!(
+        "SELECT agent_id, name FROM agents
+        WHERE name = $1 OR starts_with(agent_id::text, lower($1))",
+        agent
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|row| (row.agent_id, row.name))
+    .collect::<Vec<_>>();
+    pick(agent, &rows)
+}
+
+/// Pick the agent meant by `agent` among the agents whose name or id prefix matches
+fn pick(agent: &str, matches: &[(Uuid, Option<String>)]) -> Result<Uuid> {
+    if let Some((agent_id, _)) = matches
+        .iter()
+        .find(|(_, name)| name.as_deref() == Some(agent))
+    {
+        return Ok(*agent_id);
+    }
+    match matches {
+        [(agent_id, _)] => Ok(*agent_id),
+        [] => Err(Error::NotFound),
+        _ => Err(Error::UnprocessableEntity(format!(
+            "agent {agent} is ambiguous, it matches {}",
+            matches
+                .iter()
+                .map(|(agent_id, _)| agent_id.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pick_agent() {
+        let agent = |id: &str, name: Option<&str>| {
+            (Uuid::parse_str(id).unwrap(), name.map(ToString::to_string))
+        };
+        let web = agent("ab12cd34-0000-0000-0000-000000000001", Some("web"));
+        let db = agent("ab12ef56-0000-0000-0000-000000000002", Some("db"));
+        // named like the id prefix of the others
+        let ab12 = agent("c0ffee00-0000-0000-0000-000000000003", Some("ab12"));
+        let unnamed = agent("ab12cd34-0000-0000-0000-000000000004", None);
+
+        let cases = [
+            ("web", vec![web.clone()], Some(web.0)),
+            (
+                "ab12cd34-0000-0000-0000-000000000004",
+                vec![unnamed.clone()],
+                Some(unnamed.0),
+            ),
+            ("ab12e", vec![db.clone()], Some(db.0)),
+            // names take precedence over id prefixes
+            (
+                "ab12",
+                vec![web.clone(), db.clone(), ab12.clone()],
+                Some(ab12.0),
+            ),
+            ("nope", vec![], None),
+        ];
+        for (name, matches, expected) in cases {
+            assert_eq!(pick(name, &matches).ok(), expected, "{name}");
+        }
+
+        assert!(matches!(pick("nope", &[]), Err(Error::NotFound)));
+        assert!(matches!(
+            pick("ab12", &[web, db, unnamed]),
+            Err(Error::UnprocessableEntity(_))
+        ));
+    }
+}
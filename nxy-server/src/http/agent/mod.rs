@@ -1,104 +1,798 @@
+mod id;
 mod websocket;
 
+use std::{future::Future, path::PathBuf};
+
 use axum::{
-    extract::{Path, State},
+    extract::{Query, State},
     routing::{get, post},
     Json, Router,
 };
-use serde::{Deserialize, Serialize};
+use futures_util::future::join_all;
+use nxy_common::{
+    api::{
+        Activate, Agent, AgentDetails, AgentFilter, AgentResult, AgentUnits, DeployPolicy,
+        Deployment, DownloadStorePath, Pin, Reboot, Rollback, RolledBack, Selected,
+        SelectedActivate, SelectedDownloadStorePath, SelectedReboot, SelectedRollback,
+        SetConfiguration, SetDeployPolicy, SetLabels, SetName, SetSite,
+    },
+    labels::{self as label, Selector},
+    methods,
+    types::{ActivationMode, DiskUsage, Facts, RebootWindow, UnitStatus, Units},
+    Method,
+};
+use sqlx::types::Json as DbJson;
+use utoipa::OpenApi;
 use uuid::Uuid;
 
-use super::{ApiContext, Result};
+use crate::{agent::RpcError, labels};
+
+use super::{
+    error::{Error, ResultExt},
+    nixos_configuration::resolve_revision,
+    ApiContext, Result,
+};
+
+pub(crate) use id::{resolve, AgentId};
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        get_agents,
+        get_agent,
+        set_configuration,
+        download_store_path,
+        activate,
+        rollback,
+        reboot,
+        get_units,
+        get_deployments,
+        set_site,
+        set_deploy_policy,
+        pin_agent,
+        set_labels,
+        set_name,
+        download_selected,
+        activate_selected,
+        rollback_selected,
+        reboot_selected
+    ),
+    components(schemas(
+        Agent,
+        AgentDetails,
+        AgentUnits,
+        Deployment,
+        SetConfiguration,
+        SetSite,
+        SetDeployPolicy,
+        DeployPolicy,
+        SetLabels,
+        SetName,
+        AgentResult,
+        DownloadStorePath,
+        SelectedDownloadStorePath,
+        SelectedActivate,
+        SelectedRollback,
+        SelectedReboot,
+        Activate,
+        ActivationMode,
+        Rollback,
+        RolledBack,
+        Reboot,
+        RebootWindow,
+        Facts,
+        DiskUsage,
+        UnitStatus
+    ))
+)]
+pub(super) struct ApiDoc;
 
 pub(crate) fn router() -> Router<ApiContext> {
     Router::new()
         .route("/api/v1/agent", get(get_agents))
         .route("/api/v1/agent/ws", get(websocket::ws_handler))
-        .route("/api/v1/agent/:agent_id", post(set_configuration))
+        .route("/api/v1/agent/download", post(download_selected))
+        .route("/api/v1/agent/activate", post(activate_selected))
+        .route("/api/v1/agent/rollback", post(rollback_selected))
+        .route("/api/v1/agent/reboot", post(reboot_selected))
+        .route(
+            "/api/v1/agent/:agent_id",
+            get(get_agent).post(set_configuration),
+        )
         .route(
             "/api/v1/agent/:agent_id/download",
             post(download_store_path),
         )
         .route("/api/v1/agent/:agent_id/activate", post(activate))
+        .route("/api/v1/agent/:agent_id/rollback", post(rollback))
+        .route("/api/v1/agent/:agent_id/reboot", post(reboot))
+        .route("/api/v1/agent/:agent_id/units", get(get_units))
+        .route("/api/v1/agent/:agent_id/deployments", get(get_deployments))
+        .route("/api/v1/agent/:agent_id/site", post(set_site))
+        .route(
+            "/api/v1/agent/:agent_id/deploy-policy",
+            post(set_deploy_policy),
+        )
+        .route("/api/v1/agent/:agent_id/pin", post(pin_agent))
+        .route("/api/v1/agent/:agent_id/labels", post(set_labels))
+        .route("/api/v1/agent/:agent_id/name", post(set_name))
 }
 
-#[derive(Serialize)]
-struct Agent {
-    id: Uuid,
-    current_system: Option<String>,
-}
-
-async fn get_agents(ctx: State<ApiContext>) -> Result<Json<Vec<Agent>>> {
-    let agents = sqlx::query!("SELECT agent_id, current_system FROM agents")
-        .fetch_all(&ctx.db)
-        .await?
-        .into_iter()
-        .map(|row| Agent {
+/// List all agents
+#[utoipa::path(
+    get,
+    path = "/api/v1/agent",
+    params(AgentFilter),
+    responses((status = 200, body = [Agent]))
+)]
+async fn get_agents(
+    ctx: State<ApiContext>,
+    Query(filter): Query<AgentFilter>,
+) -> Result<Json<Vec<Agent>>> {
+    let mut labels = labels::all(&ctx.db).await?;
+    let mut agents: Vec<Agent> = sqlx::query!(
+        "SELECT agent_id, name, current_system, site, reboot_required, drifted, deploy_policy
+        FROM agents
+        WHERE NOT $1 OR cardinality(reboot_required) > 0",
+        filter.reboot_required
+    )
+    .fetch_all(&ctx.db)
+    .await?
+    .into_iter()
+    .map(|row| {
+        Ok(Agent {
             id: row.agent_id,
+            name: row.name,
             current_system: row.current_system,
+            site: row.site,
+            reboot_required: row.reboot_required,
+            drifted: row.drifted,
+            deploy_policy: row.deploy_policy.parse()?,
+            connected: ctx.agent_manager.get(row.agent_id).is_some(),
+            labels: labels.remove(&row.agent_id).unwrap_or_default(),
         })
-        .collect();
+    })
+    .collect::<color_eyre::Result<_>>()?;
+    if let Some(selector) = filter.selector {
+        agents.retain(|agent| selector.matches(&agent.labels));
+    }
 
     Ok(Json(agents))
 }
 
-#[derive(Deserialize)]
-struct SetConfiguration {
-    config_id: i64,
+/// Show an agent with its last reported facts
+#[utoipa::path(
+    get,
+    path = "/api/v1/agent/{agent_id}",
+    params(("agent_id" = String, Path, description = "Agent id, name or id prefix")),
+    responses((status = 200, body = AgentDetails), (status = 404, body = ErrorBody))
+)]
+async fn get_agent(
+    ctx: State<ApiContext>,
+    AgentId(agent_id): AgentId,
+) -> Result<Json<AgentDetails>> {
+    let row = sqlx::query!(
+        r#"
+        SELECT agent_id, name, current_system, site, reboot_required, drifted,
+            r.revision AS "pinned_revision?",
+            f.facts AS "facts?: DbJson<Facts>", f.updated_at AS "facts_updated_at?"
+        FROM agents
+        LEFT JOIN agent_facts AS f USING (agent_id)
+        LEFT JOIN flake_revisions AS r ON r.flake_revision_id = pinned_flake_revision_id
+        WHERE agent_id = $1
+        "#,
+        agent_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+    let labels = labels::of(&ctx.db, agent_id).await?;
+
+    Ok(Json(AgentDetails {
+        id: row.agent_id,
+        name: row.name,
+        current_system: row.current_system,
+        site: row.site,
+        reboot_required: row.reboot_required,
+        drifted: row.drifted,
+        pinned_revision: row.pinned_revision,
+        connected: ctx.agent_manager.get(agent_id).is_some(),
+        labels,
+        facts: row.facts.map(|facts| facts.0),
+        facts_updated_at: row.facts_updated_at,
+    }))
+}
+
+/// Last reported systemd unit states of an agent
+#[utoipa::path(
+    get,
+    path = "/api/v1/agent/{agent_id}/units",
+    params(("agent_id" = String, Path, description = "Agent id, name or id prefix")),
+    responses(
+        (status = 200, body = AgentUnits),
+        (status = 404, description = "Agent never reported its units", body = ErrorBody)
+    )
+)]
+async fn get_units(ctx: State<ApiContext>, AgentId(agent_id): AgentId) -> Result<Json<AgentUnits>> {
+    let row = sqlx::query!(
+        r#"SELECT units AS "units: DbJson<Units>", updated_at FROM agent_units WHERE agent_id = $1"#,
+        agent_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let DbJson(units) = row.units;
+    Ok(Json(AgentUnits {
+        failed: units.failed,
+        activated: units.activated,
+        updated_at: row.updated_at,
+    }))
 }
 
+/// Deployment steps the reconciler ran on an agent, newest first
+#[utoipa::path(
+    get,
+    path = "/api/v1/agent/{agent_id}/deployments",
+    params(("agent_id" = String, Path, description = "Agent id, name or id prefix")),
+    responses((status = 200, body = [Deployment]))
+)]
+async fn get_deployments(
+    ctx: State<ApiContext>,
+    AgentId(agent_id): AgentId,
+) -> Result<Json<Vec<Deployment>>> {
+    let deployments = sqlx::query_as!(
+        Deployment,
+        "SELECT store_path, action, error, created_at
+        FROM agent_deployments
+        WHERE agent_id = $1
+        ORDER BY agent_deployment_id DESC
+        LIMIT 100",
+        agent_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(deployments))
+}
+
+/// Assign a configuration to an agent
+#[utoipa::path(
+    post,
+    path = "/api/v1/agent/{agent_id}",
+    params(("agent_id" = String, Path, description = "Agent id, name or id prefix")),
+    request_body = SetConfiguration,
+    responses(
+        (status = 200),
+        (status = 404, description = "Agent not found", body = ErrorBody),
+        (status = 422, description = "Unknown configuration", body = ErrorBody)
+    )
+)]
 async fn set_configuration(
     ctx: State<ApiContext>,
-    Path(agent): Path<Uuid>,
+    AgentId(agent): AgentId,
     Json(req): Json<SetConfiguration>,
 ) -> Result<()> {
-    sqlx::query!(
+    let updated = sqlx::query!(
         "UPDATE agents SET nixos_configuration_id = $1 WHERE agent_id = $2",
         req.config_id,
         agent
     )
     .execute(&ctx.db)
+    .await
+    .on_constraint("agents_nixos_configuration_id_fkey", |_| {
+        Error::UnprocessableEntity(format!("configuration {} doesn't exist", req.config_id))
+    })?;
+    if updated.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    ctx.agent_manager.trigger_reconcile();
+    Ok(())
+}
+
+/// Assign an agent to a substituter site
+#[utoipa::path(
+    post,
+    path = "/api/v1/agent/{agent_id}/site",
+    params(("agent_id" = String, Path, description = "Agent id, name or id prefix")),
+    request_body = SetSite,
+    responses(
+        (status = 200),
+        (status = 404, description = "Agent not found", body = ErrorBody),
+        (status = 422, description = "Site not configured", body = ErrorBody)
+    )
+)]
+async fn set_site(
+    ctx: State<ApiContext>,
+    AgentId(agent): AgentId,
+    Json(req): Json<SetSite>,
+) -> Result<()> {
+    if let Some(ref site) = req.site {
+        if !ctx.config.sites.contains_key(site) {
+            return Err(Error::UnprocessableEntity(format!(
+                "site {site} isn't configured"
+            )));
+        }
+    }
+    let updated = sqlx::query!(
+        "UPDATE agents SET site = $1 WHERE agent_id = $2",
+        req.site,
+        agent
+    )
+    .execute(&ctx.db)
+    .await?;
+    if updated.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+    Ok(())
+}
+
+/// Change how far the reconciler may go to deploy an agent
+#[utoipa::path(
+    post,
+    path = "/api/v1/agent/{agent_id}/deploy-policy",
+    params(("agent_id" = String, Path, description = "Agent id, name or id prefix")),
+    request_body = SetDeployPolicy,
+    responses((status = 200), (status = 404, body = ErrorBody))
+)]
+async fn set_deploy_policy(
+    ctx: State<ApiContext>,
+    AgentId(agent): AgentId,
+    Json(req): Json<SetDeployPolicy>,
+) -> Result<()> {
+    let updated = sqlx::query!(
+        "UPDATE agents SET deploy_policy = $1 WHERE agent_id = $2",
+        req.policy.as_str(),
+        agent
+    )
+    .execute(&ctx.db)
+    .await?;
+    if updated.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    ctx.agent_manager.trigger_reconcile();
+    Ok(())
+}
+
+/// Pin an agent to a flake revision, or unpin it
+#[utoipa::path(
+    post,
+    path = "/api/v1/agent/{agent_id}/pin",
+    params(("agent_id" = String, Path, description = "Agent id, name or id prefix")),
+    request_body = Pin,
+    responses(
+        (status = 200),
+        (status = 404, body = ErrorBody),
+        (status = 422, description = "Revision unknown or ambiguous", body = ErrorBody)
+    )
+)]
+async fn pin_agent(
+    ctx: State<ApiContext>,
+    AgentId(agent_id): AgentId,
+    Json(req): Json<Pin>,
+) -> Result<()> {
+    let flake_revision_id = match req.revision {
+        Some(ref revision) => {
+            let config_id = sqlx::query_scalar!(
+                "SELECT nixos_configuration_id FROM agents WHERE agent_id = $1",
+                agent_id
+            )
+            .fetch_optional(&ctx.db)
+            .await?
+            .ok_or(Error::NotFound)?
+            .ok_or_else(|| {
+                Error::UnprocessableEntity(format!("agent {agent_id} has no configuration"))
+            })?;
+            Some(resolve_revision(&ctx.db, config_id, revision).await?)
+        }
+        None => None,
+    };
+
+    let updated = sqlx::query!(
+        "UPDATE agents SET pinned_flake_revision_id = $1 WHERE agent_id = $2",
+        flake_revision_id,
+        agent_id
+    )
+    .execute(&ctx.db)
     .await?;
+    if updated.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    ctx.agent_manager.trigger_reconcile();
+    Ok(())
+}
+
+/// Set or remove labels of an agent
+#[utoipa::path(
+    post,
+    path = "/api/v1/agent/{agent_id}/labels",
+    params(("agent_id" = String, Path, description = "Agent id, name or id prefix")),
+    request_body = SetLabels,
+    responses(
+        (status = 200),
+        (status = 404, body = ErrorBody),
+        (status = 422, description = "Invalid label key or value", body = ErrorBody)
+    )
+)]
+async fn set_labels(
+    ctx: State<ApiContext>,
+    AgentId(agent_id): AgentId,
+    Json(req): Json<SetLabels>,
+) -> Result<()> {
+    for (key, value) in &req.set {
+        label::validate_key(key)
+            .and_then(|_| label::validate_value(key, value))
+            .map_err(|err| Error::UnprocessableEntity(err.to_string()))?;
+    }
+
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM agents WHERE agent_id = $1) AS "exists!""#,
+        agent_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+    if !exists {
+        return Err(Error::NotFound);
+    }
+
+    labels::update(&ctx.db, agent_id, &req.set, &req.remove).await?;
+    Ok(())
+}
+
+/// Rename an agent, or name it after its hostname again
+#[utoipa::path(
+    post,
+    path = "/api/v1/agent/{agent_id}/name",
+    params(("agent_id" = String, Path, description = "Agent id, name or id prefix")),
+    request_body = SetName,
+    responses(
+        (status = 200),
+        (status = 404, body = ErrorBody),
+        (status = 409, description = "Name used by another agent", body = ErrorBody),
+        (status = 422, description = "Invalid name", body = ErrorBody)
+    )
+)]
+async fn set_name(
+    ctx: State<ApiContext>,
+    AgentId(agent_id): AgentId,
+    Json(req): Json<SetName>,
+) -> Result<()> {
+    let Some(name) = req.name else {
+        let hostname = sqlx::query_scalar!(
+            r#"SELECT facts->>'hostname' AS "hostname?"
+            FROM agents LEFT JOIN agent_facts USING (agent_id)
+            WHERE agent_id = $1"#,
+            agent_id
+        )
+        .fetch_optional(&ctx.db)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+        sqlx::query!(
+            "UPDATE agents SET name = NULL, name_overridden = false WHERE agent_id = $1",
+            agent_id
+        )
+        .execute(&ctx.db)
+        .await?;
+        if let Some(hostname) = hostname {
+            ctx.agent_manager
+                .name_after_hostname(agent_id, &hostname)
+                .await?;
+        }
+        return Ok(());
+    };
+
+    validate_name(&name)?;
+    let updated = sqlx::query!(
+        "UPDATE agents SET name = $2, name_overridden = true WHERE agent_id = $1",
+        agent_id,
+        name
+    )
+    .execute(&ctx.db)
+    .await
+    .on_constraint("agents_name_key", |_| {
+        Error::Conflict(format!("name {name} is used by another agent"))
+    })?;
+    if updated.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
     Ok(())
 }
 
-#[derive(Deserialize)]
-struct DownloadStorePath {
-    store_path: String,
+fn validate_name(name: &str) -> Result<()> {
+    // names are accepted wherever an id is
+    if name.parse::<Uuid>().is_ok() {
+        return Err(Error::UnprocessableEntity(format!(
+            "invalid name {name:?}, names can't be agent ids"
+        )));
+    }
+    let valid = !name.is_empty()
+        && name.len() <= 63
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::UnprocessableEntity(format!(
+            "invalid name {name:?}, allowed are up to 63 alphanumerics, '-', '_' and '.'"
+        )))
+    }
 }
 
+/// Copy a store path to an agent
+#[utoipa::path(
+    post,
+    path = "/api/v1/agent/{agent_id}/download",
+    params(("agent_id" = String, Path, description = "Agent id, name or id prefix")),
+    request_body = DownloadStorePath,
+    responses(
+        (status = 200),
+        (status = 503, description = "Agent is not connected", body = ErrorBody),
+        (status = 504, description = "Agent didn't answer in time", body = ErrorBody),
+        (status = 502, description = "Agent answered with an error", body = ErrorBody)
+    )
+)]
 async fn download_store_path(
     ctx: State<ApiContext>,
-    Path(agent_id): Path<Uuid>,
+    AgentId(agent_id): AgentId,
     Json(req): Json<DownloadStorePath>,
 ) -> Result<()> {
-    let agent = ctx.agent_manager.get(agent_id).unwrap();
+    ctx.agent_manager
+        .download(agent_id, req.store_path.into())
+        .await
+        .map_err(Into::into)
+}
+
+/// Activate a system on an agent
+#[utoipa::path(
+    post,
+    path = "/api/v1/agent/{agent_id}/activate",
+    params(("agent_id" = String, Path, description = "Agent id, name or id prefix")),
+    request_body = Activate,
+    responses(
+        (status = 200),
+        (status = 422, description = "Rollbacks or activation mode not supported", body = ErrorBody),
+        (status = 503, description = "Agent is not connected", body = ErrorBody),
+        (status = 504, description = "Agent didn't answer in time", body = ErrorBody),
+        (status = 502, description = "Agent answered with an error", body = ErrorBody)
+    )
+)]
+async fn activate(
+    ctx: State<ApiContext>,
+    AgentId(agent_id): AgentId,
+    Json(req): Json<Activate>,
+) -> Result<()> {
+    activate_agent(&ctx, agent_id, &req).await
+}
+
+async fn activate_agent(ctx: &ApiContext, agent_id: Uuid, req: &Activate) -> Result<()> {
+    let agent = ctx
+        .agent_manager
+        .get(agent_id)
+        .ok_or(RpcError::NotConnected(agent_id))?;
+    if !agent.supports_activation_mode(req.mode) {
+        return Err(Error::UnprocessableEntity(format!(
+            "agent doesn't support activation mode {}",
+            req.mode
+        )));
+    }
 
     agent
-        .download(nxy_common::types::DownloadParams {
-            store_path: req.store_path.into(),
-            from: ctx.config.external_url.clone(),
+        .call::<methods::Activate>(nxy_common::types::ActivateParams {
+            store_path: req.store_path.clone().into(),
+            mode: req.mode,
         })
-        .await
-        .map_err(Into::into)
+        .await?;
+
+    ctx.agent_manager.refresh_status(agent_id).await?;
+    Ok(())
 }
 
-#[derive(Deserialize)]
-struct ActivateParams {
-    store_path: String,
+/// Activate the previous system generation of an agent
+#[utoipa::path(
+    post,
+    path = "/api/v1/agent/{agent_id}/rollback",
+    params(("agent_id" = String, Path, description = "Agent id, name or id prefix")),
+    request_body = Rollback,
+    responses(
+        (status = 200, body = RolledBack),
+        (status = 422, description = "Rollbacks or activation mode not supported", body = ErrorBody),
+        (status = 503, description = "Agent is not connected", body = ErrorBody),
+        (status = 504, description = "Agent didn't answer in time", body = ErrorBody),
+        (status = 502, description = "Agent answered with an error", body = ErrorBody)
+    )
+)]
+async fn rollback(
+    ctx: State<ApiContext>,
+    AgentId(agent_id): AgentId,
+    Json(req): Json<Rollback>,
+) -> Result<Json<RolledBack>> {
+    let store_path = rollback_agent(&ctx, agent_id, &req).await?;
+    Ok(Json(RolledBack { store_path }))
 }
 
-async fn activate(
+/// Returns the system the agent rolled back to
+async fn rollback_agent(ctx: &ApiContext, agent_id: Uuid, req: &Rollback) -> Result<PathBuf> {
+    let agent = ctx
+        .agent_manager
+        .get(agent_id)
+        .ok_or(RpcError::NotConnected(agent_id))?;
+    if !agent.supports(methods::Rollback::NAME) {
+        return Err(Error::UnprocessableEntity(
+            "agent doesn't support rollbacks".to_string(),
+        ));
+    }
+    if !agent.supports_activation_mode(req.mode) {
+        return Err(Error::UnprocessableEntity(format!(
+            "agent doesn't support activation mode {}",
+            req.mode
+        )));
+    }
+
+    let store_path = agent
+        .call::<methods::Rollback>(nxy_common::types::RollbackParams { mode: req.mode })
+        .await?;
+    ctx.agent_manager.refresh_status(agent_id).await?;
+
+    Ok(store_path)
+}
+
+/// Reboot an agent now, after a delay or in a maintenance window
+#[utoipa::path(
+    post,
+    path = "/api/v1/agent/{agent_id}/reboot",
+    params(("agent_id" = String, Path, description = "Agent id, name or id prefix")),
+    request_body = Reboot,
+    responses(
+        (status = 200),
+        (status = 422, description = "Reboots not supported", body = ErrorBody),
+        (status = 503, description = "Agent is not connected", body = ErrorBody),
+        (status = 504, description = "Agent didn't answer in time", body = ErrorBody),
+        (status = 502, description = "Agent answered with an error", body = ErrorBody)
+    )
+)]
+async fn reboot(
     ctx: State<ApiContext>,
-    Path(agent_id): Path<Uuid>,
-    Json(req): Json<ActivateParams>,
+    AgentId(agent_id): AgentId,
+    Json(req): Json<Reboot>,
 ) -> Result<()> {
-    let agent = ctx.agent_manager.get(agent_id).unwrap();
+    reboot_agent(&ctx, agent_id, req).await
+}
+
+async fn reboot_agent(ctx: &ApiContext, agent_id: Uuid, req: Reboot) -> Result<()> {
+    let agent = ctx
+        .agent_manager
+        .get(agent_id)
+        .ok_or(RpcError::NotConnected(agent_id))?;
+    if !agent.supports(methods::Reboot::NAME) {
+        return Err(Error::UnprocessableEntity(
+            "agent doesn't support reboots".to_string(),
+        ));
+    }
 
     agent
-        .activate(nxy_common::types::ActivateParams {
-            store_path: req.store_path.into(),
+        .call::<methods::Reboot>(nxy_common::types::RebootParams {
+            delay: req.delay,
+            window: req.window,
         })
         .await
         .map_err(Into::into)
 }
+
+/// Copy a store path to every agent matching a selector
+#[utoipa::path(
+    post,
+    path = "/api/v1/agent/download",
+    request_body = SelectedDownloadStorePath,
+    responses((status = 200, description = "Outcome per matching agent", body = [AgentResult]))
+)]
+async fn download_selected(
+    State(ctx): State<ApiContext>,
+    Json(Selected { selector, request }): Json<Selected<DownloadStorePath>>,
+) -> Result<Json<Vec<AgentResult>>> {
+    let (ctx, request) = (&ctx, &request);
+    for_selected(ctx, &selector, |agent_id| async move {
+        ctx.agent_manager
+            .download(agent_id, request.store_path.clone().into())
+            .await?;
+        Ok(None)
+    })
+    .await
+}
+
+/// Activate a system on every agent matching a selector
+#[utoipa::path(
+    post,
+    path = "/api/v1/agent/activate",
+    request_body = SelectedActivate,
+    responses((status = 200, description = "Outcome per matching agent", body = [AgentResult]))
+)]
+async fn activate_selected(
+    State(ctx): State<ApiContext>,
+    Json(Selected { selector, request }): Json<Selected<Activate>>,
+) -> Result<Json<Vec<AgentResult>>> {
+    let (ctx, request) = (&ctx, &request);
+    for_selected(ctx, &selector, |agent_id| async move {
+        activate_agent(ctx, agent_id, request).await?;
+        Ok(None)
+    })
+    .await
+}
+
+/// Activate the previous system generation of every agent matching a selector
+#[utoipa::path(
+    post,
+    path = "/api/v1/agent/rollback",
+    request_body = SelectedRollback,
+    responses((status = 200, description = "Outcome per matching agent", body = [AgentResult]))
+)]
+async fn rollback_selected(
+    State(ctx): State<ApiContext>,
+    Json(Selected { selector, request }): Json<Selected<Rollback>>,
+) -> Result<Json<Vec<AgentResult>>> {
+    let (ctx, request) = (&ctx, &request);
+    for_selected(ctx, &selector, |agent_id| async move {
+        let store_path = rollback_agent(ctx, agent_id, request).await?;
+        Ok(Some(store_path.to_string_lossy().into_owned()))
+    })
+    .await
+}
+
+/// Reboot every agent matching a selector
+#[utoipa::path(
+    post,
+    path = "/api/v1/agent/reboot",
+    request_body = SelectedReboot,
+    responses((status = 200, description = "Outcome per matching agent", body = [AgentResult]))
+)]
+async fn reboot_selected(
+    State(ctx): State<ApiContext>,
+    Json(Selected { selector, request }): Json<Selected<Reboot>>,
+) -> Result<Json<Vec<AgentResult>>> {
+    let (ctx, request) = (&ctx, &request);
+    for_selected(ctx, &selector, |agent_id| async move {
+        reboot_agent(ctx, agent_id, request.clone()).await?;
+        Ok(None)
+    })
+    .await
+}
+
+/// Send a request to all agents matching `selector` at once
+///
+/// A failing agent doesn't fail the whole request, its error is reported in its result.
+async fn for_selected<F, Fut>(
+    ctx: &ApiContext,
+    selector: &Selector,
+    f: F,
+) -> Result<Json<Vec<AgentResult>>>
+where
+    F: Fn(Uuid) -> Fut,
+    Fut: Future<Output = Result<Option<String>>>,
+{
+    let agents = labels::select(&ctx.db, selector).await?;
+    let results = join_all(agents.into_iter().map(|agent_id| {
+        let request = f(agent_id);
+        async move {
+            let (store_path, error) = match request.await {
+                Ok(store_path) => (store_path, None),
+                Err(err) => {
+                    tracing::warn!(%agent_id, %err, "request to selected agent failed");
+                    (None, Some(err.body()))
+                }
+            };
+            AgentResult {
+                agent_id,
+                store_path,
+                error,
+            }
+        }
+    }))
+    .await;
+
+    Ok(Json(results))
+}
@@ -1,6 +1,8 @@
+use std::sync::Arc;
+
 use axum::{
     extract::{
-        ws::{Message, WebSocket},
+        ws::{Message as WsMessage, WebSocket},
         State, WebSocketUpgrade,
     },
     headers,
@@ -11,11 +13,11 @@ use futures_util::{
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
 };
-use nxy_common::{ErrorCode, JsonRPC, Response};
+use nxy_common::{JsonRPC, Message};
 use tokio::sync::mpsc;
 use tracing::instrument;
 
-use crate::{agent::Agent, http::ApiContext};
+use crate::{agent::Agent, http::ApiContext, metrics::Metrics};
 
 #[instrument(skip_all)]
 pub(super) async fn ws_handler(
@@ -32,71 +34,107 @@ pub(super) async fn ws_handler(
 
 #[instrument(skip_all)]
 async fn handle_socket(socket: WebSocket, ctx: State<ApiContext>) {
+    let metrics = Arc::clone(ctx.agent_manager.metrics());
+    metrics.websocket_connections.inc();
+
     let (inbox_sender, inbox) = mpsc::channel(4096);
     let (outbox, outbox_receiver) = mpsc::channel(4096);
     let (sink, stream) = socket.split();
-    let inbox_handler = tokio::spawn(process_inbox(stream, inbox_sender));
-    let outbox_handler = tokio::spawn(process_outbox(sink, outbox_receiver));
+    let inbox_handler = tokio::spawn(process_inbox(
+        stream,
+        inbox_sender,
+        outbox.clone(),
+        Arc::clone(&metrics),
+    ));
+    let outbox_handler = tokio::spawn(process_outbox(sink, outbox_receiver, Arc::clone(&metrics)));
+
+    let (agent, notifications) = Agent::new(inbox, outbox, Arc::clone(&metrics));
+    match ctx.agent_manager.add_agent(agent.clone()).await {
+        Ok(agent_id) => {
+            let agent_manager = Arc::clone(&ctx.agent_manager);
+            tokio::spawn(async move {
+                agent_manager
+                    .process_notifications(agent_id, notifications)
+                    .await
+            });
 
-    let agent = Agent::new(inbox, outbox);
-    ctx.agent_manager.add_agent(agent).await.unwrap();
+            inbox_handler.await.unwrap();
+            ctx.agent_manager.remove_agent(agent_id, &agent);
+        }
+        Err(err) => {
+            tracing::error!(?err, "failed to register agent, closing connection");
+            inbox_handler.abort();
+        }
+    }
 
-    inbox_handler.await.unwrap();
-    outbox_handler.await.unwrap();
+    // the agent might still be referenced elsewhere, keeping the outbox open
+    outbox_handler.abort();
+    metrics.websocket_connections.dec();
 }
 
 #[instrument(skip_all)]
 async fn process_outbox(
-    mut sink: SplitSink<WebSocket, Message>,
-    mut outbox_receiver: mpsc::Receiver<JsonRPC>,
+    mut sink: SplitSink<WebSocket, WsMessage>,
+    mut outbox_receiver: mpsc::Receiver<Message>,
+    metrics: Arc<Metrics>,
 ) {
     while let Some(msg) = outbox_receiver.recv().await {
-        if let Err(err) = sink.send(Message::Text(msg.to_string())).await {
+        if let Err(err) = sink.send(WsMessage::Text(msg.to_string())).await {
             tracing::warn!(?err, "connection closed");
             return;
         };
+        metrics.record_websocket_message("sent");
     }
 }
 
 #[instrument(skip_all)]
-async fn process_inbox(mut stream: SplitStream<WebSocket>, tx: mpsc::Sender<JsonRPC>) {
+async fn process_inbox(
+    mut stream: SplitStream<WebSocket>,
+    tx: mpsc::Sender<JsonRPC>,
+    outbox: mpsc::Sender<Message>,
+    metrics: Arc<Metrics>,
+) {
     while let Some(Ok(msg)) = stream.next().await {
         match msg {
-            Message::Text(t) => {
+            WsMessage::Text(t) => {
+                metrics.record_websocket_message("received");
                 tracing::debug!("client sent str: {:?}", t);
-                let msg = match t.parse() {
-                    Ok(rpc) => rpc,
-                    Err(err) => {
-                        // compiler needs a little help with the type signature
-                        let err: nxy_common::error::Error = err;
-                        Response::new_err(
-                            // we don't have a request id in this case, the standard allow
-                            // that the request id is empty in this case, but our
-                            // implementation doesn't support this.
-                            u64::MAX.into(),
-                            ErrorCode::ParseError as i32,
-                            err.to_string(),
-                        )
-                        .into()
+                let mut msgs = Vec::new();
+                let replies = Message::parse(&t).filter_map(|msg| match msg {
+                    Ok(msg) => {
+                        msgs.push(msg);
+                        None
+                    }
+                    Err(res) => {
+                        tracing::warn!(error = ?res.error, "client sent invalid message");
+                        Some(res.into())
+                    }
+                });
+
+                for msg in msgs {
+                    if let Err(err) = tx.send(msg).await {
+                        tracing::warn!(
+                            ?err,
+                            "error sending incomming msg to agent, closing connection"
+                        );
+                        return;
+                    };
+                }
+                if let Some(replies) = replies {
+                    if outbox.send(replies).await.is_err() {
+                        break;
                     }
-                };
-                if let Err(err) = tx.send(msg).await {
-                    tracing::warn!(
-                        ?err,
-                        "error sending incomming msg to agent, closing connection"
-                    );
-                    break;
-                };
+                }
             }
-            Message::Binary(_) => {
+            WsMessage::Binary(_) => {
                 tracing::warn!(
                     "client sent binary data, this is not supported. Closing connection"
                 );
                 break;
             }
             // ignore ping and pong axum handles this for us
-            Message::Ping(_) | Message::Pong(_) => {}
-            Message::Close(_) => {
+            WsMessage::Ping(_) | WsMessage::Pong(_) => {}
+            WsMessage::Close(_) => {
                 break;
             }
         }
@@ -0,0 +1,218 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    body::{self, Body, Full},
+    extract::{ConnectInfo, MatchedPath, Path, Query, State},
+    http::{HeaderMap, Method, Request},
+    middleware::Next,
+    response::Response,
+    routing::get,
+    Json, Router,
+};
+use nxy_common::api::{AuditEntry, AuditFilter, ErrorBody};
+use sha2::{Digest, Sha256};
+use utoipa::OpenApi;
+
+use crate::audit::{self, Entry};
+
+use super::{agent, ApiContext, Result};
+
+/// Entries returned if the filter doesn't set a limit
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// Header set by an authenticating reverse proxy in front of the server, see
+/// [`Config::trusted_proxies`](crate::config::Config::trusted_proxies)
+const REMOTE_USER: &str = "remote-user";
+
+#[derive(OpenApi)]
+#[openapi(paths(get_audit), components(schemas(AuditEntry)))]
+pub(super) struct ApiDoc;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new().route("/api/v1/audit", get(get_audit))
+}
+
+/// Audit log entries, newest first
+#[utoipa::path(
+    get,
+    path = "/api/v1/audit",
+    params(AuditFilter),
+    responses((status = 200, body = [AuditEntry]))
+)]
+async fn get_audit(
+    ctx: State<ApiContext>,
+    Query(filter): Query<AuditFilter>,
+) -> Result<Json<Vec<AuditEntry>>> {
+    let limit = filter.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let entries = sqlx::query_as!(
+        AuditEntry,
+        r#"
+        SELECT audit_log_id AS id, created_at, actor, action, target, parameters, source_ip,
+            status, outcome, error
+        FROM audit_log
+        WHERE ($1::text IS NULL OR actor = $1)
+            AND ($2::text IS NULL OR starts_with(action, $2))
+            AND ($3::text IS NULL OR target = $3)
+            AND ($4::text IS NULL OR outcome = $4)
+            AND ($5::timestamptz IS NULL OR created_at >= $5)
+            AND ($6::timestamptz IS NULL OR created_at < $6)
+        ORDER BY audit_log_id DESC
+        LIMIT $7
+        "#,
+        filter.actor,
+        filter.action,
+        filter.target,
+        filter.outcome,
+        filter.since,
+        filter.until,
+        limit
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(entries))
+}
+
+/// Record every mutating request in the audit log
+///
+/// Read-only requests pass through untouched. The request body is logged as parameters, the
+/// message of the error body as error.
+pub(super) async fn record_requests(
+    State(ctx): State<ApiContext>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    matched_path: Option<MatchedPath>,
+    path_params: Option<Path<HashMap<String, String>>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return next.run(request).await;
+    }
+
+    let route = match matched_path {
+        Some(ref path) => path.as_str().to_string(),
+        None => request.uri().path().to_string(),
+    };
+    let action = format!("{} {route}", request.method());
+    let source_ip = connect_info.map(|ConnectInfo(addr)| addr.ip());
+    let actor = actor(&ctx.config.trusted_proxies, source_ip, request.headers());
+    // before the request, it may rename the target
+    let target = match path_params {
+        Some(Path(params)) => target(&ctx, params).await,
+        None => None,
+    };
+
+    let (parts, body) = request.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => {
+            tracing::warn!(?err, "failed to read request body");
+            Default::default()
+        }
+    };
+    let parameters = serde_json::from_slice(&body).ok();
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    let status = response.status();
+    let (response, error) = if status.is_client_error() || status.is_server_error() {
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+        let error = match serde_json::from_slice::<ErrorBody>(&body) {
+            Ok(error) => error.message,
+            Err(_) => status.to_string(),
+        };
+        let response = Response::from_parts(parts, body::boxed(Full::from(body)));
+        (response, Some(error))
+    } else {
+        (response, None)
+    };
+
+    let entry = Entry {
+        actor,
+        action,
+        target,
+        parameters,
+        source_ip: source_ip.map(|ip| ip.to_string()),
+        status: Some(status.as_u16().into()),
+        error,
+    };
+    if let Err(err) = audit::record(&ctx.db, entry).await {
+        tracing::error!(?err, "failed to write audit log");
+    }
+    response
+}
+
+/// User authenticated by a reverse proxy, a fingerprint of the bearer token, or `anonymous`
+///
+/// The user is only taken from requests of `trusted_proxies`, anyone else could set it.
+fn actor(trusted_proxies: &[IpAddr], source_ip: Option<IpAddr>, headers: &HeaderMap) -> String {
+    let trusted = source_ip.map_or(false, |ip| trusted_proxies.contains(&ip));
+    let user = headers
+        .get(REMOTE_USER)
+        .filter(|_| trusted)
+        .and_then(|v| v.to_str().ok());
+    if let Some(user) = user {
+        return user.to_string();
+    }
+    let token = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match token {
+        // never store the token itself
+        Some(token) => {
+            let digest = format!("{:x}", Sha256::digest(token.as_bytes()));
+            format!("token:{}", &digest[..12])
+        }
+        None => "anonymous".to_string(),
+    }
+}
+
+/// `<kind>/<id>` of the object a route acts on, eg. `agent/<id>` for `:agent_id`
+///
+/// Agent names are resolved, so all entries of an agent share the same target.
+async fn target(ctx: &ApiContext, params: HashMap<String, String>) -> Option<String> {
+    let (name, value) = params.into_iter().next()?;
+    let value = match name.as_str() {
+        "agent_id" => match agent::resolve(&ctx.db, &value).await {
+            Ok(agent_id) => agent_id.to_string(),
+            Err(_) => value,
+        },
+        _ => value,
+    };
+    let kind = name.strip_suffix("_id").unwrap_or(&name);
+    Some(format!("{kind}/{value}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn remote_user_only_from_trusted_proxies() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let client: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(REMOTE_USER, HeaderValue::from_static("alice"));
+
+        assert_eq!(actor(&[proxy], Some(proxy), &headers), "alice");
+        assert_eq!(actor(&[proxy], Some(client), &headers), "anonymous");
+        assert_eq!(actor(&[], Some(proxy), &headers), "anonymous");
+        assert_eq!(actor(&[proxy], None, &headers), "anonymous");
+
+        headers.insert(
+            axum::http::header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer secret"),
+        );
+        assert!(actor(&[], Some(client), &headers).starts_with("token:"));
+    }
+}
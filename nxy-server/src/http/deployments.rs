@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, State},
+    routing::{get, post},
+    Json, Router,
+};
+use nxy_common::api::{AgentResult, Deploy, DeploymentAgent, DeploymentDetails, DeploymentStarted};
+use sqlx::{PgPool, Postgres, Transaction};
+use utoipa::OpenApi;
+use uuid::Uuid;
+
+use crate::{deploy, http::Result, labels};
+
+use super::{agent, error::Error, nixos_configuration::resolve_revision, ApiContext};
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(start_deployment, get_deployment),
+    components(schemas(Deploy, DeploymentStarted, DeploymentDetails, DeploymentAgent))
+)]
+pub(super) struct ApiDoc;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route("/api/v1/deployment", post(start_deployment))
+        .route("/api/v1/deployment/:deployment_id", get(get_deployment))
+}
+
+/// Download and activate the desired system on all agents of a configuration, on all agents
+/// matching a selector, or on a single agent
+///
+/// With a `revision`, the configuration, or every selected agent, is pinned to it, unless no agent
+/// is deployed to. Without one, existing pins are kept, see the `pin` endpoints to remove them.
+/// Pins of single agents take precedence over pins of their configuration.
+/// Agents without an evaluated system, or already being deployed to, are skipped, the others are
+/// deployed to in the background, see the `deployment` and `deployment-finished` events.
+#[utoipa::path(
+    post,
+    path = "/api/v1/deployment",
+    request_body = Deploy,
+    responses(
+        (status = 200, body = DeploymentStarted),
+        (status = 404, description = "Configuration or agent not found", body = ErrorBody),
+        (status = 422, description = "Invalid target or unknown revision", body = ErrorBody)
+    )
+)]
+async fn start_deployment(
+    State(ctx): State<ApiContext>,
+    Json(req): Json<Deploy>,
+) -> Result<Json<DeploymentStarted>> {
+    let (agents, mut skipped, pins) = match (&req.config, &req.selector, &req.agent) {
+        (Some(config), None, None) => {
+            let (agents, pins) =
+                resolve_configuration(&ctx.db, config, req.revision.as_deref()).await?;
+            (agents, Vec::new(), pins)
+        }
+        (None, Some(selector), None) => {
+            let agents = labels::select(&ctx.db, selector).await?;
+            resolve_agent_pins(&ctx.db, agents, req.revision.as_deref()).await?
+        }
+        (None, None, Some(agent)) => {
+            let agent_id = agent::resolve(&ctx.db, agent).await?;
+            resolve_agent_pins(&ctx.db, vec![agent_id], req.revision.as_deref()).await?
+        }
+        _ => {
+            return Err(Error::UnprocessableEntity(
+                "exactly one of config, selector and agent is required".to_string(),
+            ))
+        }
+    };
+
+    // the desired systems depend on the pins, so they're read after pinning in the transaction
+    let mut tx = ctx.db.begin().await?;
+    pins.apply(&mut tx).await?;
+    let desired: HashMap<Uuid, String> = sqlx::query!(
+        r#"SELECT agent_id AS "agent_id!", store_path AS "store_path!"
+        FROM agent_desired_systems WHERE agent_id = ANY($1)"#,
+        &agents
+    )
+    .fetch_all(&mut tx)
+    .await?
+    .into_iter()
+    .map(|row| (row.agent_id, row.store_path))
+    .collect();
+
+    let mut deployed = Vec::new();
+    for agent_id in agents {
+        let Some(store_path) = desired.get(&agent_id) else {
+            let err = Error::UnprocessableEntity(format!(
+                "agent {agent_id} has no evaluated configuration"
+            ));
+            skipped.push((agent_id, err));
+            continue;
+        };
+        // held until the agent is done, the reconciler leaves it alone meanwhile
+        let Some(guard) = ctx.agent_manager.running().start(agent_id) else {
+            let err = Error::Conflict(format!("agent {agent_id} is already being deployed to"));
+            skipped.push((agent_id, err));
+            continue;
+        };
+        deployed.push((agent_id, store_path.clone(), guard));
+    }
+
+    if deployed.is_empty() {
+        // no agent was accepted, keep the previous pins and only record the deployment
+        tx.rollback().await?;
+        tx = ctx.db.begin().await?;
+    }
+    let deployment_id = sqlx::query_scalar!(
+        "INSERT INTO deployments (config, selector, agent, revision, mode)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING deployment_id",
+        req.config,
+        req.selector.as_ref().map(ToString::to_string),
+        req.agent,
+        req.revision,
+        req.mode.as_str()
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    for (agent_id, store_path, _) in &deployed {
+        sqlx::query!(
+            "INSERT INTO deployment_agents (deployment_id, agent_id, store_path, status)
+            VALUES ($1, $2, $3, 'running')",
+            deployment_id,
+            agent_id,
+            store_path
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    for (agent_id, err) in &skipped {
+        sqlx::query!(
+            "INSERT INTO deployment_agents (deployment_id, agent_id, status, error)
+            VALUES ($1, $2, 'skipped', $3)",
+            deployment_id,
+            agent_id,
+            err.to_string()
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    let agents = deployed
+        .iter()
+        .map(|(agent_id, store_path, _)| AgentResult {
+            agent_id: *agent_id,
+            store_path: Some(store_path.clone()),
+            error: None,
+        })
+        .chain(skipped.into_iter().map(|(agent_id, err)| AgentResult {
+            agent_id,
+            store_path: None,
+            error: Some(err.body()),
+        }))
+        .collect();
+
+    tokio::spawn(deploy::run(
+        ctx.agent_manager.clone(),
+        ctx.db.clone(),
+        deployment_id,
+        deployed,
+        req.mode,
+    ));
+
+    Ok(Json(DeploymentStarted {
+        id: deployment_id,
+        agents,
+    }))
+}
+
+/// A deployment with the outcome on every agent, agents still being deployed to are `running`
+#[utoipa::path(
+    get,
+    path = "/api/v1/deployment/{deployment_id}",
+    params(("deployment_id" = i64, Path,)),
+    responses(
+        (status = 200, body = DeploymentDetails),
+        (status = 404, description = "Deployment not found", body = ErrorBody)
+    )
+)]
+async fn get_deployment(
+    State(ctx): State<ApiContext>,
+    Path(deployment_id): Path<i64>,
+) -> Result<Json<DeploymentDetails>> {
+    let deployment = sqlx::query!(
+        "SELECT created_at, config, selector, agent, revision, mode, finished_at
+        FROM deployments WHERE deployment_id = $1",
+        deployment_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let agents = sqlx::query_as!(
+        DeploymentAgent,
+        "SELECT agent_id, store_path, status, error FROM deployment_agents
+        WHERE deployment_id = $1 ORDER BY agent_id",
+        deployment_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(DeploymentDetails {
+        id: deployment_id,
+        created_at: deployment.created_at,
+        config: deployment.config,
+        selector: deployment.selector,
+        agent: deployment.agent,
+        revision: deployment.revision,
+        mode: deployment.mode,
+        finished_at: deployment.finished_at,
+        agents,
+    }))
+}
+
+/// Pins a deployment sets, applied in its transaction
+#[derive(Default)]
+struct Pins {
+    /// Configuration id with the flake revision to pin it to
+    config: Option<(i64, i64)>,
+    /// Agent ids with the flake revision to pin each to
+    agents: Vec<(Uuid, i64)>,
+}
+
+impl Pins {
+    async fn apply(&self, tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+        if let Some((config_id, flake_revision_id)) = self.config {
+            sqlx::query!(
+                "UPDATE nixos_configurations SET pinned_flake_revision_id = $1
+                WHERE nixos_configuration_id = $2",
+                flake_revision_id,
+                config_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        for (agent_id, flake_revision_id) in &self.agents {
+            sqlx::query!(
+                "UPDATE agents SET pinned_flake_revision_id = $1 WHERE agent_id = $2",
+                flake_revision_id,
+                agent_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Resolve configuration `config`, given by id or name, and `revision`, if any, and return its
+/// agents with the pin to set
+async fn resolve_configuration(
+    db: &PgPool,
+    config: &str,
+    revision: Option<&str>,
+) -> Result<(Vec<Uuid>, Pins)> {
+    let config_ids = sqlx::query_scalar!(
+        "SELECT nixos_configuration_id FROM nixos_configurations
+        WHERE nixos_configuration_id::text = $1 OR name = $1",
+        config
+    )
+    .fetch_all(db)
+    .await?;
+    let config_id = match config_ids[..] {
+        [id] => id,
+        [] => return Err(Error::NotFound),
+        _ => {
+            return Err(Error::UnprocessableEntity(format!(
+                "configuration {config} is ambiguous, use one of the ids {config_ids:?}"
+            )))
+        }
+    };
+
+    let mut pins = Pins::default();
+    if let Some(revision) = revision {
+        let flake_revision_id = resolve_revision(db, config_id, revision).await?;
+        pins.config = Some((config_id, flake_revision_id));
+    }
+
+    let agents = sqlx::query_scalar!(
+        "SELECT agent_id FROM agents WHERE nixos_configuration_id = $1 ORDER BY agent_id",
+        config_id
+    )
+    .fetch_all(db)
+    .await?;
+    Ok((agents, pins))
+}
+
+/// Resolve `revision`, if any, for every agent in its own configuration
+///
+/// Returns the agents to deploy, the ones skipped because the revision doesn't apply to them and
+/// the pins to set.
+async fn resolve_agent_pins(
+    db: &PgPool,
+    agents: Vec<Uuid>,
+    revision: Option<&str>,
+) -> Result<(Vec<Uuid>, Vec<(Uuid, Error)>, Pins)> {
+    let Some(revision) = revision else {
+        return Ok((agents, Vec::new(), Pins::default()));
+    };
+    let config_ids: HashMap<Uuid, Option<i64>> = sqlx::query!(
+        "SELECT agent_id, nixos_configuration_id FROM agents WHERE agent_id = ANY($1)",
+        &agents
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|row| (row.agent_id, row.nixos_configuration_id))
+    .collect();
+
+    let mut pinned = Vec::new();
+    let mut skipped = Vec::new();
+    let mut pins = Pins::default();
+    for agent_id in agents {
+        let Some(config_id) = config_ids.get(&agent_id).copied().flatten() else {
+            let err = Error::UnprocessableEntity(format!("agent {agent_id} has no configuration"));
+            skipped.push((agent_id, err));
+            continue;
+        };
+        match resolve_revision(db, config_id, revision).await {
+            Ok(flake_revision_id) => pins.agents.push((agent_id, flake_revision_id)),
+            Err(err) => {
+                skipped.push((agent_id, err));
+                continue;
+            }
+        }
+        pinned.push(agent_id);
+    }
+    Ok((pinned, skipped, pins))
+}
@@ -0,0 +1,81 @@
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Json, Router,
+};
+use nxy_common::api::{AgentFilter, Drift, DriftStatus};
+use utoipa::OpenApi;
+
+use crate::{http::Result, labels};
+
+use super::ApiContext;
+
+#[derive(OpenApi)]
+#[openapi(paths(get_drift), components(schemas(Drift, DriftStatus)))]
+pub(super) struct ApiDoc;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new().route("/api/v1/drift", get(get_drift))
+}
+
+/// Desired and actual system of every agent
+#[utoipa::path(
+    get,
+    path = "/api/v1/drift",
+    params(AgentFilter),
+    responses((status = 200, body = [Drift]))
+)]
+async fn get_drift(
+    ctx: State<ApiContext>,
+    Query(filter): Query<AgentFilter>,
+) -> Result<Json<Vec<Drift>>> {
+    let selected = match filter.selector {
+        Some(ref selector) => Some(labels::select(&ctx.db, selector).await?),
+        None => None,
+    };
+    let rows = sqlx::query!(
+        r#"
+        SELECT agent_id, name, nixos_configuration_id, current_system, booted_system, drifted,
+            desired.store_path AS "desired_system?",
+            EXISTS (
+                SELECT 1 FROM agent_store_paths AS p
+                WHERE p.agent_id = agents.agent_id AND p.store_path = desired.store_path
+            ) AS "downloaded!"
+        FROM agents
+        LEFT JOIN agent_desired_systems AS desired USING (agent_id)
+        WHERE (NOT $1 OR cardinality(reboot_required) > 0)
+            AND ($2::uuid[] IS NULL OR agent_id = ANY($2))
+        ORDER BY agent_id
+        "#,
+        filter.reboot_required,
+        selected.as_deref()
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    let drift = rows
+        .into_iter()
+        .map(|row| {
+            let status = match row.desired_system {
+                None => DriftStatus::UnknownConfiguration,
+                Some(ref desired) if row.current_system.as_ref() == Some(desired) => {
+                    DriftStatus::InSync
+                }
+                Some(_) if row.drifted => DriftStatus::Drifted,
+                Some(_) if row.downloaded => DriftStatus::DownloadedNotActivated,
+                Some(_) => DriftStatus::PendingDownload,
+            };
+            Drift {
+                agent_id: row.agent_id,
+                agent_name: row.name,
+                nixos_configuration_id: row.nixos_configuration_id,
+                desired_system: row.desired_system,
+                current_system: row.current_system,
+                booted_system: row.booted_system,
+                status,
+            }
+        })
+        .collect();
+
+    Ok(Json(drift))
+}
@@ -1,10 +1,47 @@
-use axum::response::{IntoResponse, Response};
+use axum::{
+    response::{IntoResponse, Response},
+    Json,
+};
 use hyper::StatusCode;
+use nxy_common::{api::ErrorBody, MethodError};
 use sqlx::error::DatabaseError;
 
+use crate::{agent::RpcError, nix::NixError};
+
 /// An API-friendly error type.
+///
+/// Errors are returned as JSON body `{"code": "...", "message": "..."}`, with `code` being one
+/// of the machine-readable codes listed at the variants.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
+    /// Return `404 Not Found`, code `not-found`
+    #[error("request path not found")]
+    NotFound,
+
+    /// Return `409 Conflict` with a message explaining the conflict, code `conflict`
+    #[error("{0}")]
+    Conflict(String),
+
+    /// Return `422 Unprocessable Entity` with a message explaining what's wrong with the
+    /// request, code `validation`
+    #[error("{0}")]
+    UnprocessableEntity(String),
+
+    /// A call to an agent failed without a response, e.g. because the agent is not connected.
+    ///
+    /// Code `agent-offline`, `agent-timeout`, or `agent-unsupported` with `422 Unprocessable
+    /// Entity` if the agent doesn't support the method.
+    #[error("{0}")]
+    Rpc(RpcError),
+
+    /// The agent answered with an error, code `agent-rpc`
+    #[error("{0}")]
+    AgentRpc(MethodError<()>),
+
+    /// A nix command failed, e.g. because a flake doesn't evaluate, code `nix-eval`
+    #[error("{0}")]
+    Nix(NixError),
+
     /// A SQLx call returned an error.
     ///
     /// The exact error contents are not reported to the user in order to avoid leaking
@@ -14,15 +51,70 @@ pub enum Error {
 
     /// Similarly, we don't want to report random `anyhow` errors to the user.
     #[error("an internal server error occurred")]
-    Eyre(#[from] color_eyre::Report),
+    Eyre(color_eyre::Report),
+}
+
+impl From<RpcError> for Error {
+    fn from(err: RpcError) -> Self {
+        Self::Rpc(err)
+    }
+}
+
+impl From<color_eyre::Report> for Error {
+    fn from(report: color_eyre::Report) -> Self {
+        let report = match report.downcast::<RpcError>() {
+            Ok(err) => return Self::Rpc(err),
+            Err(report) => report,
+        };
+        let report = match report.downcast::<MethodError<()>>() {
+            Ok(err) => return Self::AgentRpc(err),
+            Err(report) => report,
+        };
+        match report.downcast::<NixError>() {
+            Ok(err) => Self::Nix(err),
+            Err(report) => Self::Eyre(report),
+        }
+    }
 }
 
 impl Error {
     fn status_code(&self) -> StatusCode {
         match self {
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::UnprocessableEntity(_) | Self::Nix(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Rpc(RpcError::NotConnected(_) | RpcError::Disconnected) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Self::Rpc(RpcError::Timeout { .. }) => StatusCode::GATEWAY_TIMEOUT,
+            Self::Rpc(RpcError::Unsupported(_)) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::AgentRpc(_) => StatusCode::BAD_GATEWAY,
             Self::Sqlx(_) | Self::Eyre(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    /// Body of the response, also reported per agent by requests to multiple agents
+    pub(crate) fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code().to_string(),
+            message: self.to_string(),
+        }
+    }
+
+    /// Machine-readable error code
+    fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound => "not-found",
+            Self::Conflict(_) => "conflict",
+            Self::UnprocessableEntity(_) => "validation",
+            Self::Rpc(RpcError::NotConnected(_) | RpcError::Disconnected) => "agent-offline",
+            Self::Rpc(RpcError::Timeout { .. }) => "agent-timeout",
+            Self::Rpc(RpcError::Unsupported(_)) => "agent-unsupported",
+            Self::AgentRpc(_) => "agent-rpc",
+            Self::Nix(_) => "nix-eval",
+            Self::Sqlx(_) | Self::Eyre(_) => "internal",
+        }
+    }
 }
 
 impl IntoResponse for Error {
@@ -37,12 +129,10 @@ impl IntoResponse for Error {
             }
 
             // Other errors geht mapped normally.
-            // we currently don't have any more patterns, but let's keep this as a reminder
-            #[allow(unreachable_patterns)]
             _ => {}
         }
 
-        (self.status_code(), self.to_string()).into_response()
+        (self.status_code(), Json(self.body())).into_response()
     }
 }
 
@@ -88,3 +178,16 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_method_is_unprocessable() {
+        let report = color_eyre::Report::from(RpcError::Unsupported("$/reboot"));
+        let err = Error::from(report);
+        assert_eq!(err.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(err.body().code, "agent-unsupported");
+    }
+}
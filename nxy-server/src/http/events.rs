@@ -0,0 +1,62 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::{Query, State},
+    response::sse::{self, KeepAlive, Sse},
+    routing::get,
+    Router,
+};
+use futures_util::{stream, Stream, StreamExt};
+use nxy_common::api::{DeploymentPhase, Event, EventFilter};
+use tokio::sync::broadcast::error::RecvError;
+use utoipa::OpenApi;
+
+use super::ApiContext;
+
+#[derive(OpenApi)]
+#[openapi(paths(get_events), components(schemas(Event, DeploymentPhase)))]
+pub(super) struct ApiDoc;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new().route("/api/v1/events", get(get_events))
+}
+
+/// Stream events as they happen
+///
+/// Every event is sent as server-sent event, its `event` field is the `type` of the JSON
+/// payload. Past events are not replayed, the stream ends if the subscriber can't keep up.
+#[utoipa::path(
+    get,
+    path = "/api/v1/events",
+    params(EventFilter),
+    responses((status = 200, content_type = "text/event-stream", body = Event))
+)]
+async fn get_events(
+    ctx: State<ApiContext>,
+    Query(filter): Query<EventFilter>,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    let receiver = ctx.agent_manager.subscribe();
+
+    let events = stream::unfold(receiver, move |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) if filter.matches(&event) => return Some((event, receiver)),
+                Ok(_) => {}
+                // end the stream rather than silently skip events, subscribers reconnect and
+                // catch up through the REST API
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(skipped, "event subscriber is too slow, closing the stream");
+                    return None;
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    .map(|event| {
+        // events serialize infallibly
+        let data = serde_json::to_string(&event).unwrap();
+        Ok(sse::Event::default().event(event.name()).data(data))
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
@@ -1,42 +1,74 @@
-use axum::{extract::State, routing::get, Json, Router};
-use serde::{Deserialize, Serialize};
+use axum::{
+    extract::{Path, State},
+    routing::get,
+    Json, Router,
+};
+use nxy_common::api::{
+    Event, Flake, FlakeBody, FlakeBodyFlake, FlakeBodyFlakeDetails, FlakeBodyNewFlake,
+    FlakeConfiguration, FlakeDetails, FlakeRevision, NewFlake, Revision,
+};
+use utoipa::OpenApi;
 
 use crate::nix::{self, flake_metadata, process_configurations};
 
-use super::{ApiContext, Result};
+use super::{
+    error::{Error, ResultExt},
+    ApiContext, Result,
+};
 
-pub(crate) fn router() -> Router<ApiContext> {
-    Router::new().route(
-        "/api/v1/flake",
-        get(get_flakes).post(create_flake).put(update_flake),
-    )
-}
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        get_flakes,
+        create_flake,
+        update_flakes,
+        get_flake,
+        update_flake,
+        rename_flake,
+        delete_flake,
+        get_revisions
+    ),
+    components(schemas(
+        FlakeBodyFlake,
+        FlakeBodyNewFlake,
+        FlakeBodyFlakeDetails,
+        Flake,
+        FlakeRevision,
+        NewFlake,
+        FlakeDetails,
+        FlakeConfiguration,
+        Revision
+    ))
+)]
+pub(super) struct ApiDoc;
 
-#[derive(Serialize, Deserialize)]
-struct FlakeBody<T> {
-    flake: T,
-}
-
-#[derive(Serialize)]
-struct Flake {
-    flake_id: i64,
-    flake_url: String,
-    lastest_revision: FlakeRevision,
-}
-
-#[derive(Serialize)]
-struct FlakeRevision {
-    flake_revision_id: i64,
-    revision: String,
-    last_modified: String,
-    url: String,
-}
-
-#[derive(Deserialize)]
-struct NewFlake {
-    flake_url: String,
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route(
+            "/api/v1/flake",
+            get(get_flakes).post(create_flake).put(update_flakes),
+        )
+        .route(
+            "/api/v1/flake/:flake_id",
+            get(get_flake)
+                .put(update_flake)
+                .patch(rename_flake)
+                .delete(delete_flake),
+        )
+        .route("/api/v1/flake/:flake_id/revisions", get(get_revisions))
 }
 
+/// Add a flake and evaluate its configurations
+#[utoipa::path(
+    post,
+    path = "/api/v1/flake",
+    request_body = FlakeBodyNewFlake,
+    responses(
+        (status = 200, body = FlakeBodyFlake),
+        (status = 409, description = "Flake already exists", body = ErrorBody),
+        (status = 422, description = "Flake metadata can't be fetched", body = ErrorBody)
+    )
+)]
 async fn create_flake(
     ctx: State<ApiContext>,
     Json(req): Json<FlakeBody<NewFlake>>,
@@ -66,8 +98,16 @@ async fn create_flake(
         meta
     )
     .fetch_one(&ctx.db)
-    .await?;
+    .await
+    .on_constraint("flakes_flake_url_key", |_| {
+        Error::Conflict(format!("flake {} already exists", req.flake.flake_url))
+    })?;
 
+    ctx.agent_manager.publish(Event::FlakeRevision {
+        flake_id: flake.flake_id,
+        flake_revision_id: flake.flake_revision_id,
+        revision: flake.revision.clone(),
+    });
     tokio::spawn(process_configurations(
         ctx.db.clone(),
         ctx.agent_manager.clone(),
@@ -88,6 +128,12 @@ async fn create_flake(
     }))
 }
 
+/// List all flakes with their latest revision
+#[utoipa::path(
+    get,
+    path = "/api/v1/flake",
+    responses((status = 200, body = [Flake]))
+)]
 async fn get_flakes(ctx: State<ApiContext>) -> Result<Json<Vec<Flake>>> {
     let flakes = sqlx::query!(
         r#"
@@ -123,7 +169,281 @@ async fn get_flakes(ctx: State<ApiContext>) -> Result<Json<Vec<Flake>>> {
     Ok(Json(flakes))
 }
 
-async fn update_flake(ctx: State<ApiContext>) -> Result<()> {
+/// Fetch and evaluate the latest revision of all flakes
+#[utoipa::path(put, path = "/api/v1/flake", responses((status = 200)))]
+async fn update_flakes(ctx: State<ApiContext>) -> Result<()> {
     nix::update_flakes(&ctx.db, ctx.agent_manager.clone()).await?;
     Ok(())
 }
+
+/// Show a flake and its configurations
+#[utoipa::path(
+    get,
+    path = "/api/v1/flake/{flake_id}",
+    params(("flake_id" = i64, Path, description = "Flake id")),
+    responses(
+        (status = 200, body = FlakeBodyFlakeDetails),
+        (status = 404, body = ErrorBody)
+    )
+)]
+async fn get_flake(
+    ctx: State<ApiContext>,
+    Path(flake_id): Path<i64>,
+) -> Result<Json<FlakeBody<FlakeDetails>>> {
+    let row = sqlx::query!(
+        r#"
+        SELECT flakes.flake_id, flake_url, flake_revision_id, revision, last_modified, url
+        FROM flakes
+        JOIN flake_revisions USING (flake_id)
+        WHERE flake_id = $1
+        ORDER BY flake_revision_id DESC
+        LIMIT 1
+        "#,
+        flake_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let configurations = sqlx::query!(
+        r#"
+        SELECT nixos_configuration_id, name,
+            (SELECT COUNT(*) FROM agents AS a
+             WHERE a.nixos_configuration_id = c.nixos_configuration_id) AS "agents!"
+        FROM nixos_configurations AS c
+        WHERE flake_id = $1
+        ORDER BY name
+        "#,
+        flake_id
+    )
+    .fetch_all(&ctx.db)
+    .await?
+    .into_iter()
+    .map(|row| FlakeConfiguration {
+        id: row.nixos_configuration_id,
+        name: row.name,
+        agents: row.agents,
+    })
+    .collect();
+
+    Ok(Json(FlakeBody {
+        flake: FlakeDetails {
+            flake: Flake {
+                flake_id: row.flake_id,
+                flake_url: row.flake_url,
+                lastest_revision: FlakeRevision {
+                    flake_revision_id: row.flake_revision_id,
+                    revision: row.revision,
+                    last_modified: row.last_modified.to_string(),
+                    url: row.url,
+                },
+            },
+            configurations,
+        },
+    }))
+}
+
+/// Fetch and evaluate the latest revision of a single flake
+#[utoipa::path(
+    put,
+    path = "/api/v1/flake/{flake_id}",
+    params(("flake_id" = i64, Path, description = "Flake id")),
+    responses((status = 200), (status = 404, body = ErrorBody))
+)]
+async fn update_flake(ctx: State<ApiContext>, Path(flake_id): Path<i64>) -> Result<()> {
+    let flake = sqlx::query!(
+        r#"
+        SELECT flake_url, revision
+        FROM flakes
+        JOIN flake_revisions USING (flake_id)
+        WHERE flake_id = $1
+        ORDER BY flake_revision_id DESC
+        LIMIT 1
+        "#,
+        flake_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    nix::update_flake(
+        &ctx.db,
+        ctx.agent_manager.clone(),
+        flake_id,
+        &flake.flake_url,
+        &flake.revision,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Change the url of a flake, eg. after its repository moved.
+///
+/// Revisions fetched from the old url are kept.
+#[utoipa::path(
+    patch,
+    path = "/api/v1/flake/{flake_id}",
+    params(("flake_id" = i64, Path, description = "Flake id")),
+    request_body = FlakeBodyNewFlake,
+    responses(
+        (status = 200),
+        (status = 404, body = ErrorBody),
+        (status = 409, description = "Another flake has the url", body = ErrorBody),
+        (status = 422, description = "Flake metadata can't be fetched", body = ErrorBody)
+    )
+)]
+async fn rename_flake(
+    ctx: State<ApiContext>,
+    Path(flake_id): Path<i64>,
+    Json(req): Json<FlakeBody<NewFlake>>,
+) -> Result<()> {
+    // validate the new url before touching the flake
+    flake_metadata(&req.flake.flake_url).await?;
+
+    let result = sqlx::query!(
+        "UPDATE flakes SET flake_url = $2 WHERE flake_id = $1",
+        flake_id,
+        req.flake.flake_url
+    )
+    .execute(&ctx.db)
+    .await
+    .on_constraint("flakes_flake_url_key", |_| {
+        Error::Conflict(format!("flake {} already exists", req.flake.flake_url))
+    })?;
+    if result.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+    Ok(())
+}
+
+/// Remove a flake with all its revisions, configurations and evaluations.
+///
+/// Refused while agents are still assigned to one of its configurations.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/flake/{flake_id}",
+    params(("flake_id" = i64, Path, description = "Flake id")),
+    responses(
+        (status = 200),
+        (status = 404, body = ErrorBody),
+        (status = 409, description = "Agents still use the flake", body = ErrorBody)
+    )
+)]
+async fn delete_flake(ctx: State<ApiContext>, Path(flake_id): Path<i64>) -> Result<()> {
+    let mut tx = ctx.db.begin().await?;
+
+    // agents assigned or pinned concurrently key-share lock these rows, lock them first so
+    // those wait for the delete instead of slipping past the check below
+    sqlx::query!(
+        "SELECT nixos_configuration_id FROM nixos_configurations WHERE flake_id = $1 FOR UPDATE",
+        flake_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    sqlx::query!(
+        "SELECT flake_revision_id FROM flake_revisions WHERE flake_id = $1 FOR UPDATE",
+        flake_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    let agents = sqlx::query_scalar!(
+        "SELECT agent_id FROM agents
+        JOIN nixos_configurations USING (nixos_configuration_id)
+        WHERE flake_id = $1",
+        flake_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    if !agents.is_empty() {
+        let agents: Vec<_> = agents.iter().map(ToString::to_string).collect();
+        return Err(Error::Conflict(format!(
+            "flake {flake_id} is still used by agents {}",
+            agents.join(", ")
+        )));
+    }
+
+    sqlx::query!(
+        "UPDATE agents SET pinned_flake_revision_id = NULL
+        WHERE pinned_flake_revision_id IN (
+            SELECT flake_revision_id FROM flake_revisions WHERE flake_id = $1
+        )",
+        flake_id
+    )
+    .execute(&mut tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM nixos_configuration_evaluations
+        WHERE nixos_configuration_id IN (
+            SELECT nixos_configuration_id FROM nixos_configurations WHERE flake_id = $1
+        )",
+        flake_id
+    )
+    .execute(&mut tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM nixos_configurations WHERE flake_id = $1",
+        flake_id
+    )
+    .execute(&mut tx)
+    .await?;
+    sqlx::query!("DELETE FROM flake_revisions WHERE flake_id = $1", flake_id)
+        .execute(&mut tx)
+        .await?;
+    let deleted = sqlx::query!("DELETE FROM flakes WHERE flake_id = $1", flake_id)
+        .execute(&mut tx)
+        .await?;
+    if deleted.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// List all revisions of a flake, newest first
+#[utoipa::path(
+    get,
+    path = "/api/v1/flake/{flake_id}/revisions",
+    params(("flake_id" = i64, Path, description = "Flake id")),
+    responses((status = 200, body = [Revision]), (status = 404, body = ErrorBody))
+)]
+async fn get_revisions(
+    ctx: State<ApiContext>,
+    Path(flake_id): Path<i64>,
+) -> Result<Json<Vec<Revision>>> {
+    let exists = sqlx::query_scalar!("SELECT flake_id FROM flakes WHERE flake_id = $1", flake_id)
+        .fetch_optional(&ctx.db)
+        .await?;
+    if exists.is_none() {
+        return Err(Error::NotFound);
+    }
+
+    let revisions = sqlx::query!(
+        r#"
+        SELECT flake_revision_id, revision, last_modified, url, evaluation_status,
+            evaluation_error,
+            (SELECT COUNT(*) FROM nixos_configuration_evaluations AS e
+             WHERE e.flake_revision_id = r.flake_revision_id) AS "evaluations!"
+        FROM flake_revisions AS r
+        WHERE flake_id = $1
+        ORDER BY flake_revision_id DESC
+        "#,
+        flake_id
+    )
+    .fetch_all(&ctx.db)
+    .await?
+    .into_iter()
+    .map(|row| Revision {
+        flake_revision_id: row.flake_revision_id,
+        revision: row.revision,
+        last_modified: row.last_modified.to_string(),
+        url: row.url,
+        evaluation_status: row.evaluation_status,
+        evaluation_error: row.evaluation_error,
+        evaluations: row.evaluations,
+    })
+    .collect();
+
+    Ok(Json(revisions))
+}
@@ -0,0 +1,39 @@
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+
+use super::{ApiContext, Result};
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new().route("/metrics", get(get_metrics))
+}
+
+/// Prometheus metrics, not part of the REST API
+async fn get_metrics(ctx: State<ApiContext>) -> Result<impl IntoResponse> {
+    let metrics = ctx.agent_manager.metrics();
+
+    let drifted = sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM agents WHERE drifted"#)
+        .fetch_one(&ctx.db)
+        .await?;
+    metrics.drifted_agents.set(drifted);
+
+    let deployments = sqlx::query!(
+        r#"
+        SELECT agent_id,
+            extract(epoch FROM now() - max(created_at))::float8 AS "seconds!"
+        FROM agent_deployments
+        WHERE error IS NULL AND action <> 'download'
+        GROUP BY agent_id
+        "#
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+    metrics.set_seconds_since_deploy(
+        deployments
+            .into_iter()
+            .map(|row| (row.agent_id, row.seconds)),
+    );
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.encode(),
+    ))
+}
@@ -1,14 +1,22 @@
 mod agent;
+mod audit;
+mod deployments;
+mod drift;
 mod error;
+mod events;
 mod flakes;
+mod metrics;
 mod nixos_configuration;
+mod openapi;
+mod sites;
+mod ui;
 
 use std::{
     net::{Ipv4Addr, SocketAddr},
     sync::Arc,
 };
 
-use axum::Router;
+use axum::{middleware, Router};
 use color_eyre::eyre::WrapErr;
 use sqlx::PgPool;
 use tower_http::trace::TraceLayer;
@@ -41,7 +49,7 @@ pub async fn serve(
     let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 8085));
     tracing::info!("running on {addr}");
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .wrap_err("error running HTTP server")
 }
@@ -51,6 +59,18 @@ fn api_router(api_context: ApiContext) -> Router<()> {
         .merge(flakes::router())
         .merge(agent::router())
         .merge(nixos_configuration::router())
+        .merge(sites::router())
+        .merge(drift::router())
+        .merge(deployments::router())
+        .merge(events::router())
+        .merge(metrics::router())
+        .merge(audit::router())
+        .merge(ui::router())
+        .layer(middleware::from_fn_with_state(
+            api_context.clone(),
+            audit::record_requests,
+        ))
+        .merge(openapi::router())
         // Enable logging. Use `RUST_LOG=tower_http=debug`
         .layer(TraceLayer::new_for_http())
         .with_state(api_context)
@@ -1,27 +1,44 @@
-use axum::{extract::State, routing::get, Json, Router};
-use serde::Serialize;
+use axum::{
+    extract::{Path, State},
+    routing::{get, post},
+    Json, Router,
+};
+use nxy_common::api::{Configuration, Pin};
+use sqlx::PgPool;
+use utoipa::OpenApi;
 
 use crate::http::Result;
 
-use super::ApiContext;
+use super::{error::Error, ApiContext};
 
-pub(crate) fn router() -> Router<ApiContext> {
-    Router::new().route("/api/v1/configuration", get(list_configurations))
-}
+#[derive(OpenApi)]
+#[openapi(
+    paths(list_configurations, pin_configuration),
+    components(schemas(Configuration, Pin))
+)]
+pub(super) struct ApiDoc;
 
-#[derive(Debug, Serialize)]
-struct Configuration {
-    id: i64,
-    name: String,
-    flake_id: i64,
-    flake_url: String,
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route("/api/v1/configuration", get(list_configurations))
+        .route(
+            "/api/v1/configuration/:config_id/pin",
+            post(pin_configuration),
+        )
 }
 
+/// List all configurations of all flakes
+#[utoipa::path(
+    get,
+    path = "/api/v1/configuration",
+    responses((status = 200, body = [Configuration]))
+)]
 async fn list_configurations(ctx: State<ApiContext>) -> Result<Json<Vec<Configuration>>> {
     let configs = sqlx::query!(
-        "SELECT flake_id, flake_url, nixos_configuration_id, name
+        r#"SELECT flakes.flake_id, flake_url, nixos_configuration_id, name, r.revision AS "pinned_revision?"
          FROM nixos_configurations 
-         JOIN flakes USING (flake_id)"
+         JOIN flakes USING (flake_id)
+         LEFT JOIN flake_revisions AS r ON r.flake_revision_id = pinned_flake_revision_id"#
     )
     .fetch_all(&ctx.db)
     .await?
@@ -31,8 +48,72 @@ async fn list_configurations(ctx: State<ApiContext>) -> Result<Json<Vec<Configur
         name: row.name,
         flake_id: row.flake_id,
         flake_url: row.flake_url,
+        pinned_revision: row.pinned_revision,
     })
     .collect();
 
     Ok(Json(configs))
 }
+
+/// Pin all agents of a configuration to a flake revision, or unpin them
+#[utoipa::path(
+    post,
+    path = "/api/v1/configuration/{config_id}/pin",
+    params(("config_id" = i64, Path, description = "Configuration id")),
+    request_body = Pin,
+    responses(
+        (status = 200),
+        (status = 404, body = ErrorBody),
+        (status = 422, description = "Revision unknown or ambiguous", body = ErrorBody)
+    )
+)]
+async fn pin_configuration(
+    ctx: State<ApiContext>,
+    Path(config_id): Path<i64>,
+    Json(req): Json<Pin>,
+) -> Result<()> {
+    let flake_revision_id = match req.revision {
+        Some(ref revision) => Some(resolve_revision(&ctx.db, config_id, revision).await?),
+        None => None,
+    };
+
+    let updated = sqlx::query!(
+        "UPDATE nixos_configurations SET pinned_flake_revision_id = $1
+        WHERE nixos_configuration_id = $2",
+        flake_revision_id,
+        config_id
+    )
+    .execute(&ctx.db)
+    .await?;
+    if updated.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    ctx.agent_manager.trigger_reconcile();
+    Ok(())
+}
+
+/// Returns the id of the flake revision `revision` at which configuration `config_id` was
+/// evaluated.
+pub(super) async fn resolve_revision(db: &PgPool, config_id: i64, revision: &str) -> Result<i64> {
+    let ids = sqlx::query_scalar!(
+        "SELECT flake_revision_id
+        FROM flake_revisions
+        JOIN nixos_configuration_evaluations USING (flake_revision_id)
+        WHERE nixos_configuration_id = $1 AND starts_with(revision, $2)",
+        config_id,
+        revision
+    )
+    .fetch_all(db)
+    .await?;
+
+    match ids[..] {
+        [id] => Ok(id),
+        [] => Err(Error::UnprocessableEntity(format!(
+            "configuration {config_id} wasn't evaluated at revision {revision}"
+        ))),
+        _ => Err(Error::UnprocessableEntity(format!(
+            "revision {revision} is ambiguous"
+        ))),
+    }
+}
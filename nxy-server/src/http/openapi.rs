@@ -0,0 +1,211 @@
+use axum::{routing::get, Json, Router};
+use nxy_common::api::ErrorBody;
+use utoipa::OpenApi;
+
+use super::{
+    agent, audit, deployments, drift, events, flakes, nixos_configuration, sites, ApiContext,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "nxy",
+        description = "Deploy NixOS configurations to nxy agents"
+    ),
+    paths(get_openapi),
+    components(schemas(ErrorBody))
+)]
+struct ApiDoc;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new().route("/api/v1/openapi.json", get(get_openapi))
+}
+
+/// OpenAPI document of all REST routes, the agent websocket is not included
+pub(crate) fn spec() -> utoipa::openapi::OpenApi {
+    let mut spec = ApiDoc::openapi();
+    spec.merge(flakes::ApiDoc::openapi());
+    spec.merge(agent::ApiDoc::openapi());
+    spec.merge(nixos_configuration::ApiDoc::openapi());
+    spec.merge(sites::ApiDoc::openapi());
+    spec.merge(drift::ApiDoc::openapi());
+    spec.merge(deployments::ApiDoc::openapi());
+    spec.merge(events::ApiDoc::openapi());
+    spec.merge(audit::ApiDoc::openapi());
+    spec
+}
+
+/// This document
+#[utoipa::path(
+    get,
+    path = "/api/v1/openapi.json",
+    responses((status = 200, description = "OpenAPI 3 document"))
+)]
+async fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(spec())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeSet, HashMap},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+        Router,
+    };
+    use sqlx::postgres::PgPoolOptions;
+    use tower::ServiceExt;
+    use utoipa::openapi::PathItemType;
+
+    use super::spec;
+    use crate::{
+        agent::AgentManager,
+        config::load_config,
+        http::{api_router, ApiContext},
+    };
+
+    /// The REST API with a database that can't be reached, requests nobody routes are answered
+    /// with `501 Not Implemented`
+    async fn app() -> Router {
+        // handlers reaching the database fail fast, we only care about routing
+        let db = PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(100))
+            .connect_lazy("postgres://localhost:1/nxy")
+            .unwrap();
+        let config = Arc::new(load_config(None));
+        let agent_manager = AgentManager::start(Arc::clone(&config), db.clone()).await;
+        api_router(ApiContext {
+            config,
+            db,
+            agent_manager,
+        })
+        .fallback(|| async { StatusCode::NOT_IMPLEMENTED })
+    }
+
+    /// Whether `app` has a handler for `method` on `path`, given with OpenAPI parameters
+    async fn is_routed(app: &Router, method: Method, path: &str) -> bool {
+        // any value will do, a path parameter of the wrong type is rejected by the handler
+        let uri = path
+            .split('/')
+            .map(|segment| {
+                if segment.starts_with('{') {
+                    "1"
+                } else {
+                    segment
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        let status = app.clone().oneshot(request).await.unwrap().status();
+        status != StatusCode::NOT_IMPLEMENTED && status != StatusCode::METHOD_NOT_ALLOWED
+    }
+
+    fn method(item_type: &PathItemType) -> Method {
+        match item_type {
+            PathItemType::Get => Method::GET,
+            PathItemType::Post => Method::POST,
+            PathItemType::Put => Method::PUT,
+            PathItemType::Delete => Method::DELETE,
+            PathItemType::Patch => Method::PATCH,
+            _ => panic!("unexpected method in the spec"),
+        }
+    }
+
+    /// Every operation in the spec must be served by a handler with the same path and method.
+    #[tokio::test]
+    async fn spec_matches_routes() {
+        let app = app().await;
+        for (path, item) in spec().paths.paths {
+            for item_type in item.operations.keys() {
+                let method = method(item_type);
+                assert!(
+                    is_routed(&app, method.clone(), &path).await,
+                    "{method} {path} is documented but not routed"
+                );
+            }
+        }
+    }
+
+    /// Routes not meant for REST clients
+    const UNDOCUMENTED: &[&str] = &["/api/v1/agent/ws"];
+
+    /// Every route of the REST API must be documented in the spec, with all its methods.
+    ///
+    /// Routes can't be listed from the router, so every method is tried on every documented path
+    /// and on all paths above it.
+    #[tokio::test]
+    async fn routes_in_spec() {
+        let app = app().await;
+        let documented: HashMap<String, Vec<Method>> = spec()
+            .paths
+            .paths
+            .into_iter()
+            .map(|(path, item)| (path, item.operations.keys().map(method).collect()))
+            .collect();
+
+        let mut paths = BTreeSet::new();
+        for path in documented.keys() {
+            let segments: Vec<_> = path.split('/').collect();
+            // skip the empty segment before the leading slash
+            for end in 2..=segments.len() {
+                paths.insert(segments[..end].join("/"));
+            }
+        }
+
+        for path in paths {
+            if UNDOCUMENTED.contains(&path.as_str()) {
+                continue;
+            }
+            let methods = documented.get(&path).map_or(&[][..], Vec::as_slice);
+            for method in [
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::DELETE,
+                Method::PATCH,
+            ] {
+                if !methods.contains(&method) {
+                    assert!(
+                        !is_routed(&app, method.clone(), &path).await,
+                        "{method} {path} is routed but not documented"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Every schema referenced by the spec must be registered as component.
+    #[test]
+    fn spec_references_resolve() {
+        let spec = serde_json::to_value(spec()).unwrap();
+        let schemas = &spec["components"]["schemas"];
+
+        let mut values = vec![&spec];
+        while let Some(value) = values.pop() {
+            match value {
+                serde_json::Value::Object(map) => {
+                    if let Some(reference) = map.get("$ref").and_then(|r| r.as_str()) {
+                        let name = reference.trim_start_matches("#/components/schemas/");
+                        assert!(
+                            schemas.get(name).is_some(),
+                            "schema {name} is referenced but not registered"
+                        );
+                    }
+                    values.extend(map.values());
+                }
+                serde_json::Value::Array(array) => values.extend(array),
+                _ => {}
+            }
+        }
+    }
+}
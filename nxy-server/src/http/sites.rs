@@ -0,0 +1,32 @@
+use axum::{extract::State, routing::get, Json, Router};
+use nxy_common::api::Site;
+use utoipa::OpenApi;
+
+use crate::http::Result;
+
+use super::ApiContext;
+
+#[derive(OpenApi)]
+#[openapi(paths(list_sites), components(schemas(Site)))]
+pub(super) struct ApiDoc;
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new().route("/api/v1/site", get(list_sites))
+}
+
+/// List all configured substituter sites
+#[utoipa::path(get, path = "/api/v1/site", responses((status = 200, body = [Site])))]
+async fn list_sites(ctx: State<ApiContext>) -> Result<Json<Vec<Site>>> {
+    let mut sites: Vec<Site> = ctx
+        .config
+        .sites
+        .keys()
+        .map(|name| Site {
+            name: name.clone(),
+            substituters: ctx.config.substituters(Some(name)),
+        })
+        .collect();
+    sites.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Json(sites))
+}
@@ -0,0 +1,33 @@
+//! Web dashboard, embedded in the binary and driven by the REST API and the event stream.
+
+use axum::{
+    http::header,
+    response::{Html, IntoResponse},
+    routing::get,
+    Router,
+};
+
+use super::ApiContext;
+
+const INDEX: &str = include_str!("../../ui/index.html");
+const APP: &str = include_str!("../../ui/app.js");
+const STYLE: &str = include_str!("../../ui/style.css");
+
+pub(crate) fn router() -> Router<ApiContext> {
+    Router::new()
+        .route("/", get(index))
+        .route("/ui/app.js", get(app))
+        .route("/ui/style.css", get(style))
+}
+
+async fn index() -> Html<&'static str> {
+    Html(INDEX)
+}
+
+async fn app() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/javascript")], APP)
+}
+
+async fn style() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/css")], STYLE)
+}
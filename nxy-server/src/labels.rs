@@ -0,0 +1,113 @@
+//! Labels of agents, set through the REST API or reported by the agents.
+//!
+//! Labels set through the API take precedence, an agent reporting the same key doesn't
+//! overwrite them.
+
+use std::collections::HashMap;
+
+use nxy_common::labels::{Labels, Selector};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Labels of all agents, agents without labels are missing
+pub(crate) async fn all(db: &PgPool) -> sqlx::Result<HashMap<Uuid, Labels>> {
+    let rows = sqlx::query!("SELECT agent_id, key, value FROM agent_labels")
+        .fetch_all(db)
+        .await?;
+
+    let mut labels: HashMap<Uuid, Labels> = HashMap::new();
+    for row in rows {
+        labels
+            .entry(row.agent_id)
+            .or_default()
+            .insert(row.key, row.value);
+    }
+    Ok(labels)
+}
+
+pub(crate) async fn of(db: &PgPool, agent_id: Uuid) -> sqlx::Result<Labels> {
+    let rows = sqlx::query!(
+        "SELECT key, value FROM agent_labels WHERE agent_id = $1",
+        agent_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.key, row.value)).collect())
+}
+
+/// Ids of all agents matching `selector`
+pub(crate) async fn select(db: &PgPool, selector: &Selector) -> sqlx::Result<Vec<Uuid>> {
+    let agents = sqlx::query_scalar!("SELECT agent_id FROM agents ORDER BY agent_id")
+        .fetch_all(db)
+        .await?;
+    let labels = all(db).await?;
+
+    let empty = Labels::new();
+    Ok(agents
+        .into_iter()
+        .filter(|agent_id| selector.matches(labels.get(agent_id).unwrap_or(&empty)))
+        .collect())
+}
+
+/// Set and remove labels of an agent through the API
+pub(crate) async fn update(
+    db: &PgPool,
+    agent_id: Uuid,
+    set: &Labels,
+    remove: &[String],
+) -> sqlx::Result<()> {
+    let (keys, values): (Vec<_>, Vec<_>) = set.iter().map(|(k, v)| (k.clone(), v.clone())).unzip();
+
+    let mut tx = db.begin().await?;
+    sqlx::query!(
+        "DELETE FROM agent_labels WHERE agent_id = $1 AND key = ANY($2)",
+        agent_id,
+        remove
+    )
+    .execute(&mut tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO agent_labels (agent_id, key, value, source)
+        SELECT $1, key, value, 'api' FROM UNNEST($2::text[], $3::text[]) AS l (key, value)
+        ON CONFLICT (agent_id, key) DO UPDATE SET value = EXCLUDED.value, source = 'api'",
+        agent_id,
+        &keys,
+        &values
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await
+}
+
+/// Replace the labels an agent reported, see [`nxy_common::types::Facts::labels`]
+pub(crate) async fn sync_reported(
+    db: &PgPool,
+    agent_id: Uuid,
+    labels: &Labels,
+) -> sqlx::Result<()> {
+    let (keys, values): (Vec<_>, Vec<_>) =
+        labels.iter().map(|(k, v)| (k.clone(), v.clone())).unzip();
+
+    let mut tx = db.begin().await?;
+    sqlx::query!(
+        "DELETE FROM agent_labels
+        WHERE agent_id = $1 AND source = 'agent' AND NOT key = ANY($2)",
+        agent_id,
+        &keys
+    )
+    .execute(&mut tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO agent_labels (agent_id, key, value, source)
+        SELECT $1, key, value, 'agent' FROM UNNEST($2::text[], $3::text[]) AS l (key, value)
+        ON CONFLICT (agent_id, key) DO UPDATE SET value = EXCLUDED.value
+            WHERE agent_labels.source = 'agent'",
+        agent_id,
+        &keys,
+        &values
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await
+}
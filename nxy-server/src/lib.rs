@@ -1,4 +1,9 @@
 pub mod agent;
+mod audit;
 pub mod config;
+mod deploy;
 pub mod http;
+mod labels;
+mod metrics;
 pub mod nix;
+pub mod reconcile;
@@ -0,0 +1,240 @@
+//! Prometheus metrics of the server, served at `/metrics`.
+//!
+//! Counters and histograms are updated where things happen, gauges derived from the database
+//! are refreshed on every scrape, see [`crate::http`].
+
+use std::{sync::atomic::AtomicU64, time::Duration};
+
+use color_eyre::Result;
+use prometheus_client::{
+    encoding::{text::encode, EncodeLabelSet},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+use uuid::Uuid;
+
+use crate::agent::RpcError;
+
+type HistogramFamily<L> = Family<L, Histogram, fn() -> Histogram>;
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub(crate) struct RpcLabels {
+    method: &'static str,
+    /// `ok`, `error`, `timeout` or `disconnected`
+    outcome: &'static str,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub(crate) struct MethodLabels {
+    method: &'static str,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub(crate) struct FlakeLabels {
+    flake_id: i64,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub(crate) struct DeploymentLabels {
+    action: String,
+    /// `succeeded` or `failed`
+    outcome: &'static str,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub(crate) struct DirectionLabels {
+    /// `received` or `sent`
+    direction: &'static str,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub(crate) struct AgentLabels {
+    agent_id: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct Metrics {
+    registry: Registry,
+    pub(crate) connected_agents: Gauge,
+    pub(crate) websocket_connections: Gauge,
+    websocket_messages: Family<DirectionLabels, Counter>,
+    pub(crate) heartbeat_rtt: Histogram,
+    rpc_requests: Family<RpcLabels, Counter>,
+    rpc_duration: HistogramFamily<MethodLabels>,
+    evaluation_duration: HistogramFamily<FlakeLabels>,
+    evaluation_failures: Family<FlakeLabels, Counter>,
+    deployments: Family<DeploymentLabels, Counter>,
+    pub(crate) drift_detected: Counter,
+    pub(crate) drifted_agents: Gauge,
+    seconds_since_deploy: Family<AgentLabels, Gauge<f64, AtomicU64>>,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        let metrics = Self {
+            registry: Registry::with_prefix("nxy"),
+            connected_agents: Default::default(),
+            websocket_connections: Default::default(),
+            websocket_messages: Default::default(),
+            // 1ms to ~16s
+            heartbeat_rtt: Histogram::new(exponential_buckets(0.001, 2.0, 15)),
+            rpc_requests: Default::default(),
+            // 5ms to ~10min, downloads take a while
+            rpc_duration: Family::new_with_constructor(|| {
+                Histogram::new(exponential_buckets(0.005, 2.0, 18))
+            }),
+            // 1s to ~1h
+            evaluation_duration: Family::new_with_constructor(|| {
+                Histogram::new(exponential_buckets(1.0, 2.0, 13))
+            }),
+            evaluation_failures: Default::default(),
+            deployments: Default::default(),
+            drift_detected: Default::default(),
+            drifted_agents: Default::default(),
+            seconds_since_deploy: Default::default(),
+        };
+        metrics.register()
+    }
+
+    fn register(mut self) -> Self {
+        let registry = &mut self.registry;
+        registry.register(
+            "connected_agents",
+            "Agents connected to this server",
+            self.connected_agents.clone(),
+        );
+        registry.register(
+            "websocket_connections",
+            "Open agent websocket connections, including agents not registered yet",
+            self.websocket_connections.clone(),
+        );
+        registry.register(
+            "websocket_messages",
+            "Websocket messages exchanged with agents",
+            self.websocket_messages.clone(),
+        );
+        registry.register(
+            "heartbeat_rtt_seconds",
+            "Round trip time of agent heartbeats",
+            self.heartbeat_rtt.clone(),
+        );
+        registry.register(
+            "rpc_requests",
+            "Requests sent to agents by method and outcome",
+            self.rpc_requests.clone(),
+        );
+        registry.register(
+            "rpc_duration_seconds",
+            "Time until agents answered a request",
+            self.rpc_duration.clone(),
+        );
+        registry.register(
+            "evaluation_duration_seconds",
+            "Time to evaluate all configurations of a flake revision",
+            self.evaluation_duration.clone(),
+        );
+        registry.register(
+            "evaluation_failures",
+            "Flake revisions that failed to evaluate",
+            self.evaluation_failures.clone(),
+        );
+        registry.register(
+            "deployments",
+            "Deployment steps run by the reconciler by action and outcome",
+            self.deployments.clone(),
+        );
+        registry.register(
+            "drift_detected",
+            "Agents switching to a system not deployed by nxy",
+            self.drift_detected.clone(),
+        );
+        registry.register(
+            "drifted_agents",
+            "Agents currently running a system not deployed by nxy",
+            self.drifted_agents.clone(),
+        );
+        registry.register(
+            "agent_seconds_since_last_deploy",
+            "Time since the last successful activation on an agent",
+            self.seconds_since_deploy.clone(),
+        );
+        self
+    }
+
+    pub(crate) fn record_websocket_message(&self, direction: &'static str) {
+        self.websocket_messages
+            .get_or_create(&DirectionLabels { direction })
+            .inc();
+    }
+
+    pub(crate) fn record_rpc<T>(
+        &self,
+        method: &'static str,
+        elapsed: Duration,
+        result: &Result<T>,
+    ) {
+        let outcome = match result {
+            Ok(_) => "ok",
+            Err(err) => match err.downcast_ref::<RpcError>() {
+                Some(RpcError::Timeout { .. }) => "timeout",
+                Some(RpcError::Disconnected | RpcError::NotConnected(_)) => "disconnected",
+                Some(RpcError::Unsupported(_)) => "unsupported",
+                None => "error",
+            },
+        };
+        self.rpc_requests
+            .get_or_create(&RpcLabels { method, outcome })
+            .inc();
+        // timeouts would only measure the timeout
+        if result.is_ok() {
+            self.rpc_duration
+                .get_or_create(&MethodLabels { method })
+                .observe(elapsed.as_secs_f64());
+        }
+    }
+
+    pub(crate) fn record_evaluation(&self, flake_id: i64, elapsed: Duration, succeeded: bool) {
+        let labels = FlakeLabels { flake_id };
+        self.evaluation_duration
+            .get_or_create(&labels)
+            .observe(elapsed.as_secs_f64());
+        if !succeeded {
+            self.evaluation_failures.get_or_create(&labels).inc();
+        }
+    }
+
+    pub(crate) fn record_deployment(&self, action: &str, succeeded: bool) {
+        let outcome = if succeeded { "succeeded" } else { "failed" };
+        self.deployments
+            .get_or_create(&DeploymentLabels {
+                action: action.to_string(),
+                outcome,
+            })
+            .inc();
+    }
+
+    /// Replace the per agent deployment ages, agents never deployed to are left out
+    pub(crate) fn set_seconds_since_deploy(&self, ages: impl IntoIterator<Item = (Uuid, f64)>) {
+        self.seconds_since_deploy.clear();
+        for (agent_id, seconds) in ages {
+            self.seconds_since_deploy
+                .get_or_create(&AgentLabels {
+                    agent_id: agent_id.to_string(),
+                })
+                .set(seconds);
+        }
+    }
+
+    /// All metrics in the prometheus text format
+    pub(crate) fn encode(&self) -> String {
+        let mut buffer = String::new();
+        // writing to a string doesn't fail
+        encode(&mut buffer, &self.registry).unwrap();
+        buffer
+    }
+}
@@ -1,7 +1,8 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use chrono::{DateTime, Utc};
-use color_eyre::{eyre::eyre, Help, Report, Result, SectionExt};
+use color_eyre::{Help, Report, Result, SectionExt};
+use nxy_common::api::Event;
 use serde::{de::DeserializeOwned, Deserialize};
 use serde_json::Value;
 use sqlx::PgPool;
@@ -10,6 +11,14 @@ use tracing::instrument;
 
 use crate::agent::AgentManager;
 
+/// A nix command exited with a non-zero status code, e.g. because a flake doesn't evaluate
+#[derive(Debug, thiserror::Error)]
+#[error("{command} failed: {stderr}")]
+pub struct NixError {
+    command: String,
+    stderr: String,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct FlakeMetadata {
     pub revision: String,
@@ -49,56 +58,126 @@ pub(crate) async fn update_flakes(db: &PgPool, agent_manager: Arc<AgentManager>)
     .await?;
 
     for flake in flakes {
-        tracing::info!("updating {}", flake.flake_url);
-        let (metadata, meta) = flake_metadata(&flake.flake_url).await?;
-        if metadata.revision == flake.revision {
-            continue;
-        }
-        let flake_revision_id = sqlx::query_scalar!(
-            r#"
-            INSERT INTO flake_revisions (flake_id, revision, last_modified, url, metadata)
-            VALUES ($1, $2, $3, $4, $5)
-            RETURNING flake_revision_id
-            "#,
+        update_flake(
+            db,
+            agent_manager.clone(),
             flake.flake_id,
-            metadata.revision,
-            metadata.last_modified,
-            metadata.url,
-            meta
+            &flake.flake_url,
+            &flake.revision,
         )
-        .fetch_one(db)
         .await?;
-
-        process_configurations(db.clone(), agent_manager.clone(), flake_revision_id).await?;
     }
     Ok(())
 }
 
+/// Fetch the latest revision of a flake and evaluate it, if it differs from `current_revision`.
+///
+/// # Returns
+///
+/// Id of the new flake revision, `None` if the flake didn't change
+#[instrument(skip(db, agent_manager))]
+pub(crate) async fn update_flake(
+    db: &PgPool,
+    agent_manager: Arc<AgentManager>,
+    flake_id: i64,
+    flake_url: &str,
+    current_revision: &str,
+) -> Result<Option<i64>> {
+    tracing::info!("updating {}", flake_url);
+    let (metadata, meta) = flake_metadata(flake_url).await?;
+    if metadata.revision == current_revision {
+        return Ok(None);
+    }
+    let flake_revision_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO flake_revisions (flake_id, revision, last_modified, url, metadata)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING flake_revision_id
+        "#,
+        flake_id,
+        metadata.revision,
+        metadata.last_modified,
+        metadata.url,
+        meta
+    )
+    .fetch_one(db)
+    .await?;
+
+    agent_manager.publish(Event::FlakeRevision {
+        flake_id,
+        flake_revision_id,
+        revision: metadata.revision,
+    });
+    process_configurations(db.clone(), agent_manager, flake_revision_id).await?;
+    Ok(Some(flake_revision_id))
+}
+
+/// Evaluate all configurations of a flake revision and record the outcome.
 #[instrument(skip(db, agent_manager))]
 pub(crate) async fn process_configurations(
     db: PgPool,
     agent_manager: Arc<AgentManager>,
     flake_revision_id: i64,
 ) -> Result<()> {
-    tracing::info!("foo");
     let revision = sqlx::query!(
         "SELECT flake_id, url FROM flake_revisions WHERE flake_revision_id = $1",
         flake_revision_id
     )
     .fetch_one(&db)
     .await?;
+    let flake_id = revision.flake_id;
+
+    agent_manager.publish(Event::EvaluationStarted {
+        flake_id,
+        flake_revision_id,
+    });
+    let start = Instant::now();
+    let result = evaluate_configurations(&db, flake_id, flake_revision_id, &revision.url).await;
+    agent_manager
+        .metrics()
+        .record_evaluation(flake_id, start.elapsed(), result.is_ok());
+
+    let (status, error) = match result {
+        Ok(()) => ("succeeded", None),
+        Err(ref err) => ("failed", Some(format!("{err:#}"))),
+    };
+    agent_manager.publish(match error {
+        None => Event::EvaluationSucceeded {
+            flake_id,
+            flake_revision_id,
+        },
+        Some(ref error) => Event::EvaluationFailed {
+            flake_id,
+            flake_revision_id,
+            error: error.clone(),
+        },
+    });
+    sqlx::query!(
+        "UPDATE flake_revisions SET evaluation_status = $2, evaluation_error = $3
+        WHERE flake_revision_id = $1",
+        flake_revision_id,
+        status,
+        error
+    )
+    .execute(&db)
+    .await?;
+
+    agent_manager.trigger_reconcile();
+    result
+}
 
-    let configs = list_configurations(&revision.url).await?;
+async fn evaluate_configurations(
+    db: &PgPool,
+    flake_id: i64,
+    flake_revision_id: i64,
+    url: &str,
+) -> Result<()> {
+    let configs = list_configurations(url).await?;
     for config in configs {
-        let config_id = upsert_nixos_configuration(&db, revision.flake_id, &config).await?;
-
-        let store_path = config_store_path(&revision.url, &config).await?;
-        insert_nixos_configutaion_evaluation(&db, flake_revision_id, config_id, &store_path)
-            .await?;
-        //TODO(xanderio): this is a hack
-        agent_manager
-            .process_update(config_id, flake_revision_id)
-            .await?;
+        let config_id = upsert_nixos_configuration(db, flake_id, &config).await?;
+
+        let store_path = config_store_path(url, &config).await?;
+        insert_nixos_configutaion_evaluation(db, flake_revision_id, config_id, &store_path).await?;
     }
     Ok(())
 }
@@ -196,9 +275,13 @@ async fn json_output<T: DeserializeOwned>(mut cmd: Command) -> Result<T> {
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(eyre!("cmd exited with non-zero status code")
-            .with_section(move || stdout.trim().to_string().header("Stdout:"))
-            .with_section(move || stderr.trim().to_string().header("Stderr:")));
+        let error = NixError {
+            command: format!("{:?}", cmd.as_std().get_program()),
+            stderr: stderr.trim().to_string(),
+        };
+        return Err(
+            Report::new(error).with_section(move || stdout.trim().to_string().header("Stdout:"))
+        );
     }
 
     serde_json::from_str(&stdout).map_err(|e| {
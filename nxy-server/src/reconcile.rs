@@ -0,0 +1,240 @@
+//! Converge every connected agent to its desired system.
+//!
+//! The desired system of an agent is the latest evaluation of its assigned configuration, see
+//! the `agent_desired_systems` view. What the reconciler does to reach it depends on the
+//! [`DeployPolicy`] of the agent.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use color_eyre::Result;
+use nxy_common::{api::DeployPolicy, types::ActivationMode};
+use sqlx::PgPool;
+use tokio::{sync::Notify, time::Instant};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{agent::AgentManager, audit, deploy};
+
+/// How often all agents are reconciled without being triggered.
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Delay before retrying an agent after its first failed reconciliation, doubled on every
+/// further failure.
+const MIN_BACKOFF: Duration = Duration::from_secs(30);
+/// Upper bound of the retry delay.
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// Failed reconciliations of an agent
+#[derive(Debug)]
+struct Backoff {
+    failures: u32,
+    retry_at: Instant,
+}
+
+/// Agents being deployed to, by the reconciler or by a deployment
+///
+/// Agents handle every request on its own thread, two activations at once would race each other.
+#[derive(Debug, Default)]
+pub(crate) struct Running(Arc<Mutex<HashSet<Uuid>>>);
+
+/// Marks an agent as running until dropped
+#[derive(Debug)]
+pub(crate) struct RunningGuard {
+    agent_id: Uuid,
+    running: Arc<Mutex<HashSet<Uuid>>>,
+}
+
+impl Running {
+    /// Mark `agent_id` as running, `None` if it already is
+    pub(crate) fn start(&self, agent_id: Uuid) -> Option<RunningGuard> {
+        if !self.0.lock().unwrap().insert(agent_id) {
+            return None;
+        }
+        Some(RunningGuard {
+            agent_id,
+            running: Arc::clone(&self.0),
+        })
+    }
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.running.lock().unwrap().remove(&self.agent_id);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Reconciler {
+    manager: Arc<AgentManager>,
+    pool: PgPool,
+    trigger: Arc<Notify>,
+    backoff: Mutex<HashMap<Uuid, Backoff>>,
+}
+
+impl Reconciler {
+    pub(crate) fn new(manager: Arc<AgentManager>, pool: PgPool, trigger: Arc<Notify>) -> Self {
+        Self {
+            manager,
+            pool,
+            trigger,
+            backoff: Default::default(),
+        }
+    }
+
+    /// Reconcile all agents whenever triggered, or every [`RECONCILE_INTERVAL`].
+    pub(crate) async fn run(self: Arc<Self>) {
+        loop {
+            tokio::select! {
+                _ = self.trigger.notified() => {},
+                _ = tokio::time::sleep(RECONCILE_INTERVAL) => {},
+            }
+
+            for agent_id in self.manager.connected() {
+                let Some(guard) = self.start(agent_id) else {
+                    continue;
+                };
+                let reconciler = Arc::clone(&self);
+                tokio::spawn(async move {
+                    let result = reconciler.reconcile(agent_id).await;
+                    drop(guard);
+                    reconciler.finish(agent_id, result);
+                });
+            }
+        }
+    }
+
+    /// Mark `agent_id` as running, `None` if it's already running or backing off
+    fn start(&self, agent_id: Uuid) -> Option<RunningGuard> {
+        let backing_off = self
+            .backoff
+            .lock()
+            .unwrap()
+            .get(&agent_id)
+            .map_or(false, |backoff| backoff.retry_at > Instant::now());
+        if backing_off {
+            return None;
+        }
+        self.manager.running().start(agent_id)
+    }
+
+    fn finish(&self, agent_id: Uuid, result: Result<()>) {
+        let mut backoff = self.backoff.lock().unwrap();
+        match result {
+            Ok(()) => {
+                backoff.remove(&agent_id);
+            }
+            Err(err) => {
+                let failures = backoff.get(&agent_id).map_or(0, |b| b.failures) + 1;
+                let delay = MIN_BACKOFF
+                    .saturating_mul(2u32.saturating_pow(failures - 1))
+                    .min(MAX_BACKOFF);
+                tracing::warn!(%agent_id, ?err, failures, retry_in = ?delay, "reconciliation failed");
+                backoff.insert(
+                    agent_id,
+                    Backoff {
+                        failures,
+                        retry_at: Instant::now() + delay,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Bring `agent_id` one step closer to its desired system, as far as its policy allows.
+    #[instrument(skip(self))]
+    async fn reconcile(&self, agent_id: Uuid) -> Result<()> {
+        let row = sqlx::query!(
+            r#"
+            SELECT deploy_policy, current_system, d.store_path AS "desired_system!",
+                EXISTS (
+                    SELECT 1 FROM agent_store_paths AS p
+                    WHERE p.agent_id = agents.agent_id AND p.store_path = d.store_path
+                ) AS "downloaded!",
+                EXISTS (
+                    SELECT 1 FROM agent_deployments AS dep
+                    WHERE dep.agent_id = agents.agent_id AND dep.store_path = d.store_path
+                        AND dep.action = 'boot' AND dep.error IS NULL
+                ) AS "boot_default!"
+            FROM agents
+            JOIN agent_desired_systems AS d USING (agent_id)
+            WHERE agent_id = $1
+            "#,
+            agent_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        // no configuration assigned or not evaluated yet
+        let Some(row) = row else {
+            return Ok(());
+        };
+        let policy: DeployPolicy = row.deploy_policy.parse()?;
+        let desired = row.desired_system;
+
+        if row.current_system.as_ref() == Some(&desired) {
+            return Ok(());
+        }
+        let mode = match policy {
+            DeployPolicy::Manual => return Ok(()),
+            DeployPolicy::Download if row.downloaded => return Ok(()),
+            DeployPolicy::Download => None,
+            DeployPolicy::Boot if row.boot_default => return Ok(()),
+            DeployPolicy::Boot => Some(ActivationMode::Boot),
+            DeployPolicy::Switch => Some(ActivationMode::Switch),
+        };
+
+        if !row.downloaded {
+            tracing::info!(desired, "downloading desired system");
+            deploy::started(&self.manager, None, agent_id, &desired, "download");
+            let result = self
+                .manager
+                .download(agent_id, PathBuf::from(&desired))
+                .await;
+            self.record(agent_id, &desired, "download", &result).await?;
+            result?;
+        }
+
+        if let Some(mode) = mode {
+            tracing::info!(desired, %mode, "activating desired system");
+            deploy::started(&self.manager, None, agent_id, &desired, mode.as_str());
+            let result = self.manager.activate(agent_id, &desired, mode).await;
+            self.record(agent_id, &desired, mode.as_str(), &result)
+                .await?;
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Remember the outcome of a deployment step, see [`deploy::record`]
+    async fn record(
+        &self,
+        agent_id: Uuid,
+        store_path: &str,
+        action: &str,
+        result: &Result<()>,
+    ) -> Result<()> {
+        deploy::record(
+            &self.manager,
+            &self.pool,
+            None,
+            agent_id,
+            store_path,
+            action,
+            result,
+        )
+        .await?;
+
+        let error = result.as_ref().err().map(|err| format!("{err:#}"));
+        let entry =
+            audit::Entry::server(format!("reconcile/{action}"), format!("agent/{agent_id}"))
+                .parameters(serde_json::json!({ "store_path": store_path }))
+                .error(error);
+        audit::record(&self.pool, entry).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,409 @@
+// nxy dashboard, a thin client of the REST API at /api/v1
+"use strict";
+
+const API = "/api/v1";
+const MODES = ["switch", "boot", "test", "dry-activate"];
+const MAX_EVENTS = 50;
+
+// ---- helpers --------------------------------------------------------------
+
+function escape(value) {
+  return String(value ?? "").replace(/[&<>"']/g, (c) => `&#${c.charCodeAt(0)};`);
+}
+
+/// `/nix/store/<hash>-<name>` shortened to `<hash prefix>…-<name>`
+function storePath(path) {
+  if (!path) {
+    return `<span class="muted">–</span>`;
+  }
+  const short = path.replace("/nix/store/", "").replace(/^(\w{8})\w+/, "$1…");
+  return `<code title="${escape(path)}">${escape(short)}</code>`;
+}
+
+function badge(text, kind) {
+  return `<span class="badge ${kind ?? ""}">${escape(text)}</span>`;
+}
+
+const DRIFT_KIND = {
+  "in-sync": "ok",
+  "pending-download": "warn",
+  "downloaded-not-activated": "warn",
+  drifted: "bad",
+  "unknown-configuration": "",
+};
+
+function labels(labels) {
+  const entries = Object.entries(labels ?? {});
+  if (!entries.length) return `<span class="muted">–</span>`;
+  return entries.map(([key, value]) => badge(`${key}=${value}`)).join(" ");
+}
+
+function time(value) {
+  return value ? escape(new Date(value).toLocaleString()) : "";
+}
+
+function showError(message) {
+  const error = document.getElementById("error");
+  error.textContent = message;
+  error.hidden = !message;
+}
+
+async function api(method, path, body) {
+  const options = { method, headers: {} };
+  if (body !== undefined) {
+    options.headers["Content-Type"] = "application/json";
+    options.body = JSON.stringify(body);
+  }
+  const response = await fetch(API + path, options);
+  const text = await response.text();
+  if (!response.ok) {
+    let message = `${method} ${path} failed with ${response.status}`;
+    try {
+      message = JSON.parse(text).message;
+    } catch (_) {}
+    throw new Error(message);
+  }
+  return text ? JSON.parse(text) : null;
+}
+
+const get = (path) => api("GET", path);
+
+/// Run an action of a button, errors are shown in the banner
+async function run(label, action) {
+  showError("");
+  try {
+    await action();
+    render();
+  } catch (err) {
+    showError(`${label}: ${err.message}`);
+  }
+}
+
+// ---- actions --------------------------------------------------------------
+
+function deploy(agentId, desired) {
+  if (!desired) {
+    showError("agent has no desired system");
+    return;
+  }
+  if (!confirm(`Download and switch ${agentId} to\n${desired}?`)) {
+    return;
+  }
+  // as a deployment, so it shows up in the history and metrics like any other
+  run("deploy", async () => {
+    const started = await api("POST", "/deployment", { agent: agentId, mode: "switch" });
+    const error = started.agents.find((agent) => agent.error)?.error;
+    if (error) {
+      throw new Error(error.message);
+    }
+  });
+}
+
+function activate(agentId) {
+  const storePath = document.getElementById("activate-path").value.trim();
+  const mode = document.getElementById("activate-mode").value;
+  if (!storePath || !confirm(`Activate ${storePath} on ${agentId} with ${mode}?`)) {
+    return;
+  }
+  run("activate", () =>
+    api("POST", `/agent/${agentId}/activate`, { store_path: storePath, mode })
+  );
+}
+
+function rollback(agentId, mode) {
+  if (!confirm(`Roll ${agentId} back to its previous generation with ${mode ?? "switch"}?`)) {
+    return;
+  }
+  run("rollback", () => api("POST", `/agent/${agentId}/rollback`, { mode: mode ?? "switch" }));
+}
+
+function refreshFlake(flakeId) {
+  run("refresh", () => api("PUT", `/flake/${flakeId}`));
+}
+
+// ---- views ----------------------------------------------------------------
+
+async function fleetView() {
+  const [agents, drift, configurations] = await Promise.all([
+    get("/agent"),
+    get("/drift"),
+    get("/configuration"),
+  ]);
+  const driftById = new Map(drift.map((d) => [d.agent_id, d]));
+  const configById = new Map(configurations.map((c) => [c.id, c]));
+
+  const rows = agents.map((agent) => {
+    const d = driftById.get(agent.id) ?? {};
+    const config = configById.get(d.nixos_configuration_id);
+    return `<tr>
+      <td><a href="#/agent/${escape(agent.id)}">${agent.name ? escape(agent.name) : `<code>${escape(agent.id)}</code>`}</a></td>
+      <td>${agent.connected ? badge("online", "ok") : badge("offline")}</td>
+      <td>${d.status ? badge(d.status, DRIFT_KIND[d.status]) : ""}</td>
+      <td>${config ? escape(config.name) : `<span class="muted">–</span>`}</td>
+      <td>${storePath(d.desired_system)}</td>
+      <td>${storePath(agent.current_system)}</td>
+      <td>${escape(agent.deploy_policy)}</td>
+      <td>${labels(agent.labels)}</td>
+      <td>${agent.reboot_required.length ? badge("reboot", "warn") : ""}</td>
+      <td class="actions">
+        <button data-deploy="${escape(agent.id)}" data-desired="${escape(d.desired_system ?? "")}"
+          ${agent.connected && d.status !== "in-sync" ? "" : "disabled"}>Deploy</button>
+        <button data-rollback="${escape(agent.id)}" ${agent.connected ? "" : "disabled"}>Rollback</button>
+      </td>
+    </tr>`;
+  });
+
+  return `<h1>Fleet</h1>
+    <table>
+      <tr><th>Agent</th><th>Connection</th><th>Status</th><th>Configuration</th>
+        <th>Desired</th><th>Current</th><th>Policy</th><th>Labels</th><th></th><th></th></tr>
+      ${rows.join("")}
+    </table>`;
+}
+
+async function agentView(agentId) {
+  const [agent, drift, deployments, units] = await Promise.all([
+    get(`/agent/${agentId}`),
+    get("/drift"),
+    get(`/agent/${agentId}/deployments`),
+    // agents which never reported units answer with 404
+    get(`/agent/${agentId}/units`).catch(() => null),
+  ]);
+  const d = drift.find((d) => d.agent_id === agentId) ?? {};
+  const facts = agent.facts;
+  const modes = MODES.map((m) => `<option>${m}</option>`).join("");
+
+  const history = deployments.map(
+    (dep) => `<tr>
+      <td>${time(dep.created_at)}</td>
+      <td>${escape(dep.action)}</td>
+      <td>${storePath(dep.store_path)}</td>
+      <td>${dep.error ? badge("failed", "bad") + " " + escape(dep.error) : badge("succeeded", "ok")}</td>
+    </tr>`
+  );
+  const failed = (units?.failed ?? []).map(
+    (unit) => `<tr><td>${escape(unit.name)}</td><td>${escape(unit.active_state)}</td>
+      <td>${escape(unit.sub_state)}</td></tr>`
+  );
+
+  return `<h1>${escape(agent.name ?? agent.id)}</h1>
+    <div class="actions">
+      <button data-deploy="${escape(agent.id)}" data-desired="${escape(d.desired_system ?? "")}"
+        ${agent.connected ? "" : "disabled"}>Deploy desired system</button>
+      <input id="activate-path" size="50" placeholder="/nix/store/…" value="${escape(d.desired_system ?? "")}">
+      <select id="activate-mode">${modes}</select>
+      <button data-activate="${escape(agent.id)}" ${agent.connected ? "" : "disabled"}>Activate</button>
+      <button data-rollback="${escape(agent.id)}" ${agent.connected ? "" : "disabled"}>Rollback</button>
+    </div>
+    <dl>
+      <dt>Id</dt><dd><code>${escape(agent.id)}</code></dd>
+      <dt>Connection</dt><dd>${agent.connected ? badge("online", "ok") : badge("offline")}</dd>
+      <dt>Status</dt><dd>${d.status ? badge(d.status, DRIFT_KIND[d.status]) : ""}</dd>
+      <dt>Desired system</dt><dd>${storePath(d.desired_system)}</dd>
+      <dt>Current system</dt><dd>${storePath(agent.current_system)}</dd>
+      <dt>Booted system</dt><dd>${storePath(d.booted_system)}</dd>
+      <dt>Pinned revision</dt><dd>${escape(agent.pinned_revision ?? "–")}</dd>
+      <dt>Site</dt><dd>${escape(agent.site ?? "–")}</dd>
+      <dt>Labels</dt><dd>${labels(agent.labels)}</dd>
+      <dt>Reboot required</dt><dd>${escape(agent.reboot_required.join(", ") || "no")}</dd>
+      ${
+        facts
+          ? `<dt>NixOS</dt><dd>${escape(facts.nixos_version ?? "–")}</dd>
+            <dt>Kernel</dt><dd>${escape(facts.kernel_version)} (${escape(facts.architecture)})</dd>
+            <dt>Addresses</dt><dd>${escape(facts.ip_addresses.join(", "))}</dd>
+            <dt>Facts updated</dt><dd>${time(agent.facts_updated_at)}</dd>`
+          : ""
+      }
+    </dl>
+    <h2>Failed units</h2>
+    ${
+      failed.length
+        ? `<table><tr><th>Unit</th><th>State</th><th>Sub state</th></tr>${failed.join("")}</table>`
+        : `<p class="muted">None</p>`
+    }
+    <h2>Deployment history</h2>
+    ${
+      history.length
+        ? `<table><tr><th>Time</th><th>Action</th><th>System</th><th>Outcome</th></tr>${history.join("")}</table>`
+        : `<p class="muted">No deployments yet</p>`
+    }`;
+}
+
+async function flakesView() {
+  const flakes = await get("/flake");
+  const rows = flakes.map(
+    (flake) => `<tr>
+      <td><a href="#/flake/${flake.flake_id}">${escape(flake.flake_url)}</a></td>
+      <td><code>${escape(flake.lastest_revision.revision.slice(0, 12))}</code></td>
+      <td>${escape(flake.lastest_revision.last_modified)}</td>
+      <td><button data-refresh="${flake.flake_id}">Refresh</button></td>
+    </tr>`
+  );
+  return `<h1>Flakes</h1>
+    <table>
+      <tr><th>Flake</th><th>Latest revision</th><th>Last modified</th><th></th></tr>
+      ${rows.join("")}
+    </table>`;
+}
+
+async function flakeView(flakeId) {
+  const [{ flake }, revisions] = await Promise.all([
+    get(`/flake/${flakeId}`),
+    get(`/flake/${flakeId}/revisions`),
+  ]);
+  const statusKind = { succeeded: "ok", pending: "warn", failed: "bad" };
+
+  const configurations = flake.configurations.map(
+    (c) => `<tr><td>${escape(c.name)}</td><td>${c.agents}</td></tr>`
+  );
+  const rows = revisions.map(
+    (r) => `<tr>
+      <td><code title="${escape(r.revision)}">${escape(r.revision.slice(0, 12))}</code></td>
+      <td>${escape(r.last_modified)}</td>
+      <td>${badge(r.evaluation_status, statusKind[r.evaluation_status])}</td>
+      <td>${r.evaluations}</td>
+      <td>${r.evaluation_error ? `<code>${escape(r.evaluation_error)}</code>` : ""}</td>
+    </tr>`
+  );
+
+  return `<h1>${escape(flake.flake_url)}</h1>
+    <div class="actions">
+      <button data-refresh="${flake.flake_id}">Fetch latest revision</button>
+    </div>
+    <h2>Configurations</h2>
+    <table><tr><th>Name</th><th>Agents</th></tr>${configurations.join("")}</table>
+    <h2>Revisions</h2>
+    <table>
+      <tr><th>Revision</th><th>Last modified</th><th>Evaluation</th><th>Configurations</th><th>Error</th></tr>
+      ${rows.join("")}
+    </table>`;
+}
+
+function route() {
+  const [view, id] = location.hash.replace(/^#\/?/, "").split("/");
+  switch (view) {
+    case "agent":
+      return () => agentView(decodeURIComponent(id));
+    case "flakes":
+      return flakesView;
+    case "flake":
+      return () => flakeView(decodeURIComponent(id));
+    default:
+      return fleetView;
+  }
+}
+
+// ---- rendering ------------------------------------------------------------
+
+let rendering = null;
+
+async function render() {
+  const view = route();
+  const current = (rendering = view().catch((err) => {
+    showError(err.message);
+    return null;
+  }));
+  const html = await current;
+  // a newer render started meanwhile
+  if (rendering !== current || html === null) {
+    return;
+  }
+  document.getElementById("view").innerHTML = html;
+}
+
+document.getElementById("view").addEventListener("click", (event) => {
+  const button = event.target.closest("button");
+  if (!button) {
+    return;
+  }
+  const data = button.dataset;
+  if (data.deploy) {
+    deploy(data.deploy, data.desired);
+  } else if (data.activate) {
+    activate(data.activate);
+  } else if (data.rollback) {
+    const mode = document.getElementById("activate-mode")?.value;
+    rollback(data.rollback, mode);
+  } else if (data.refresh) {
+    refreshFlake(data.refresh);
+  }
+});
+
+// ---- events ---------------------------------------------------------------
+
+function describe(event) {
+  switch (event.type) {
+    case "agent-connected":
+      return `agent ${event.agent_id} connected`;
+    case "agent-disconnected":
+      return `agent ${event.agent_id} disconnected`;
+    case "flake-revision":
+      return `flake ${event.flake_id} has new revision ${event.revision.slice(0, 12)}`;
+    case "evaluation-started":
+      return `flake ${event.flake_id} evaluating revision ${event.flake_revision_id}`;
+    case "evaluation-succeeded":
+      return `flake ${event.flake_id} evaluated revision ${event.flake_revision_id}`;
+    case "evaluation-failed":
+      return `flake ${event.flake_id} failed to evaluate revision ${event.flake_revision_id}`;
+    case "deployment":
+      return `agent ${event.agent_id} ${event.action} ${event.phase}` +
+        (event.error ? `: ${event.error}` : "");
+    case "deployment-finished":
+      return `deployment ${event.deployment_id} finished, ${event.failed} of ${event.agents} agents failed`;
+    case "drifted":
+      return `agent ${event.agent_id} drifted`;
+    default:
+      return event.type;
+  }
+}
+
+let pendingRender = null;
+
+function connectEvents() {
+  const live = document.getElementById("live");
+  const source = new EventSource(`${API}/events`);
+  source.onopen = () => {
+    live.textContent = "live";
+    live.className = "badge ok";
+  };
+  // EventSource reconnects on its own
+  source.onerror = () => {
+    live.textContent = "offline";
+    live.className = "badge";
+  };
+
+  const onEvent = (message) => {
+    const event = JSON.parse(message.data);
+    const list = document.getElementById("events");
+    const item = document.createElement("li");
+    item.innerHTML = `<time>${escape(new Date().toLocaleTimeString())}</time>${escape(describe(event))}`;
+    list.prepend(item);
+    while (list.children.length > MAX_EVENTS) {
+      list.lastChild.remove();
+    }
+
+    // coalesce bursts, eg. during an evaluation
+    clearTimeout(pendingRender);
+    pendingRender = setTimeout(render, 500);
+  };
+  for (const type of [
+    "agent-connected",
+    "agent-disconnected",
+    "flake-revision",
+    "evaluation-started",
+    "evaluation-succeeded",
+    "evaluation-failed",
+    "deployment",
+    "deployment-finished",
+    "drifted",
+  ]) {
+    source.addEventListener(type, onEvent);
+  }
+}
+
+window.addEventListener("hashchange", () => {
+  showError("");
+  render();
+});
+render();
+connectEvents();